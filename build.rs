@@ -48,19 +48,68 @@ fn get_function_bytes(full_wasm: &[u8]) -> &[u8] {{
     "#)
 }
 
+/// The test name prefixes which change how a test is compiled, see [`generate_test_function`]
+const TEST_PREFIXES: &[&str] = &["simd", "trap", "opt", "bulk", "shared", "tail", "exc"];
+
+/// Tests whose names start with a feature prefix (e.g. `simd_`)
+/// are compiled and validated with that WebAssembly feature enabled.
+/// Tests starting with `trap_` use the trapping (non-saturating) float to int conversions.
+/// Tests starting with `opt_` are compiled with the optimizations enabled.
+/// Tests starting with `bulk_` are compiled with bulk memory enabled.
+/// Tests starting with `shared_` are compiled with shared memory (and threads) enabled.
+/// Tests starting with `tail_` are compiled with tail calls enabled.
+/// Tests starting with `exc_` are compiled with exception handling enabled.
+///
+/// The IR may start with type and exception tag definitions before the function.
+///
+/// The prefixes can be combined, e.g. `opt_tail_`.
 fn generate_test_function(w: &mut impl Write, test_name: &str, ir_input: &str, wat_input: &str) -> std::io::Result<()> {
+    let mut prefixes = Vec::new();
+    let mut rest = test_name;
+    while let Some((prefix, tail)) = rest.split_once('_') {
+        if !TEST_PREFIXES.contains(&prefix) { break }
+        prefixes.push(prefix);
+        rest = tail;
+    }
+    let simd = prefixes.contains(&"simd");
+    let saturating_ftoi = !prefixes.contains(&"trap");
+    let opt = prefixes.contains(&"opt");
+    let bulk_memory = prefixes.contains(&"bulk");
+    let shared_memory = prefixes.contains(&"shared");
+    let tail_calls = prefixes.contains(&"tail");
+    let exceptions = prefixes.contains(&"exc");
+    let maximum_memory_size = if shared_memory { "Some(16)" } else { "None" };
+
     write!(w, r#"
 #[test]
 pub fn {}() {{
-    let mut m = Module::default();
+    let mut m = Module::new(WasmModuleConf {{
+        enable_simd: {simd},
+        use_saturating_ftoi: {saturating_ftoi},
+        enable_bulk_memory: {bulk_memory},
+        shared_memory: {shared_memory},
+        maximum_memory_size: {maximum_memory_size},
+        enable_tail_calls: {tail_calls},
+        enable_exceptions: {exceptions},
+        ..WasmModuleConf::default()
+    }});
     let f = {{
         let mut p = IRParser::new(&mut m, "{}");
+        p.parse_definitions().unwrap();
         p.parse_function().unwrap()
     }};
     m.add_function(f);
-    let wasm_bytes_ir = pipeline_compile_module_to_wasm(m, false);
-
-    assert!(wasmparser::validate(&wasm_bytes_ir).is_ok(), "Invalid WASM produced by IR compilation");
+    let wasm_bytes_ir = pipeline_compile_module_to_wasm(m, {opt});
+
+    let mut validator = wasmparser::Validator::new();
+    validator.wasm_features(wasmparser::WasmFeatures {{
+        simd: {simd},
+        threads: {shared_memory},
+        tail_call: {tail_calls},
+        exceptions: {exceptions},
+        ..wasmparser::WasmFeatures::default()
+    }});
+    assert!(validator.validate_all(&wasm_bytes_ir).is_ok(), "Invalid WASM produced by IR compilation");
 
     let wasm_bytes_wat = wat::parse_str("{}").unwrap();
 
@@ -74,5 +123,7 @@ WAT WebAssembly:
 
     assert_eq!(get_function_bytes(&wasm_bytes_ir), get_function_bytes(&wasm_bytes_wat), "{{}}", error_message);
 }}
-    "#, test_name, ir_input.replace('"', "\\\""), wat_input.replace('"', "\\\""))
+    "#, test_name, ir_input.replace('"', "\\\""), wat_input.replace('"', "\\\""), simd = simd, saturating_ftoi = saturating_ftoi, opt = opt, bulk_memory = bulk_memory,
+    shared_memory = shared_memory, maximum_memory_size = maximum_memory_size, tail_calls = tail_calls,
+    exceptions = exceptions)
 }
\ No newline at end of file
@@ -20,6 +20,18 @@ typedef enum BlockTag {
    * A block which is used as the body of a Loop instruction
    */
   Loop,
+  /**
+   * A block which is used as one of the cases (or the default case) of a Switch instruction
+   */
+  Switch,
+  /**
+   * A block which is used as the body of a TryCatch instruction
+   */
+  Try,
+  /**
+   * A block which is used as one of the handlers (or the catch_all handler) of a TryCatch instruction
+   */
+  Catch,
 } BlockTag;
 
 typedef enum Cmp {
@@ -31,6 +43,20 @@ typedef enum Cmp {
   Ge,
 } Cmp;
 
+/**
+ * The operation performed by an AtomicRmw instruction
+ */
+typedef enum AtomicRmwOp {
+  Add,
+  Sub,
+  And,
+  Or,
+  /**
+   * Replace the integer in memory with the popped one
+   */
+  Xchg,
+} AtomicRmwOp;
+
 typedef void *ModuleRef;
 
 typedef const void *TypeRef;
@@ -52,6 +78,10 @@ void free_module(ModuleRef module);
 
 void dump_module(ModuleRef module);
 
+TypeRef module_get_int64_type(ModuleRef module);
+
+TypeRef module_get_uint64_type(ModuleRef module);
+
 TypeRef module_get_int32_type(ModuleRef module);
 
 TypeRef module_get_uint32_type(ModuleRef module);
@@ -66,6 +96,10 @@ TypeRef module_get_uint8_type(ModuleRef module);
 
 TypeRef module_get_float32_type(ModuleRef module);
 
+TypeRef module_get_float64_type(ModuleRef module);
+
+TypeRef module_get_extern_ref_type(ModuleRef module);
+
 TypeRef module_get_ptr_type(ModuleRef module);
 
 TypeRef module_get_func_type(ModuleRef module,
@@ -76,10 +110,46 @@ TypeRef module_get_func_type(ModuleRef module,
 
 TypeRef module_get_struct_type(ModuleRef module, const TypeRef *field_types, uintptr_t fieldc);
 
+TypeRef module_get_tagged_union_type(ModuleRef module,
+                                     const TypeRef *variant_types,
+                                     uintptr_t variantc);
+
+TypeRef module_declare_named_struct(ModuleRef module, const int8_t *name);
+
+void module_set_named_struct_body(ModuleRef module,
+                                  TypeRef named_struct,
+                                  const TypeRef *field_types,
+                                  uintptr_t fieldc);
+
+TypeRef module_get_array_type(ModuleRef module, TypeRef elem_type, uintptr_t len);
+
+/**
+ * The size of a type in bytes
+ */
+uintptr_t module_type_sizeof(ModuleRef module, TypeRef ty);
+
+/**
+ * The alignment of a type, expressed as an exponent of two
+ */
+uintptr_t module_type_alignment(ModuleRef module, TypeRef ty);
+
+/**
+ * The offset of the Nth field of a struct type
+ * (or of the payload of the Nth variant of a tagged union)
+ */
+uintptr_t module_type_field_offset(ModuleRef module, TypeRef ty, uintptr_t field_idx);
+
 void module_new_int_global(ModuleRef module, const int8_t *global_name, int32_t value);
 
 void module_new_float_global(ModuleRef module, const int8_t *global_name, float value);
 
+void module_new_extern_ref_global(ModuleRef module, const int8_t *global_name);
+
+void module_new_exception_tag(ModuleRef module,
+                              const int8_t *tag_name,
+                              const TypeRef *payload,
+                              uintptr_t payloadc);
+
 void module_new_extern_function(ModuleRef module,
                                 const int8_t *function_name,
                                 TypeRef function_type);
@@ -105,14 +175,23 @@ BlockId builder_new_block(FunctionBuilderRef builder,
                           uintptr_t block_returnc,
                           enum BlockTag block_tag);
 
+BlockId builder_new_block_with_params(FunctionBuilderRef builder,
+                                      const TypeRef *block_params,
+                                      uintptr_t block_paramc,
+                                      const TypeRef *block_returns,
+                                      uintptr_t block_returnc,
+                                      enum BlockTag block_tag);
+
 void builder_switch_block(FunctionBuilderRef builder, BlockId new_block);
 
 BlockId builder_get_current_block(FunctionBuilderRef builder);
 
-void builder_i_ld_int(FunctionBuilderRef builder, uint32_t val, TypeRef int_type);
+void builder_i_ld_int(FunctionBuilderRef builder, uint64_t val, TypeRef int_type);
 
 void builder_i_ld_float(FunctionBuilderRef builder, float val);
 
+void builder_i_ld_float64(FunctionBuilderRef builder, double val);
+
 void builder_i_iadd(FunctionBuilderRef builder);
 
 void builder_i_isub(FunctionBuilderRef builder);
@@ -121,6 +200,10 @@ void builder_i_imul(FunctionBuilderRef builder);
 
 void builder_i_idiv(FunctionBuilderRef builder);
 
+void builder_i_irem(FunctionBuilderRef builder);
+
+void builder_i_ineg(FunctionBuilderRef builder);
+
 void builder_i_fadd(FunctionBuilderRef builder);
 
 void builder_i_fsub(FunctionBuilderRef builder);
@@ -129,7 +212,29 @@ void builder_i_fmul(FunctionBuilderRef builder);
 
 void builder_i_fdiv(FunctionBuilderRef builder);
 
-void builder_i_itof(FunctionBuilderRef builder);
+void builder_i_fmin(FunctionBuilderRef builder);
+
+void builder_i_fmax(FunctionBuilderRef builder);
+
+void builder_i_fcopysign(FunctionBuilderRef builder);
+
+void builder_i_fsqrt(FunctionBuilderRef builder);
+
+void builder_i_fabs(FunctionBuilderRef builder);
+
+void builder_i_fneg(FunctionBuilderRef builder);
+
+void builder_i_ffloor(FunctionBuilderRef builder);
+
+void builder_i_fceil(FunctionBuilderRef builder);
+
+void builder_i_ftrunc(FunctionBuilderRef builder);
+
+void builder_i_fnearest(FunctionBuilderRef builder);
+
+void builder_i_fpromote(FunctionBuilderRef builder);
+
+void builder_i_fdemote(FunctionBuilderRef builder);
 
 void builder_i_not(FunctionBuilderRef builder);
 
@@ -137,30 +242,72 @@ void builder_i_bitand(FunctionBuilderRef builder);
 
 void builder_i_bitor(FunctionBuilderRef builder);
 
+void builder_i_bitxor(FunctionBuilderRef builder);
+
+void builder_i_bitnot(FunctionBuilderRef builder);
+
+void builder_i_shl(FunctionBuilderRef builder);
+
+void builder_i_shr(FunctionBuilderRef builder);
+
 void builder_i_call_indirect(FunctionBuilderRef builder);
 
+void builder_i_tail_call_indirect(FunctionBuilderRef builder);
+
 void builder_i_memory_grow(FunctionBuilderRef builder);
 
 void builder_i_memory_size(FunctionBuilderRef builder);
 
+void builder_i_atomic_notify(FunctionBuilderRef builder);
+
+void builder_i_mem_copy(FunctionBuilderRef builder);
+
+void builder_i_mem_fill(FunctionBuilderRef builder);
+
 void builder_i_discard(FunctionBuilderRef builder);
 
+void builder_i_dup(FunctionBuilderRef builder);
+
+void builder_i_swap(FunctionBuilderRef builder);
+
+void builder_i_over(FunctionBuilderRef builder);
+
+void builder_i_select(FunctionBuilderRef builder);
+
 void builder_i_return(FunctionBuilderRef builder);
 
 void builder_i_fail(FunctionBuilderRef builder);
 
 void builder_i_break(FunctionBuilderRef builder);
 
+void builder_i_ld_null_ref(FunctionBuilderRef builder);
+
+void builder_i_is_null_ref(FunctionBuilderRef builder);
+
+void builder_i_itof(FunctionBuilderRef builder, TypeRef float_type);
+
 void builder_i_ftoi(FunctionBuilderRef builder, TypeRef int_type);
 
 void builder_i_iconv(FunctionBuilderRef builder, TypeRef int_type);
 
+void builder_i_atomic_read(FunctionBuilderRef builder, TypeRef ty);
+
+void builder_i_atomic_write(FunctionBuilderRef builder, TypeRef ty);
+
+void builder_i_atomic_rmw(FunctionBuilderRef builder, enum AtomicRmwOp op, TypeRef ty);
+
+void builder_i_atomic_cmpxchg(FunctionBuilderRef builder, TypeRef ty);
+
+void builder_i_atomic_wait(FunctionBuilderRef builder, TypeRef ty);
+
 void builder_i_icmp(FunctionBuilderRef builder, enum Cmp cmp);
 
 void builder_i_fcmp(FunctionBuilderRef builder, enum Cmp cmp);
 
 void builder_i_call(FunctionBuilderRef builder, const int8_t *func_name);
 
+void builder_i_tail_call(FunctionBuilderRef builder, const int8_t *func_name);
+
 void builder_i_ld_local(FunctionBuilderRef builder, LocalRef loc);
 
 void builder_i_st_local(FunctionBuilderRef builder, LocalRef loc);
@@ -173,20 +320,56 @@ void builder_i_if(FunctionBuilderRef builder, BlockId then_block);
 
 void builder_i_if_else(FunctionBuilderRef builder, BlockId then_block, BlockId else_block);
 
+void builder_i_switch(FunctionBuilderRef builder,
+                      const BlockId *case_blocks,
+                      uintptr_t casec,
+                      BlockId default_block);
+
+void builder_i_try_catch(FunctionBuilderRef builder,
+                         BlockId body_block,
+                         const int8_t *const *catch_tags,
+                         const BlockId *catch_blocks,
+                         uintptr_t catchc);
+
+void builder_i_try_catch_all(FunctionBuilderRef builder,
+                             BlockId body_block,
+                             const int8_t *const *catch_tags,
+                             const BlockId *catch_blocks,
+                             uintptr_t catchc,
+                             BlockId catch_all_block);
+
+void builder_i_throw(FunctionBuilderRef builder, const int8_t *tag_name);
+
 void builder_i_read(FunctionBuilderRef builder, TypeRef ty);
 
 void builder_i_write(FunctionBuilderRef builder, TypeRef ty);
 
 void builder_i_offset(FunctionBuilderRef builder, TypeRef ty);
 
+void builder_i_offset_const(FunctionBuilderRef builder, TypeRef ty, uintptr_t n);
+
+void builder_i_stack_alloc(FunctionBuilderRef builder, TypeRef ty);
+
 void builder_i_get_field_ptr(FunctionBuilderRef builder, TypeRef struct_ty, uintptr_t field_idx);
 
+void builder_i_get_tag(FunctionBuilderRef builder, TypeRef union_ty);
+
+void builder_i_set_tag(FunctionBuilderRef builder, TypeRef union_ty, uintptr_t variant_idx);
+
+void builder_i_get_variant_ptr(FunctionBuilderRef builder, TypeRef union_ty, uintptr_t variant_idx);
+
+void builder_i_get_elem_ptr(FunctionBuilderRef builder, TypeRef array_ty);
+
 void builder_i_ld_global(FunctionBuilderRef builder, const int8_t *name);
 
 void builder_i_st_global(FunctionBuilderRef builder, const int8_t *name);
 
 void builder_i_loop(FunctionBuilderRef builder, BlockId body_block);
 
+void builder_i_break_loop(FunctionBuilderRef builder, BlockId loop_body_block);
+
+void builder_i_continue_loop(FunctionBuilderRef builder, BlockId loop_body_block);
+
 void builder_i_ld_static_mem_ptr(FunctionBuilderRef builder, SMItemRef static_mem_item);
 
 const uint8_t *compile_full_module(ModuleRef module, bool opt, uintptr_t *out_len);
@@ -1,7 +1,11 @@
-use crate::ty::{Ty, Type};
+use crate::{module::Module, ty::{Ty, Type}};
 use wasm_encoder as wasm;
 
-pub trait Abi {
+/// Describes how types are represented in the target.
+///
+/// The aggregate-type methods recompute the layout on every call,
+/// prefer [`Module::layout_of`](crate::module::Module::layout_of), which caches it.
+pub trait Abi: 'static {
     /// How value types are represented in the target
     type BackendType;
 
@@ -18,13 +22,29 @@ pub trait Abi {
     /// 1 => two byte alignment (`short`/`int16` type)
     /// 2 => four byte alignment (`int`/`int32` type)
     /// 3 => eight byte alignment (`long`/`int64` type)
+    /// 4 => sixteen byte alignment (`v128` type)
     fn type_alignment(ty: Ty<'_>) -> usize;
 
     /// Return an offset at which the Nth field
     /// starts inside a struct
     fn struct_field_offset(struct_fields: &[Ty<'_>], field_n: usize) -> usize;
 
+    /// Return an offset at which the payload
+    /// starts inside a tagged union (the tag always starts at zero)
+    fn union_payload_offset(variants: &[Ty<'_>]) -> usize;
+
+    /// The distance between two consecutive elements of an array,
+    /// which is the size of the element rounded up to its alignment
+    fn array_elem_stride(elem: Ty<'_>) -> usize {
+        let align = 1 << Self::type_alignment(elem);
+        Self::type_sizeof(elem).div_ceil(align) * align
+    }
+
     fn is_little_endian() -> bool;
+
+    /// The largest number of scalars a struct returned from a function is flattened into,
+    /// larger structs are returned through memory, see [`returns_indirectly`]
+    fn max_flattened_return() -> usize;
 }
 
 pub struct Wasm32Abi {}
@@ -36,13 +56,19 @@ impl Abi for Wasm32Abi {
         match &*ty {
             Type::Int32 | Type::UInt32 | Type::Int16 | Type::UInt16 | Type::Int8 | Type::UInt8 
                 => wasm::ValType::I32,
+            Type::Int64 | Type::UInt64 => wasm::ValType::I64,
             Type::Float32 => wasm::ValType::F32,
+            Type::Float64 => wasm::ValType::F64,
+            Type::V128 => wasm::ValType::V128,
+            Type::ExternRef => wasm::ValType::ExternRef,
             // Function "types" are actually integer indexes into the global function table
             Type::Func { args: _, ret: _ } => wasm::ValType::I32,
             // TODO: support 64-bit memory and pointers
             Type::Ptr => wasm::ValType::I32,
-            // calling compile_type() on a Struct type should never happen in valid code
-            Type::Struct { fields: _ } => unreachable!()
+            // calling compile_type() on a Struct or an Array type should never happen in valid code,
+            // struct values are flattened into their scalars first, see `flatten_type`
+            Type::Struct { fields: _ } | Type::Array { elem: _, len: _ } |
+            Type::NamedStruct { name: _, body: _ } | Type::TaggedUnion { variants: _ } => unreachable!()
         }
     }
 
@@ -51,13 +77,20 @@ impl Abi for Wasm32Abi {
             Type::Int8  | Type::UInt8  => 1,
             Type::Int16 | Type::UInt16 => 2,
             Type::Int32 | Type::UInt32 => 4,
+            Type::Int64 | Type::UInt64 => 8,
             Type::Float32 => 4,
+            Type::Float64 => 8,
+            Type::V128 => 16,
+            // references can't be stored in memory, the Verifier rejects it
+            Type::ExternRef => unreachable!(),
             // actually an int32, thus 4
             Type::Func { args:_, ret:_ } => 4,
             // same as above
             Type::Ptr => 4,
-            // TODO: cache the results of the struct_calc algorithm, so we don't need to recalculate it every time
-            Type::Struct { fields } => struct_calc_algorithm::<Self>(fields).1
+            Type::Struct { fields: _ } | Type::NamedStruct { name: _, body: _ } =>
+                struct_calc_algorithm(ty.struct_fields().unwrap(), size_align::<Self>).1,
+            Type::Array { elem, len } => Self::array_elem_stride(*elem) * len,
+            Type::TaggedUnion { variants } => union_calc_algorithm(variants, size_align::<Self>).1
         }
     }
 
@@ -66,34 +99,113 @@ impl Abi for Wasm32Abi {
             Type::Int8  | Type::UInt8  => 0,
             Type::Int16 | Type::UInt16 => 1,
             Type::Int32 | Type::UInt32 => 2,
+            Type::Int64 | Type::UInt64 => 3,
             Type::Float32 => 2,
+            Type::Float64 => 3,
+            Type::V128 => 4,
+            Type::ExternRef => unreachable!(),
             Type::Func { args:_, ret:_ } => 2,
             Type::Ptr => 2,
-            // TODO: cache the results of the struct_calc algorithm, so we don't need to recalculate it every time
-            Type::Struct { fields } => struct_calc_algorithm::<Self>(fields).2
+            Type::Struct { fields: _ } | Type::NamedStruct { name: _, body: _ } =>
+                struct_calc_algorithm(ty.struct_fields().unwrap(), size_align::<Self>).2,
+            // an array is aligned the same as its elements
+            Type::Array { elem, len: _ } => Self::type_alignment(*elem),
+            Type::TaggedUnion { variants } => union_calc_algorithm(variants, size_align::<Self>).2
         }
     }
 
     fn struct_field_offset(struct_fields: &[Ty<'_>], field_n: usize) -> usize {
-        // TODO: cache the results of the struct_calc algorithm, so we don't need to recalculate it every time
-        struct_calc_algorithm::<Self>(struct_fields).0[field_n]
+        struct_calc_algorithm(struct_fields, size_align::<Self>).0[field_n]
+    }
+
+    fn union_payload_offset(variants: &[Ty<'_>]) -> usize {
+        union_calc_algorithm(variants, size_align::<Self>).0
     }
 
     fn is_little_endian() -> bool { true }
+
+    fn max_flattened_return() -> usize { 4 }
+}
+
+/// The scalar values a value of the type consists of, along with their offsets in memory.
+///
+/// This is the calling convention for structs: struct values (in locals, on the stack,
+/// as arguments and results) are flattened into the scalars of their fields, recursively.
+/// A value of any other type is a single scalar at offset zero.
+pub fn flatten_type<'ctx, A: Abi>(module: &Module<'ctx>, ty: Ty<'ctx>) -> Vec<(Ty<'ctx>, usize)> {
+    match ty.struct_fields() {
+        None => vec![(ty, 0)],
+        Some(fields) => fields.iter()
+            .enumerate()
+            .flat_map(|(n, field)| {
+                let field_offset = module.field_offset::<A>(ty, n);
+                flatten_type::<A>(module, *field).into_iter()
+                    .map(move |(scalar, offset)| (scalar, field_offset + offset))
+            })
+            .collect()
+    }
+}
+
+/// Returns true if a function with these result types returns a single struct
+/// which is too large to be flattened (see [`Abi::max_flattened_return`]).
+///
+/// Such a function has no results, instead it takes a pointer to memory allocated by the caller
+/// (in its frame on the shadow stack) as an additional last argument and writes the struct there
+/// before returning.
+pub fn returns_indirectly<'ctx, A: Abi>(module: &Module<'ctx>, rets: &[Ty<'ctx>]) -> bool {
+    match rets {
+        [ret] => ret.is_struct() && flatten_type::<A>(module, *ret).len() > A::max_flattened_return(),
+        _ => false
+    }
+}
+
+/// The (size, alignment) of a type, computed without caching
+fn size_align<A: Abi>(ty: Ty<'_>) -> (usize, usize) {
+    (A::type_sizeof(ty), A::type_alignment(ty))
+}
+
+/// Calculate the layout of a tagged union.
+/// The tag is an uint32 at offset zero, the payload follows it
+/// and is aligned to the largest alignment of the variants.
+///
+/// `size_align` returns the (size, alignment) of a variant.
+///
+/// Returns a tuple (payload_offset, union_size, union_alignment)
+pub(crate) fn union_calc_algorithm<'ctx>(variants: &[Ty<'ctx>], mut size_align: impl FnMut(Ty<'ctx>) -> (usize, usize)) -> (usize, usize, usize) {
+    // the alignment of the tag (uint32)
+    let mut align = 2;
+    let mut payload_size = 0;
+
+    for variant in variants {
+        let (variant_size, variant_align) = size_align(*variant);
+        if variant_align > align {
+            align = variant_align;
+        }
+        if variant_size > payload_size {
+            payload_size = variant_size;
+        }
+    }
+    // the tag is four bytes, the payload starts right after it (with padding)
+    let payload_offset = 4_usize.next_multiple_of(2_usize.pow(align as u32));
+
+    (payload_offset, payload_offset + payload_size, align)
 }
 
 /// The algorithm for calculating struct paddings, size and alignment
 /// For the details, see Structs Pt. 1 draft, section "Padding algorithm".
 ///
+/// `size_align` returns the (size, alignment) of a field.
+///
 /// Returns a vector (field_start_offsets, struct_size, struct_alignment)
-fn struct_calc_algorithm<A: Abi>(struct_fields: &[Ty<'_>]) -> (Vec<usize>, usize, usize) {
+pub(crate) fn struct_calc_algorithm<'ctx>(struct_fields: &[Ty<'ctx>], mut size_align: impl FnMut(Ty<'ctx>) -> (usize, usize)) -> (Vec<usize>, usize, usize) {
     let mut field_start_offsets = Vec::new();
     let mut size = 0;
     let mut align = 0; // the alignment is actually one, but we use exponents of two (2**0 = 1)
 
     for field in struct_fields {
+        let (field_size, field_align) = size_align(*field);
         // we need to convert the field alignment to bytes, because the Abi api uses exponents of two
-        let field_alignment = 2_usize.pow(A::type_alignment(*field) as u32);
+        let field_alignment = 2_usize.pow(field_align as u32);
         // if alignment is not preserved, add padding
         if (size % field_alignment) != 0 {
             let padding_size = field_alignment - (size % field_alignment);
@@ -101,9 +213,9 @@ fn struct_calc_algorithm<A: Abi>(struct_fields: &[Ty<'_>]) -> (Vec<usize>, usize
         }
         // now, the field starts
         field_start_offsets.push(size);
-        size += A::type_sizeof(*field);
-        if A::type_alignment(*field) > align {
-            align = A::type_alignment(*field);
+        size += field_size;
+        if field_align > align {
+            align = field_align;
         }
     }
 
@@ -112,7 +224,7 @@ fn struct_calc_algorithm<A: Abi>(struct_fields: &[Ty<'_>]) -> (Vec<usize>, usize
 
 #[cfg(test)]
 mod tests {
-    use crate::{abi::{Abi, Wasm32Abi}, module::{Module, WasmModuleConf}, ty::{Ty, Type}};
+    use crate::{abi::{Abi, Wasm32Abi, flatten_type, returns_indirectly}, module::{Module, WasmModuleConf}, ty::{Ty, Type}};
 
     #[test]
     pub fn struct_test() {
@@ -156,6 +268,88 @@ mod tests {
         assert_eq!(Wasm32Abi::struct_field_offset(helper(&struct_t3), 3), 16);
     }
 
+    #[test]
+    pub fn tagged_union_test() {
+        let m = Module::default();
+
+        let union_t1 = m.intern_type(Type::TaggedUnion { variants: vec![
+            m.int8t(), m.int16t()
+        ] });
+        let union_t2 = m.intern_type(Type::TaggedUnion { variants: vec![
+            m.int32t(), m.float64t(), m.intern_type(Type::Array { elem: m.uint8t(), len: 13 })
+        ] });
+        let union_t3 = m.intern_type(Type::TaggedUnion { variants: vec![] });
+
+        assert_eq!(Wasm32Abi::union_payload_offset(helper_union(&union_t1)), 4);
+        assert_eq!(Wasm32Abi::type_sizeof(union_t1), 6);
+        assert_eq!(Wasm32Abi::type_alignment(union_t1), 2); // the alignment of the tag
+
+        // the payload is aligned to 8 bytes because of the float64
+        assert_eq!(Wasm32Abi::union_payload_offset(helper_union(&union_t2)), 8);
+        assert_eq!(Wasm32Abi::type_sizeof(union_t2), 21);
+        assert_eq!(Wasm32Abi::type_alignment(union_t2), 3);
+
+        assert_eq!(Wasm32Abi::type_sizeof(union_t3), 4);
+    }
+
+    fn helper_union<'a, 'ctx>(ty: &'a Ty<'ctx>) -> &'a [Ty<'ctx>] {
+        match ty.as_ref() {
+            Type::TaggedUnion { variants } => variants,
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    pub fn array_test() {
+        let m = Module::default();
+
+        let struct_t1 = m.intern_type(Type::Struct { fields: vec![
+            m.int32t(), m.int8t()
+        ] });
+
+        let array_t1 = m.intern_type(Type::Array { elem: m.uint8t(), len: 4096 });
+        let array_t2 = m.intern_type(Type::Array { elem: struct_t1, len: 3 });
+        let array_t3 = m.intern_type(Type::Array { elem: array_t2, len: 2 });
+
+        assert_eq!(Wasm32Abi::type_sizeof(array_t1), 4096);
+        assert_eq!(Wasm32Abi::type_alignment(array_t1), 0);
+
+        // struct1 has size 5, but the elements must stay aligned to 4 bytes
+        assert_eq!(Wasm32Abi::array_elem_stride(struct_t1), 8);
+        assert_eq!(Wasm32Abi::type_sizeof(array_t2), 24);
+        assert_eq!(Wasm32Abi::type_alignment(array_t2), 2);
+
+        assert_eq!(Wasm32Abi::type_sizeof(array_t3), 48);
+        assert_eq!(Wasm32Abi::type_alignment(array_t3), 2);
+    }
+
+    #[test]
+    pub fn flatten_test() {
+        let m = Module::default();
+
+        let struct_t1 = m.intern_type(Type::Struct { fields: vec![
+            m.int8t(), m.float64t()
+        ] });
+        let struct_t2 = m.intern_type(Type::Struct { fields: vec![
+            m.ptr_t(), struct_t1, m.int16t()
+        ] });
+        let struct_t3 = m.intern_type(Type::Struct { fields: vec![
+            struct_t2, m.int32t()
+        ] });
+
+        assert_eq!(flatten_type::<Wasm32Abi>(&m, m.int32t()), vec![(m.int32t(), 0)]);
+        assert_eq!(flatten_type::<Wasm32Abi>(&m, struct_t1), vec![(m.int8t(), 0), (m.float64t(), 8)]);
+        // ptr, 4-byte padding, struct1 (int8, 7-byte padding, float64), int16
+        assert_eq!(flatten_type::<Wasm32Abi>(&m, struct_t2),
+            vec![(m.ptr_t(), 0), (m.int8t(), 8), (m.float64t(), 16), (m.int16t(), 24)]);
+
+        // struct2 is flattened into 4 scalars, struct3 into 5
+        assert!(!returns_indirectly::<Wasm32Abi>(&m, &[struct_t2]));
+        assert!(returns_indirectly::<Wasm32Abi>(&m, &[struct_t3]));
+        // only a single struct result is returned through memory
+        assert!(!returns_indirectly::<Wasm32Abi>(&m, &[struct_t3, m.int32t()]));
+    }
+
     fn helper<'a, 'ctx>(ty: &'a Ty<'ctx>) -> &'a [Ty<'ctx>] {
         match ty.as_ref() {
             Type::Struct { fields } => fields,
@@ -0,0 +1,189 @@
+//! Emitting the atomic memory instructions.
+//!
+//! The atomic instructions are a part of the WebAssembly threads proposal,
+//! which `wasm_encoder` doesn't support yet, so they are encoded by hand.
+//! The same goes for the import of the shared memory.
+
+use wasm_encoder::{self as wasm, Section};
+
+use crate::{abi::Abi, instr::{AtomicRmwOp, Instr, InstrK}, module::Module, numerics::{BitWidthSign, type_to_bws}};
+
+/// The prefix byte of all the threads proposal instructions
+const ATOMIC_PREFIX: u8 = 0xFE;
+
+/// Emit the WASM instructions for an atomic IR instruction
+pub(crate) fn emit_atomic_instr<'ctx, A: Abi>(module: &Module<'ctx>, instr: &Instr<'ctx>, out_f: &mut wasm::Function) {
+    match &instr.kind {
+        InstrK::AtomicNotify => emit_atomic_op(0x00, 2, out_f),
+        InstrK::AtomicWait { ty } => match type_to_bws(*ty).unwrap() {
+            BitWidthSign::S32 | BitWidthSign::U32 => emit_atomic_op(0x01, 2, out_f),
+            BitWidthSign::S64 | BitWidthSign::U64 => emit_atomic_op(0x02, 3, out_f),
+            _ => unreachable!()
+        },
+        InstrK::AtomicRead { ty } | InstrK::AtomicWrite { ty } |
+        InstrK::AtomicRmw { op: _, ty } | InstrK::AtomicCmpxchg { ty } => {
+            let bws = type_to_bws(*ty).unwrap();
+            // Every operation has a group of 7 opcodes which are ordered by the width:
+            // i32, i64, i32 8-bit, i32 16-bit, i64 8, 16 and 32-bit.
+            // Small integer types are represented as i32, so only the first 4 are used
+            let first_opcode = match &instr.kind {
+                InstrK::AtomicRead { ty: _ } => 0x10,
+                InstrK::AtomicWrite { ty: _ } => 0x17,
+                InstrK::AtomicRmw { op: AtomicRmwOp::Add, ty: _ } => 0x1E,
+                InstrK::AtomicRmw { op: AtomicRmwOp::Sub, ty: _ } => 0x25,
+                InstrK::AtomicRmw { op: AtomicRmwOp::And, ty: _ } => 0x2C,
+                InstrK::AtomicRmw { op: AtomicRmwOp::Or, ty: _ } => 0x33,
+                InstrK::AtomicRmw { op: AtomicRmwOp::Xchg, ty: _ } => 0x41,
+                InstrK::AtomicCmpxchg { ty: _ } => 0x48,
+                _ => unreachable!()
+            };
+            let width_idx = match bws {
+                BitWidthSign::S32 | BitWidthSign::U32 => 0,
+                BitWidthSign::S64 | BitWidthSign::U64 => 1,
+                BitWidthSign::S8 | BitWidthSign::U8 => 2,
+                BitWidthSign::S16 | BitWidthSign::U16 => 3,
+            };
+            // The alignment of atomic accesses must be exactly the natural alignment
+            emit_atomic_op(first_opcode + width_idx, module.alignment::<A>(*ty) as u32, out_f);
+
+            // The narrow atomic reads zero-extend, so the signed small integers
+            // have to be sign-extended with the "shift" idiom
+            let shift = match bws {
+                BitWidthSign::S8 => 24,
+                BitWidthSign::S16 => 16,
+                _ => return
+            };
+            if !matches!(instr.kind, InstrK::AtomicWrite { ty: _ }) {
+                out_f.instruction(&wasm::Instruction::I32Const(shift));
+                out_f.instruction(&wasm::Instruction::I32Shl);
+                out_f.instruction(&wasm::Instruction::I32Const(shift));
+                out_f.instruction(&wasm::Instruction::I32ShrS);
+            }
+        }
+        _ => unreachable!()
+    }
+}
+
+/// Emit a single atomic instruction with a memory argument with zero offset
+fn emit_atomic_op(opcode: u8, align: u32, out_f: &mut wasm::Function) {
+    out_f.raw([ATOMIC_PREFIX, opcode]);
+    out_f.raw(wasm::encoders::u32(align));
+    out_f.raw(wasm::encoders::u32(0));
+}
+
+/// Encode the contents of the import section with the shared memory imported as `env.memory`
+/// before the other imports, `wasm_encoder` doesn't support shared memories yet
+pub(crate) fn encode_import_section_with_shared_memory(imports: &wasm::ImportSection, minimum: u32, maximum: u32) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    imports.encode(&mut encoded);
+    // skip the size of the section and the count of the imports
+    let entries_start = skip_leb128(&encoded, skip_leb128(&encoded, 0));
+
+    let mut data: Vec<u8> = wasm::encoders::u32(imports.len() + 1).collect();
+    data.extend(wasm::encoders::str("env"));
+    data.extend(wasm::encoders::str("memory"));
+    // a memory import, then the flags: has a maximum (0b01) and is shared (0b10)
+    data.extend([0x02, 0b11]);
+    data.extend(wasm::encoders::u32(minimum));
+    data.extend(wasm::encoders::u32(maximum));
+    data.extend(&encoded[entries_start..]);
+    data
+}
+
+/// The position after the LEB128 encoded number which starts at `pos`
+fn skip_leb128(bytes: &[u8], pos: usize) -> usize {
+    let len = bytes[pos..].iter().position(|b| b & 0x80 == 0).unwrap() + 1;
+    pos + len
+}
+
+/// The shared memory is imported by every instance (thread), so the static memory
+/// can't be initialized by an active data segment, which would overwrite it every time.
+///
+/// Instead, the start function initializes it from a passive data segment exactly once.
+/// The `i32` at address `flag` is 0 before the initialization, 1 while it's in progress
+/// and 2 after it's done. The instances which don't win the race to initialize the memory
+/// wait until it's done, so the first instance must not run on a thread which can't wait
+/// (the main browser thread).
+pub(crate) fn emit_shared_memory_init(flag: u32, data_len: u32) -> wasm::Function {
+    let mut out_f = wasm::Function::new([]);
+    out_f.instruction(&wasm::Instruction::I32Const(flag as i32));
+    out_f.instruction(&wasm::Instruction::I32Const(0));
+    out_f.instruction(&wasm::Instruction::I32Const(1));
+    emit_atomic_op(0x48, 2, &mut out_f); // i32.atomic.rmw.cmpxchg
+    out_f.instruction(&wasm::Instruction::I32Eqz);
+    out_f.instruction(&wasm::Instruction::If(wasm::BlockType::Empty));
+    // the flag was 0, initialize the memory
+    out_f.instruction(&wasm::Instruction::I32Const(0));
+    out_f.instruction(&wasm::Instruction::I32Const(0));
+    out_f.instruction(&wasm::Instruction::I32Const(data_len as i32));
+    out_f.instruction(&wasm::Instruction::MemoryInit { mem: 0, data: 0 });
+    out_f.instruction(&wasm::Instruction::I32Const(flag as i32));
+    out_f.instruction(&wasm::Instruction::I32Const(2));
+    emit_atomic_op(0x17, 2, &mut out_f); // i32.atomic.store
+    // wake up all the waiting instances
+    out_f.instruction(&wasm::Instruction::I32Const(flag as i32));
+    out_f.instruction(&wasm::Instruction::I32Const(-1));
+    emit_atomic_op(0x00, 2, &mut out_f); // memory.atomic.notify
+    out_f.instruction(&wasm::Instruction::Drop);
+    out_f.instruction(&wasm::Instruction::Else);
+    // wait while the flag is 1, without a timeout
+    out_f.instruction(&wasm::Instruction::I32Const(flag as i32));
+    out_f.instruction(&wasm::Instruction::I32Const(1));
+    out_f.instruction(&wasm::Instruction::I64Const(-1));
+    emit_atomic_op(0x01, 2, &mut out_f); // memory.atomic.wait32
+    out_f.instruction(&wasm::Instruction::Drop);
+    out_f.instruction(&wasm::Instruction::End);
+    out_f.instruction(&wasm::Instruction::DataDrop(0));
+    out_f.instruction(&wasm::Instruction::End);
+    out_f
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{builder::{FunctionBuilder, InstrBuilder}, module::{Module, WasmModuleConf}, pipeline_compile_module_to_wasm, staticmem::{Mutability, SMItem, SMValue, Sign}};
+
+    #[test]
+    pub fn shared_memory_init_test() {
+        let mut m = Module::new(WasmModuleConf {
+            shared_memory: true,
+            maximum_memory_size: Some(16),
+            ..WasmModuleConf::default()
+        });
+        let item = m.add_static_mem_item(SMItem {
+            value: SMValue::Int32(42, Sign::S),
+            mutability: Mutability::Mut,
+            unique: true
+        });
+
+        let mut builder = FunctionBuilder::new("func".to_string(), [], [m.int32t()]);
+        builder.i_ld_static_mem_ptr(item);
+        builder.i_atomic_read(m.int32t());
+        builder.finish(&mut m);
+
+        let wasm_bytes = pipeline_compile_module_to_wasm(m, false);
+        let mut validator = wasmparser::Validator::new();
+        validator.wasm_features(wasmparser::WasmFeatures { threads: true, ..wasmparser::WasmFeatures::default() });
+        validator.validate_all(&wasm_bytes).unwrap();
+
+        let mut memory_imported = false;
+        let mut start_function = None;
+        for payload in wasmparser::Parser::new(0).parse_all(&wasm_bytes) {
+            match payload.unwrap() {
+                wasmparser::Payload::ImportSection(reader) => for import in reader {
+                    let import = import.unwrap();
+                    if let wasmparser::ImportSectionEntryType::Memory(ty) = import.ty {
+                        assert_eq!((import.module, import.field), ("env", Some("memory")));
+                        assert!(ty.shared);
+                        memory_imported = true;
+                    }
+                }
+                wasmparser::Payload::StartSection { func, range: _ } => start_function = Some(func),
+                wasmparser::Payload::MemorySection(_) => panic!("A shared memory must not be defined"),
+                _ => {}
+            }
+        }
+        assert!(memory_imported);
+        // the initialization function follows the only function
+        assert_eq!(start_function, Some(1));
+    }
+}
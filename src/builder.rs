@@ -2,10 +2,13 @@
 
 use std::collections::HashMap;
 
-use crate::{instr::{BlockId, BlockTag, Cmp, Function, Instr, InstrBlock, InstrK}, metadata::Metadata, module::Module, staticmem::SMItemRef, ty::{Ty, Type}};
+use crate::{instr::{AtomicRmwOp, BlockId, BlockTag, Cmp, Function, Instr, InstrBlock, InstrK, VecShape}, metadata::Metadata, module::Module, staticmem::SMItemRef, ty::{Ty, Type}};
+
+/// The parameters, returns, instructions and tag of a block being built
+type BlockData<'ctx> = (Vec<Ty<'ctx>>, Vec<Ty<'ctx>>, Vec<Instr<'ctx>>, BlockTag);
 
 pub struct FunctionBuilder<'ctx> {
-    blocks: HashMap<BlockId, (Vec<Ty<'ctx>>, Vec<Instr<'ctx>>, BlockTag)>,
+    blocks: HashMap<BlockId, BlockData<'ctx>>,
     next_block_id: usize,
     /// The index of the block currently being modified
     current_block: usize,
@@ -29,7 +32,7 @@ impl<'ctx> FunctionBuilder<'ctx> {
 
         // The type of the block is what values it returns
         // The main "entry" block returns the same values the function does
-        let entry_block = (returns.clone(), vec![], BlockTag::Main);
+        let entry_block = (vec![], returns.clone(), vec![], BlockTag::Main);
 
         let locals: Vec<_> = arguments.into_iter().collect();
 
@@ -60,10 +63,21 @@ impl<'ctx> FunctionBuilder<'ctx> {
     }
 
     pub fn new_block(&mut self, returns: impl IntoIterator<Item = Ty<'ctx>>, tag: BlockTag) -> BlockId {
+        self.new_block_with_params([], returns, tag)
+    }
+
+    /// Create a block which takes the values of types `params` from the stack of its branch instruction
+    pub fn new_block_with_params(
+        &mut self,
+        params: impl IntoIterator<Item = Ty<'ctx>>,
+        returns: impl IntoIterator<Item = Ty<'ctx>>,
+        tag: BlockTag) -> BlockId {
+
         let new_block_id = self.next_block_id.into();
         self.next_block_id += 1;
+        let params: Vec<_> = params.into_iter().collect();
         let returns: Vec<_> = returns.into_iter().collect();
-        let new_block = (returns, vec![], tag);
+        let new_block = (params, returns, vec![], tag);
         self.blocks.insert(new_block_id, new_block);
         new_block_id
     }
@@ -81,8 +95,8 @@ impl<'ctx> FunctionBuilder<'ctx> {
     pub fn finish(self, module: &mut Module<'ctx>) {
         // Build the blocks
         let mut blocks = HashMap::new();
-        for (id, (returns, mut instrs, tag)) in self.blocks {
-            let block_ty = module.intern_type(Type::Func { args: vec![], ret: returns });
+        for (id, (params, returns, mut instrs, tag)) in self.blocks {
+            let block_ty = module.intern_type(Type::Func { args: params, ret: returns });
             let mut block = InstrBlock::new(id, block_ty, tag);
             block.body.append(&mut instrs);
 
@@ -106,7 +120,7 @@ impl<'ctx> FunctionBuilder<'ctx> {
 impl<'ctx> InstrBuilder<'ctx> for FunctionBuilder<'ctx> {
     fn instr(&mut self, i: InstrK<'ctx>) {
         let curr_block = self.current_block.into();
-        self.blocks.get_mut(&curr_block).unwrap().1.push(
+        self.blocks.get_mut(&curr_block).unwrap().2.push(
             Instr { kind: i, meta: Metadata::new() }
         );
     }
@@ -115,48 +129,108 @@ impl<'ctx> InstrBuilder<'ctx> for FunctionBuilder<'ctx> {
 pub trait InstrBuilder<'ctx> {
     fn instr(&mut self, i: InstrK<'ctx>);
 
-    fn i_ld_int(&mut self, val: u32, int_ty: Ty<'ctx>) { self.instr(InstrK::LdInt(val, int_ty)) }
+    fn i_ld_int(&mut self, val: u64, int_ty: Ty<'ctx>) { self.instr(InstrK::LdInt(val, int_ty)) }
     fn i_ld_float(&mut self, val: f32) { self.instr(InstrK::LdFloat(val)) }
+    fn i_ld_float64(&mut self, val: f64) { self.instr(InstrK::LdFloat64(val)) }
     fn i_iadd(&mut self) { self.instr(InstrK::IAdd) }
     fn i_isub(&mut self) { self.instr(InstrK::ISub) }
     fn i_imul(&mut self) { self.instr(InstrK::IMul) }
     fn i_idiv(&mut self) { self.instr(InstrK::IDiv) }
+    fn i_irem(&mut self) { self.instr(InstrK::IRem) }
+    fn i_ineg(&mut self) { self.instr(InstrK::INeg) }
     fn i_fadd(&mut self) { self.instr(InstrK::FAdd) }
     fn i_fsub(&mut self) { self.instr(InstrK::FSub) }
     fn i_fmul(&mut self) { self.instr(InstrK::FMul) }
     fn i_fdiv(&mut self) { self.instr(InstrK::FDiv) }
-    fn i_itof(&mut self) { self.instr(InstrK::Itof) }
+    fn i_fmin(&mut self) { self.instr(InstrK::FMin) }
+    fn i_fmax(&mut self) { self.instr(InstrK::FMax) }
+    fn i_fcopysign(&mut self) { self.instr(InstrK::FCopysign) }
+    fn i_fsqrt(&mut self) { self.instr(InstrK::FSqrt) }
+    fn i_fabs(&mut self) { self.instr(InstrK::FAbs) }
+    fn i_fneg(&mut self) { self.instr(InstrK::FNeg) }
+    fn i_ffloor(&mut self) { self.instr(InstrK::FFloor) }
+    fn i_fceil(&mut self) { self.instr(InstrK::FCeil) }
+    fn i_ftrunc(&mut self) { self.instr(InstrK::FTrunc) }
+    fn i_fnearest(&mut self) { self.instr(InstrK::FNearest) }
+    fn i_itof(&mut self, float_ty: Ty<'ctx>) { self.instr(InstrK::Itof { float_ty }) }
     fn i_ftoi(&mut self, int_ty: Ty<'ctx>) { self.instr(InstrK::Ftoi { int_ty }) }
     fn i_iconv(&mut self, target: Ty<'ctx>) { self.instr(InstrK::IConv { target }) }
     fn i_icmp(&mut self, cmp: Cmp) { self.instr(InstrK::ICmp(cmp)) }
     fn i_fcmp(&mut self, cmp: Cmp) { self.instr(InstrK::FCmp(cmp)) }
+    fn i_vsplat(&mut self, shape: VecShape) { self.instr(InstrK::VSplat(shape)) }
+    fn i_vextract_lane(&mut self, shape: VecShape, lane: u8) { self.instr(InstrK::VExtractLane { shape, lane }) }
+    fn i_vreplace_lane(&mut self, shape: VecShape, lane: u8) { self.instr(InstrK::VReplaceLane { shape, lane }) }
+    fn i_vadd(&mut self, shape: VecShape) { self.instr(InstrK::VAdd(shape)) }
+    fn i_vsub(&mut self, shape: VecShape) { self.instr(InstrK::VSub(shape)) }
+    fn i_vmul(&mut self, shape: VecShape) { self.instr(InstrK::VMul(shape)) }
+    fn i_fpromote(&mut self) { self.instr(InstrK::FPromote) }
+    fn i_fdemote(&mut self) { self.instr(InstrK::FDemote) }
     fn i_not(&mut self) { self.instr(InstrK::Not) }
     fn i_bitand(&mut self) { self.instr(InstrK::BitAnd) }
     fn i_bitor(&mut self) { self.instr(InstrK::BitOr) }
+    fn i_bitxor(&mut self) { self.instr(InstrK::BitXor) }
+    fn i_bitnot(&mut self) { self.instr(InstrK::BitNot) }
+    fn i_shl(&mut self) { self.instr(InstrK::Shl) }
+    fn i_shr(&mut self) { self.instr(InstrK::Shr) }
     fn i_call(&mut self, func_name: String) { self.instr(InstrK::CallDirect { func_name }) }
     fn i_ld_local(&mut self, loc: LocalRef) { self.instr(InstrK::LdLocal { idx: loc.into() }) }
     fn i_st_local(&mut self, loc: LocalRef) { self.instr(InstrK::StLocal { idx: loc.into() }) }
     fn i_ld_global_func(&mut self, func_name: String) { self.instr(InstrK::LdGlobalFunc { func_name }) }
     fn i_call_indirect(&mut self) { self.instr(InstrK::CallIndirect) }
+    fn i_tail_call(&mut self, func_name: String) { self.instr(InstrK::TailCallDirect { func_name }) }
+    fn i_tail_call_indirect(&mut self) { self.instr(InstrK::TailCallIndirect) }
     fn i_bitcast(&mut self, target_type: Ty<'ctx>) { self.instr(InstrK::Bitcast { target: target_type }) }
     fn i_if_else(&mut self, then_block: BlockId, else_block: Option<BlockId>) {
         self.instr(InstrK::IfElse { then: then_block, r#else: else_block })
     }
+    fn i_switch(&mut self, cases: Vec<BlockId>, default: BlockId) {
+        self.instr(InstrK::Switch { cases, default })
+    }
+    fn i_try_catch(&mut self, body_block: BlockId, catches: Vec<(String, BlockId)>, catch_all_block: Option<BlockId>) {
+        self.instr(InstrK::TryCatch { body: body_block, catches, catch_all: catch_all_block })
+    }
+    fn i_throw(&mut self, tag: String) { self.instr(InstrK::Throw { tag }) }
     fn i_read(&mut self, ty: Ty<'ctx>) { self.instr(InstrK::Read { ty }) }
     fn i_write(&mut self, ty: Ty<'ctx>) { self.instr(InstrK::Write { ty }) }
+    fn i_atomic_read(&mut self, ty: Ty<'ctx>) { self.instr(InstrK::AtomicRead { ty }) }
+    fn i_atomic_write(&mut self, ty: Ty<'ctx>) { self.instr(InstrK::AtomicWrite { ty }) }
+    fn i_atomic_rmw(&mut self, op: AtomicRmwOp, ty: Ty<'ctx>) { self.instr(InstrK::AtomicRmw { op, ty }) }
+    fn i_atomic_cmpxchg(&mut self, ty: Ty<'ctx>) { self.instr(InstrK::AtomicCmpxchg { ty }) }
+    fn i_atomic_wait(&mut self, ty: Ty<'ctx>) { self.instr(InstrK::AtomicWait { ty }) }
+    fn i_atomic_notify(&mut self) { self.instr(InstrK::AtomicNotify) }
     fn i_offset(&mut self, ty: Ty<'ctx>) { self.instr(InstrK::Offset { ty }) }
+    fn i_offset_const(&mut self, ty: Ty<'ctx>, n: usize) { self.instr(InstrK::OffsetConst { ty, n }) }
+    fn i_stack_alloc(&mut self, ty: Ty<'ctx>) { self.instr(InstrK::StackAlloc { ty }) }
     fn i_get_field_ptr(&mut self, struct_ty: Ty<'ctx>, field_idx: usize) {
         self.instr(InstrK::GetFieldPtr { struct_ty, field_idx });
     }
+    fn i_get_elem_ptr(&mut self, array_ty: Ty<'ctx>) { self.instr(InstrK::GetElemPtr { array_ty }) }
+    fn i_get_tag(&mut self, union_ty: Ty<'ctx>) { self.instr(InstrK::GetTag { union_ty }) }
+    fn i_set_tag(&mut self, union_ty: Ty<'ctx>, variant_idx: usize) {
+        self.instr(InstrK::SetTag { union_ty, variant_idx });
+    }
+    fn i_get_variant_ptr(&mut self, union_ty: Ty<'ctx>, variant_idx: usize) {
+        self.instr(InstrK::GetVariantPtr { union_ty, variant_idx });
+    }
     fn i_discard(&mut self) { self.instr(InstrK::Discard) }
+    fn i_dup(&mut self) { self.instr(InstrK::Dup) }
+    fn i_swap(&mut self) { self.instr(InstrK::Swap) }
+    fn i_over(&mut self) { self.instr(InstrK::Over) }
+    fn i_select(&mut self) { self.instr(InstrK::Select) }
     fn i_return(&mut self) { self.instr(InstrK::Return) }
     fn i_memory_size(&mut self) { self.instr(InstrK::MemorySize) }
     fn i_memory_grow(&mut self) { self.instr(InstrK::MemoryGrow) }
+    fn i_mem_copy(&mut self) { self.instr(InstrK::MemCopy) }
+    fn i_mem_fill(&mut self) { self.instr(InstrK::MemFill) }
     fn i_ld_global(&mut self, name: String) { self.instr(InstrK::LdGlobal(name)) }
     fn i_st_global(&mut self, name: String) { self.instr(InstrK::StGlobal(name)) }
+    fn i_ld_null_ref(&mut self) { self.instr(InstrK::LdNullRef) }
+    fn i_is_null_ref(&mut self) { self.instr(InstrK::IsNullRef) }
     fn i_fail(&mut self) { self.instr(InstrK::Fail) }
     fn i_loop(&mut self, body_block: BlockId) { self.instr(InstrK::Loop(body_block)) }
     fn i_break(&mut self) { self.instr(InstrK::Break) }
+    fn i_break_loop(&mut self, loop_body: BlockId) { self.instr(InstrK::BreakLoop(loop_body)) }
+    fn i_continue_loop(&mut self, loop_body: BlockId) { self.instr(InstrK::ContinueLoop(loop_body)) }
     fn i_ld_static_mem_ptr(&mut self, item: SMItemRef) { self.instr(InstrK::LdStaticMemPtr(item)) }
 }
 
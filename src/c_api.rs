@@ -3,7 +3,7 @@
 
 use std::{ffi::CStr, panic::catch_unwind, ptr::null};
 
-use crate::{builder::{self, FunctionBuilder, InstrBuilder}, instr::{self, BlockTag, Cmp}, irprint::IRPrint, module::{ExternFunction, Module, WasmModuleConf}, staticmem::{Mutability, SMItem, SMItemRef}, ty::{Ty, Type}};
+use crate::{abi::Wasm32Abi, builder::{self, FunctionBuilder, InstrBuilder}, instr::{self, AtomicRmwOp, BlockTag, Cmp}, irprint::IRPrint, module::{ExternFunction, Module, WasmModuleConf}, staticmem::{Mutability, SMItem, SMItemRef}, ty::{Ty, Type}};
 
 #[inline]
 fn c_alloc<T>(x: T) -> *mut () { Box::leak(Box::new(x)) as *mut T as *mut () }
@@ -54,6 +54,20 @@ pub unsafe extern "C" fn dump_module(module: ModuleRef) {
 
 pub type TypeRef = *const ();
 
+#[no_mangle]
+pub unsafe extern "C" fn module_get_int64_type(module: ModuleRef) -> TypeRef {
+    (module as *const Module).as_ref()
+        .map(|m| m.int64t().as_ref() as *const Type as _)
+        .unwrap_or(null())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn module_get_uint64_type(module: ModuleRef) -> TypeRef {
+    (module as *const Module).as_ref()
+        .map(|m| m.uint64t().as_ref() as *const Type as _)
+        .unwrap_or(null())
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn module_get_int32_type(module: ModuleRef) -> TypeRef {
     (module as *const Module).as_ref()
@@ -103,6 +117,20 @@ pub unsafe extern "C" fn module_get_float32_type(module: ModuleRef) -> TypeRef {
         .unwrap_or(null())
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn module_get_float64_type(module: ModuleRef) -> TypeRef {
+    (module as *const Module).as_ref()
+        .map(|m| m.float64t().as_ref() as *const Type as _)
+        .unwrap_or(null())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn module_get_extern_ref_type(module: ModuleRef) -> TypeRef {
+    (module as *const Module).as_ref()
+        .map(|m| m.extern_ref_t().as_ref() as *const Type as _)
+        .unwrap_or(null())
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn module_get_ptr_type(module: ModuleRef) -> TypeRef {
     (module as *const Module).as_ref()
@@ -138,6 +166,66 @@ pub unsafe extern "C" fn module_get_struct_type(module: ModuleRef, field_types:
         .unwrap_or(null())
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn module_get_tagged_union_type(module: ModuleRef, variant_types: *const TypeRef, variantc: usize) -> TypeRef {
+    let variants = slice_of(variant_types, variantc).iter().map(|type_ref| {
+        Ty::from_raw(*type_ref as *const Type)
+    });
+    (module as *mut Module).as_mut()
+        .map(|m| m.intern_type(Type::TaggedUnion {
+            variants: variants.collect(),
+        }).as_ref() as *const Type as _)
+        .unwrap_or(null())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn module_declare_named_struct(module: ModuleRef, name: *const i8) -> TypeRef {
+    (module as *const Module).as_ref()
+        .map(|m| m.declare_named_struct(&string_of(name)).as_ref() as *const Type as _)
+        .unwrap_or(null())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn module_set_named_struct_body(module: ModuleRef, named_struct: TypeRef, field_types: *const TypeRef, fieldc: usize) {
+    let fields = slice_of(field_types, fieldc).iter().map(|type_ref| {
+        Ty::from_raw(*type_ref as *const Type)
+    });
+    (module as *const Module).as_ref().unwrap()
+        .set_named_struct_body(Ty::from_raw(named_struct as *const Type), fields.collect())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn module_get_array_type(module: ModuleRef, elem_type: TypeRef, len: usize) -> TypeRef {
+    (module as *mut Module).as_mut()
+        .map(|m| m.intern_type(Type::Array {
+            elem: Ty::from_raw(elem_type as *const Type),
+            len,
+        }).as_ref() as *const Type as _)
+        .unwrap_or(null())
+}
+
+/// The size of a type in bytes
+#[no_mangle]
+pub unsafe extern "C" fn module_type_sizeof(module: ModuleRef, ty: TypeRef) -> usize {
+    (module as *const Module).as_ref().unwrap()
+        .sizeof::<Wasm32Abi>(Ty::from_raw(ty as *const Type))
+}
+
+/// The alignment of a type, expressed as an exponent of two
+#[no_mangle]
+pub unsafe extern "C" fn module_type_alignment(module: ModuleRef, ty: TypeRef) -> usize {
+    (module as *const Module).as_ref().unwrap()
+        .alignment::<Wasm32Abi>(Ty::from_raw(ty as *const Type))
+}
+
+/// The offset of the Nth field of a struct type
+/// (or of the payload of the Nth variant of a tagged union)
+#[no_mangle]
+pub unsafe extern "C" fn module_type_field_offset(module: ModuleRef, ty: TypeRef, field_idx: usize) -> usize {
+    (module as *const Module).as_ref().unwrap()
+        .field_offset::<Wasm32Abi>(Ty::from_raw(ty as *const Type), field_idx)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn module_new_int_global(module: ModuleRef, global_name: *const i8, value: i32) {
     (module as *mut Module).as_mut().unwrap()
@@ -150,6 +238,21 @@ pub unsafe extern "C" fn module_new_float_global(module: ModuleRef, global_name:
         .new_float_global(string_of(global_name), value);
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn module_new_extern_ref_global(module: ModuleRef, global_name: *const i8) {
+    (module as *mut Module).as_mut().unwrap()
+        .new_extern_ref_global(string_of(global_name));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn module_new_exception_tag(module: ModuleRef, tag_name: *const i8, payload: *const TypeRef, payloadc: usize) {
+    let payload = slice_of(payload, payloadc).iter().map(|type_ref| {
+        Ty::from_raw(*type_ref as *const Type)
+    }).collect();
+    (module as *mut Module).as_mut().unwrap()
+        .new_exception_tag(string_of(tag_name), payload);
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn module_new_extern_function(
     module: ModuleRef, 
@@ -225,6 +328,23 @@ pub unsafe extern "C" fn builder_new_block(builder: FunctionBuilderRef, block_re
     (builder as *mut FunctionBuilder).as_mut().unwrap().new_block(returns, block_tag)
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn builder_new_block_with_params(
+    builder: FunctionBuilderRef,
+    block_params: *const TypeRef,
+    block_paramc: usize,
+    block_returns: *const TypeRef,
+    block_returnc: usize,
+    block_tag: BlockTag) -> BlockId {
+    let params = slice_of(block_params, block_paramc).iter().map(|type_ref| {
+        Ty::from_raw(*type_ref as *const Type)
+    });
+    let returns = slice_of(block_returns, block_returnc).iter().map(|type_ref| {
+        Ty::from_raw(*type_ref as *const Type)
+    });
+    (builder as *mut FunctionBuilder).as_mut().unwrap().new_block_with_params(params, returns, block_tag)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn builder_switch_block(builder: FunctionBuilderRef, new_block: BlockId) {
     (builder as *mut FunctionBuilder).as_mut().unwrap().switch_block(new_block)
@@ -238,7 +358,7 @@ pub unsafe extern "C" fn builder_get_current_block(builder: FunctionBuilderRef)
 // INSTRUCTIONS
 
 #[no_mangle]
-pub unsafe extern "C" fn builder_i_ld_int(builder: FunctionBuilderRef, val: u32, int_type: TypeRef) {
+pub unsafe extern "C" fn builder_i_ld_int(builder: FunctionBuilderRef, val: u64, int_type: TypeRef) {
      (builder as *mut FunctionBuilder).as_mut().unwrap()
         .i_ld_int(val, Ty::from_raw(int_type as *const Type)) 
 }
@@ -246,6 +366,9 @@ pub unsafe extern "C" fn builder_i_ld_int(builder: FunctionBuilderRef, val: u32,
 #[no_mangle]
 pub unsafe extern "C" fn builder_i_ld_float(builder: FunctionBuilderRef, val: f32) { (builder as *mut FunctionBuilder).as_mut().unwrap().i_ld_float(val) }
 
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_ld_float64(builder: FunctionBuilderRef, val: f64) { (builder as *mut FunctionBuilder).as_mut().unwrap().i_ld_float64(val) }
+
 macro_rules! argless_instr {
     ( $( $out_name:ident : $instr_name:ident )* ) => {
         $(       
@@ -262,23 +385,57 @@ argless_instr!(
     builder_i_isub : i_isub
     builder_i_imul : i_imul
     builder_i_idiv : i_idiv
+    builder_i_irem : i_irem
+    builder_i_ineg : i_ineg
     builder_i_fadd : i_fadd
     builder_i_fsub : i_fsub
     builder_i_fmul : i_fmul
     builder_i_fdiv : i_fdiv
-    builder_i_itof : i_itof
+    builder_i_fmin : i_fmin
+    builder_i_fmax : i_fmax
+    builder_i_fcopysign : i_fcopysign
+    builder_i_fsqrt : i_fsqrt
+    builder_i_fabs : i_fabs
+    builder_i_fneg : i_fneg
+    builder_i_ffloor : i_ffloor
+    builder_i_fceil : i_fceil
+    builder_i_ftrunc : i_ftrunc
+    builder_i_fnearest : i_fnearest
+    builder_i_fpromote : i_fpromote
+    builder_i_fdemote : i_fdemote
     builder_i_not : i_not
     builder_i_bitand : i_bitand
     builder_i_bitor : i_bitor
+    builder_i_bitxor : i_bitxor
+    builder_i_bitnot : i_bitnot
+    builder_i_shl : i_shl
+    builder_i_shr : i_shr
     builder_i_call_indirect : i_call_indirect
+    builder_i_tail_call_indirect : i_tail_call_indirect
     builder_i_memory_grow : i_memory_grow
     builder_i_memory_size : i_memory_size
+    builder_i_atomic_notify : i_atomic_notify
+    builder_i_mem_copy : i_mem_copy
+    builder_i_mem_fill : i_mem_fill
     builder_i_discard : i_discard
+    builder_i_dup : i_dup
+    builder_i_swap : i_swap
+    builder_i_over : i_over
+    builder_i_select : i_select
     builder_i_return : i_return
     builder_i_fail : i_fail
     builder_i_break : i_break
+    builder_i_ld_null_ref : i_ld_null_ref
+    builder_i_is_null_ref : i_is_null_ref
 );
 
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_itof(builder: FunctionBuilderRef, float_type: TypeRef) {
+    (builder as *mut FunctionBuilder).as_mut().unwrap().i_itof(
+        Ty::from_raw(float_type as *const Type)
+    )
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn builder_i_ftoi(builder: FunctionBuilderRef, int_type: TypeRef) {
     (builder as *mut FunctionBuilder).as_mut().unwrap().i_ftoi(
@@ -293,6 +450,31 @@ pub unsafe extern "C" fn builder_i_iconv(builder: FunctionBuilderRef, int_type:
     )
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_atomic_read(builder: FunctionBuilderRef, ty: TypeRef) {
+    (builder as *mut FunctionBuilder).as_mut().unwrap().i_atomic_read(Ty::from_raw(ty as *const Type))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_atomic_write(builder: FunctionBuilderRef, ty: TypeRef) {
+    (builder as *mut FunctionBuilder).as_mut().unwrap().i_atomic_write(Ty::from_raw(ty as *const Type))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_atomic_rmw(builder: FunctionBuilderRef, op: AtomicRmwOp, ty: TypeRef) {
+    (builder as *mut FunctionBuilder).as_mut().unwrap().i_atomic_rmw(op, Ty::from_raw(ty as *const Type))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_atomic_cmpxchg(builder: FunctionBuilderRef, ty: TypeRef) {
+    (builder as *mut FunctionBuilder).as_mut().unwrap().i_atomic_cmpxchg(Ty::from_raw(ty as *const Type))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_atomic_wait(builder: FunctionBuilderRef, ty: TypeRef) {
+    (builder as *mut FunctionBuilder).as_mut().unwrap().i_atomic_wait(Ty::from_raw(ty as *const Type))
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn builder_i_icmp(builder: FunctionBuilderRef, cmp: Cmp) { (builder as *mut FunctionBuilder).as_mut().unwrap().i_icmp(cmp) }
 
@@ -302,6 +484,9 @@ pub unsafe extern "C" fn builder_i_fcmp(builder: FunctionBuilderRef, cmp: Cmp) {
 #[no_mangle]
 pub unsafe extern "C" fn builder_i_call(builder: FunctionBuilderRef, func_name: *const i8) { (builder as *mut FunctionBuilder).as_mut().unwrap().i_call(string_of(func_name)) }
 
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_tail_call(builder: FunctionBuilderRef, func_name: *const i8) { (builder as *mut FunctionBuilder).as_mut().unwrap().i_tail_call(string_of(func_name)) }
+
 #[no_mangle]
 pub unsafe extern "C" fn builder_i_ld_local(builder: FunctionBuilderRef, loc: LocalRef) { (builder as *mut FunctionBuilder).as_mut().unwrap().i_ld_local(loc) }
 
@@ -332,6 +517,39 @@ pub unsafe extern "C" fn builder_i_if_else(builder: FunctionBuilderRef, then_blo
     ) 
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_switch(builder: FunctionBuilderRef, case_blocks: *const BlockId, casec: usize, default_block: BlockId) {
+    (builder as *mut FunctionBuilder).as_mut().unwrap().i_switch(
+        slice_of(case_blocks, casec).to_vec(), default_block
+    )
+}
+
+unsafe fn catches_of(catch_tags: *const *const i8, catch_blocks: *const BlockId, catchc: usize) -> Vec<(String, BlockId)> {
+    slice_of(catch_tags, catchc).iter()
+        .map(|tag| string_of(*tag))
+        .zip(slice_of(catch_blocks, catchc).iter().copied())
+        .collect()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_try_catch(builder: FunctionBuilderRef, body_block: BlockId, catch_tags: *const *const i8, catch_blocks: *const BlockId, catchc: usize) {
+    (builder as *mut FunctionBuilder).as_mut().unwrap().i_try_catch(
+        body_block, catches_of(catch_tags, catch_blocks, catchc), None
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_try_catch_all(builder: FunctionBuilderRef, body_block: BlockId, catch_tags: *const *const i8, catch_blocks: *const BlockId, catchc: usize, catch_all_block: BlockId) {
+    (builder as *mut FunctionBuilder).as_mut().unwrap().i_try_catch(
+        body_block, catches_of(catch_tags, catch_blocks, catchc), Some(catch_all_block)
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_throw(builder: FunctionBuilderRef, tag_name: *const i8) {
+    (builder as *mut FunctionBuilder).as_mut().unwrap().i_throw(string_of(tag_name))
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn builder_i_read(builder: FunctionBuilderRef, ty: TypeRef) { 
     (builder as *mut FunctionBuilder).as_mut().unwrap().i_read(
@@ -353,6 +571,21 @@ pub unsafe extern "C" fn builder_i_offset(builder: FunctionBuilderRef, ty: TypeR
     ) 
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_offset_const(builder: FunctionBuilderRef, ty: TypeRef, n: usize) { 
+    (builder as *mut FunctionBuilder).as_mut().unwrap().i_offset_const(
+        Ty::from_raw(ty as *const Type),
+        n
+    ) 
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_stack_alloc(builder: FunctionBuilderRef, ty: TypeRef) { 
+    (builder as *mut FunctionBuilder).as_mut().unwrap().i_stack_alloc(
+        Ty::from_raw(ty as *const Type)
+    ) 
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn builder_i_get_field_ptr(builder: FunctionBuilderRef, struct_ty: TypeRef, field_idx: usize) { 
     (builder as *mut FunctionBuilder).as_mut().unwrap().i_get_field_ptr(
@@ -361,6 +594,36 @@ pub unsafe extern "C" fn builder_i_get_field_ptr(builder: FunctionBuilderRef, st
     ) 
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_get_tag(builder: FunctionBuilderRef, union_ty: TypeRef) { 
+    (builder as *mut FunctionBuilder).as_mut().unwrap().i_get_tag(
+        Ty::from_raw(union_ty as *const Type)
+    ) 
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_set_tag(builder: FunctionBuilderRef, union_ty: TypeRef, variant_idx: usize) { 
+    (builder as *mut FunctionBuilder).as_mut().unwrap().i_set_tag(
+        Ty::from_raw(union_ty as *const Type),
+        variant_idx
+    ) 
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_get_variant_ptr(builder: FunctionBuilderRef, union_ty: TypeRef, variant_idx: usize) { 
+    (builder as *mut FunctionBuilder).as_mut().unwrap().i_get_variant_ptr(
+        Ty::from_raw(union_ty as *const Type),
+        variant_idx
+    ) 
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_get_elem_ptr(builder: FunctionBuilderRef, array_ty: TypeRef) { 
+    (builder as *mut FunctionBuilder).as_mut().unwrap().i_get_elem_ptr(
+        Ty::from_raw(array_ty as *const Type)
+    ) 
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn builder_i_ld_global(builder: FunctionBuilderRef, name: *const i8) { 
     (builder as *mut FunctionBuilder).as_mut().unwrap().i_ld_global(string_of(name)) 
@@ -376,6 +639,16 @@ pub unsafe extern "C" fn builder_i_loop(builder: FunctionBuilderRef, body_block:
     (builder as *mut FunctionBuilder).as_mut().unwrap().i_loop(body_block) 
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_break_loop(builder: FunctionBuilderRef, loop_body_block: BlockId) { 
+    (builder as *mut FunctionBuilder).as_mut().unwrap().i_break_loop(loop_body_block) 
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn builder_i_continue_loop(builder: FunctionBuilderRef, loop_body_block: BlockId) { 
+    (builder as *mut FunctionBuilder).as_mut().unwrap().i_continue_loop(loop_body_block) 
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn builder_i_ld_static_mem_ptr(builder: FunctionBuilderRef, static_mem_item: SMItemRef) { 
     (builder as *mut FunctionBuilder).as_mut().unwrap().i_ld_static_mem_ptr(static_mem_item) 
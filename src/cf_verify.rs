@@ -10,6 +10,8 @@
 //! 
 //! Calculates the innermost-loop-distances as described
 //! in the Control Flow part 2 proposal
+//! and checks that labeled loop jumps (`BreakLoop`, `ContinueLoop`)
+//! target a loop which encloses them
 
 use std::collections::HashMap;
 
@@ -73,24 +75,58 @@ impl<'ctx> MutableFunctionPass<'ctx> for ControlFlowVerifier {
         
         // For every block, save its parent (where it appears)
         let mut block_parents: HashMap<BlockId, BlockId> = HashMap::new();
+        // For every IfElse, Switch, Try and Catch block, save how many wasm labels (blocks)
+        // enclose its body inside its parent's body, see `WasmEmitter`
+        let mut label_depths: HashMap<BlockId, usize> = HashMap::new();
+        // The (block, target loop body) pairs of all BreakLoop and ContinueLoop instructions
+        let mut loop_jumps: Vec<(BlockId, BlockId)> = Vec::new();
         for block in function.blocks_iter() {
             let this_block = block.idx;
 
             for instr in &block.body {
-                #[allow(clippy::single_match)]
-                match instr.kind {
+                match &instr.kind {
                    InstrK::IfElse { then, r#else } => {
-                        self.assert_parent(&mut block_parents, then, this_block)?;
-                        self.assert_tag(BlockTag::IfElse, then, function)?;
+                        self.assert_parent(&mut block_parents, *then, this_block)?;
+                        self.assert_tag(BlockTag::IfElse, *then, function)?;
+                        label_depths.insert(*then, 1);
 
                        if let Some(else_block) = r#else {
-                           self.assert_parent(&mut block_parents, else_block, this_block)?;
-                           self.assert_tag(BlockTag::IfElse, else_block, function)?;
+                           self.assert_parent(&mut block_parents, *else_block, this_block)?;
+                           self.assert_tag(BlockTag::IfElse, *else_block, function)?;
+                           label_depths.insert(*else_block, 1);
+                        }
+                   }
+                   InstrK::Switch { cases, default } => {
+                        // The Nth of the K cases is nested in (K - N + 1) blocks,
+                        // the default case only in one
+                        for (n, case) in cases.iter().enumerate() {
+                            self.assert_parent(&mut block_parents, *case, this_block)?;
+                            self.assert_tag(BlockTag::Switch, *case, function)?;
+                            label_depths.insert(*case, cases.len() - n + 1);
+                        }
+                        self.assert_parent(&mut block_parents, *default, this_block)?;
+                        self.assert_tag(BlockTag::Switch, *default, function)?;
+                        label_depths.insert(*default, 1);
+                   }
+                   InstrK::TryCatch { body, catches, catch_all } => {
+                        // The body and the handlers are all in the same `try` label
+                        self.assert_parent(&mut block_parents, *body, this_block)?;
+                        self.assert_tag(BlockTag::Try, *body, function)?;
+                        label_depths.insert(*body, 1);
+
+                        for handler in catches.iter().map(|(_, handler)| handler).chain(catch_all) {
+                            self.assert_parent(&mut block_parents, *handler, this_block)?;
+                            self.assert_tag(BlockTag::Catch, *handler, function)?;
+                            label_depths.insert(*handler, 1);
                         }
                    }
                    InstrK::Loop(child) => {
-                        self.assert_parent(&mut block_parents, child, this_block)?;
-                        self.assert_tag(BlockTag::Loop, child, function)?;
+                        self.assert_parent(&mut block_parents, *child, this_block)?;
+                        self.assert_tag(BlockTag::Loop, *child, function)?;
+                   }
+                   InstrK::BreakLoop(target) | InstrK::ContinueLoop(target) => {
+                        self.assert_tag(BlockTag::Loop, *target, function)?;
+                        loop_jumps.push((this_block, *target));
                    }
                    _ => {} // ignore other instructions
                 }
@@ -106,33 +142,48 @@ impl<'ctx> MutableFunctionPass<'ctx> for ControlFlowVerifier {
             })
         }
 
+        // The target loop body of a labeled jump has to be the block itself or one of its parents
+        for (block, target) in loop_jumps {
+            let mut current_block = block;
+            while current_block != target {
+                current_block = *block_parents.get(&current_block)
+                    .ok_or(ControlFlowVerifierError::LoopNotEnclosing { block, loop_body: target })?;
+            }
+        }
+
         // Now that we know the parents, calculate the innermost loop distances
+        // and remember which loop is the innermost one
         let mut innermost_loop_distances: HashMap<BlockId, usize> = HashMap::new();
+        let mut innermost_loops: HashMap<BlockId, BlockId> = HashMap::new();
         for block in function.blocks_iter() {
             match block.tag() {
                 BlockTag::Undefined | BlockTag::Main => continue,
                 BlockTag::Loop => {
                     // For `Loop`, the innermost_loop_distance is zero
                     innermost_loop_distances.insert(block.idx, 0usize);
+                    innermost_loops.insert(block.idx, block.idx);
                 },
-                BlockTag::IfElse => {
-                    // For `IfElse`, search though the parents until we find a `Loop` block
-                    let mut innermost_loop_distance: isize = 1;
+                BlockTag::IfElse | BlockTag::Switch | BlockTag::Try | BlockTag::Catch => {
+                    // For the other blocks, search though the parents until we find a `Loop` block
+                    // and sum up the label depths of the blocks on the way
+                    let mut innermost_loop_distance: isize = 0;
                     let mut current_block = block.idx;
                     loop {
+                        innermost_loop_distance += label_depths[&current_block] as isize;
                         let parent = block_parents[&current_block];
                         match function.get_block(parent).unwrap().tag {
                             BlockTag::Undefined | BlockTag::Main => {
-                                // The IfElse block is not a part of any kind of loop
+                                // The block is not a part of any kind of loop
                                 // because none of its parents is a loop
                                 innermost_loop_distance = -1;
                                 break
                             },
-                            BlockTag::IfElse => {
-                                innermost_loop_distance += 1;
-                            },
+                            BlockTag::IfElse | BlockTag::Switch | BlockTag::Try | BlockTag::Catch => {},
                             // We found the nearest loop
-                            BlockTag::Loop => break,
+                            BlockTag::Loop => {
+                                innermost_loops.insert(block.idx, parent);
+                                break
+                            },
                         }
                         current_block = parent;
                     }
@@ -143,7 +194,7 @@ impl<'ctx> MutableFunctionPass<'ctx> for ControlFlowVerifier {
             }
         }
 
-        Ok(ControlFlowVerifierData { block_parents, innermost_loop_distances } )
+        Ok(ControlFlowVerifierData { block_parents, innermost_loop_distances, innermost_loops } )
     }
 
     fn mutate_function(
@@ -161,6 +212,10 @@ impl<'ctx> MutableFunctionPass<'ctx> for ControlFlowVerifier {
                     key!("innermost_loop_distance"), 
                     info.innermost_loop_distances[&block.idx]);
             } 
+
+            if info.innermost_loops.contains_key(&block.idx) {
+                block.meta.insert(key!("innermost_loop"), info.innermost_loops[&block.idx]);
+            }
         }
 
         Ok(())
@@ -170,10 +225,12 @@ impl<'ctx> MutableFunctionPass<'ctx> for ControlFlowVerifier {
 pub struct ControlFlowVerifierData {
     block_parents: HashMap<BlockId, BlockId>,
     innermost_loop_distances: HashMap<BlockId, usize>,
+    innermost_loops: HashMap<BlockId, BlockId>,
 }
 
 #[derive(Debug)]
 pub enum ControlFlowVerifierError {
     MultipleParents { block: BlockId, parent: BlockId, other_parent: BlockId },
-    InvalidBlockTag { block: BlockId, expected: BlockTag, actual: BlockTag }
+    InvalidBlockTag { block: BlockId, expected: BlockTag, actual: BlockTag },
+    LoopNotEnclosing { block: BlockId, loop_body: BlockId }
 }
\ No newline at end of file
@@ -7,7 +7,7 @@ use crate::{instr::InstrK, pass::MutableFunctionPass};
 ///
 /// Specifically, this means:
 /// * Removing all instructions which follow after a "diverging instruction",
-/// which means one of: Return, Fail, Break
+/// which means one of: Return, TailCallDirect, TailCallIndirect, Throw, Fail, Break, BreakLoop, ContinueLoop
 pub struct CorrectionPass {}
 
 impl<'ctx> MutableFunctionPass<'ctx> for CorrectionPass {
@@ -29,7 +29,7 @@ impl<'ctx> MutableFunctionPass<'ctx> for CorrectionPass {
         function: &mut crate::instr::Function<'ctx>,
         _info: Self::MutationInfo) -> Result<(), Self::Error> {
 
-        // Remove instructions diverging instrs: Fail, Break, Return
+        // Remove instructions after diverging instrs: Fail, Break, BreakLoop, ContinueLoop, Return, tail calls, Throw
         for block in function.blocks_iter_mut() {
             let mut fail_instr_pos = None;
             for (n, i) in block.body.iter().enumerate() {
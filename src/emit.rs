@@ -1,15 +1,57 @@
-use std::{collections::HashMap, convert::TryInto, marker::PhantomData};
+use std::{borrow::Cow, collections::{BTreeMap, HashMap}, convert::TryInto, marker::PhantomData, ops::Range};
 
 use wasm_encoder as wasm;
 
-use crate::{abi::Abi, instr::{Cmp, Function, InstrBlock, InstrK}, module::{FuncDef, Functional, Module}, numerics::{emit_numeric_instr, type_to_bws}, pass::FunctionPass, staticmem::{CompiledStaticMemory, SMItemRef}, ty::{Ty, Type}};
+use crate::{abi::{Abi, flatten_type, returns_indirectly}, atomics::{emit_atomic_instr, emit_shared_memory_init, encode_import_section_with_shared_memory}, instr::{BlockId, Cmp, Function, Instr, InstrBlock, InstrK, VecShape}, intrinsic::Intrinsics, module::{FuncDef, Functional, Module, WasmModuleConf}, numerics::{BitWidthSign, emit_numeric_instr, type_to_bws}, pass::FunctionPass, staticmem::{CompiledStaticMemory, SMItemRef}, ty::{Ty, Type}};
+
+/// The frame of a function on the shadow stack
+struct StackFrame {
+    /// The size of the frame, a multiple of 16 so the stack pointer stays aligned
+    size: u32,
+    /// The local which points to the start of the frame
+    pointer: u32,
+    /// The offset in the frame of the memory of every `StackAlloc` instruction
+    /// and of the result of every call returning a large struct
+    offsets: HashMap<(BlockId, usize), u32>,
+}
 
 pub struct WasmEmitter<'ctx, A: Abi> {
     module: wasm::Module,
     /// A table of function types and their indexes in the resulting wasm module
     function_types: HashMap<Ty<'ctx>, u32>,
+    /// The types of blocks whose results differ from the results of a function of the same type,
+    /// which is the case if the function returns a large struct through memory
+    block_types: HashMap<Ty<'ctx>, u32>,
     /// Memory addresses of items in static memory
     static_memory_addresses: HashMap<SMItemRef, usize>,
+    /// The index of the global holding the shadow stack pointer,
+    /// it's only created if some function has a frame (see [`frame_slot_type`]).
+    ///
+    /// With shared memory, it's imported so that every instance (thread) has its own stack,
+    /// the imported global comes before all the module's globals
+    stack_pointer: Option<u32>,
+    /// True if the stack pointer is imported
+    stack_pointer_imported: bool,
+    /// The frame of the function being compiled, if it has one
+    frame: Option<StackFrame>,
+    /// The wasm locals of every IR local of the function being compiled,
+    /// a struct local is flattened into several wasm locals (see [`flatten_type`])
+    local_indices: Vec<Range<u32>>,
+    /// The argument of the function being compiled which points to where its result is written,
+    /// if it returns a large struct (see [`returns_indirectly`])
+    return_pointer: Option<u32>,
+    /// The index of the first scratch `i32` local of the function being compiled.
+    /// They're declared after all the IR locals, only if the function needs them
+    /// (one for `Switch`, three for `MemCopy` and `MemFill` without bulk memory,
+    /// one for struct `Read` and `Write` and for indirect calls returning a large struct).
+    ///
+    /// Every instruction uses them only within its own lowering, so they can be shared.
+    scratch_local: u32,
+    /// For every value type used by `Dup`, `Swap` and `Over` in the function being compiled,
+    /// the index of the first of consecutive scratch locals of that type.
+    /// There are two of them, or more if a struct value with more scalars of the type
+    /// is written to memory. They're declared after the scratch `i32` locals
+    stack_scratch_locals: HashMap<wasm::ValType, u32>,
 
     /* Follow the sections. Because the Wasm specification requires a certain order,
     the sections are saved separately and only combined into the module file at the very end */
@@ -23,6 +65,16 @@ pub struct WasmEmitter<'ctx, A: Abi> {
     table_sec: wasm::TableSection,
     /// Defines the memory
     memory_sec: wasm::MemorySection,
+    /// The (minimum, maximum) size of the shared memory, which replaces `memory_sec`
+    /// by an import, see [`encode_import_section_with_shared_memory`]
+    shared_memory: Option<(u32, u32)>,
+    /// The address of the initialization flag and the length of the static memory
+    /// if it's initialized by the start function, see [`emit_shared_memory_init`]
+    shared_memory_init: Option<(u32, u32)>,
+    /// The index of the start function, if there is one
+    start_function: Option<u32>,
+    /// Defines the exception tags, only emitted if there are any
+    tag_sec: wasm::TagSection,
     /// Defines the global items
     global_sec: wasm::GlobalSection,
     /// Defines what items (functions, memories) are exported
@@ -46,13 +98,25 @@ impl<'ctx, A: Abi<BackendType = wasm::ValType>> WasmEmitter<'ctx, A> {
         WasmEmitter {
             module: wasm::Module::new(),
             function_types: HashMap::new(),
+            block_types: HashMap::new(),
             static_memory_addresses: HashMap::new(),
+            stack_pointer: None,
+            stack_pointer_imported: false,
+            frame: None,
+            local_indices: Vec::new(),
+            return_pointer: None,
+            scratch_local: 0,
+            stack_scratch_locals: HashMap::new(),
 
             type_sec: wasm::TypeSection::new(),
             import_sec: wasm::ImportSection::new(),
             func_sec: wasm::FunctionSection::new(),
             table_sec: wasm::TableSection::new(),
             memory_sec: wasm::MemorySection::new(),
+            shared_memory: None,
+            shared_memory_init: None,
+            start_function: None,
+            tag_sec: wasm::TagSection::new(),
             global_sec: wasm::GlobalSection::new(),
             export_sec: wasm::ExportSection::new(),
             elem_sec: wasm::ElementSection::new(),
@@ -66,31 +130,170 @@ impl<'ctx, A: Abi<BackendType = wasm::ValType>> WasmEmitter<'ctx, A> {
     fn encode_types(&mut self, module: &Module<'ctx>) {
         module.for_all_types_iter(|ty| {
             if let Type::Func { args, ret } = &*ty {
-                self.type_sec.function(
-                    args.iter().map(|t| A::compile_type(*t)),
-                    ret.iter().map(|t| A::compile_type(*t)) 
-                );
+                let mut params = flat_val_types::<A>(module, args);
+                if returns_indirectly::<A>(module, ret) {
+                    // The pointer to the result is the last argument, there are no results
+                    params.push(A::compile_type(module.ptr_t()));
+                    self.type_sec.function(params, []);
+                } else {
+                    self.type_sec.function(params, flat_val_types::<A>(module, ret));
+                }
                 // The function type is the last one
                 let idx = self.type_sec.len() - 1;
                 self.function_types.insert(ty, idx);
             }
-        })
+        });
+
+        // Blocks always leave their results on the stack, so the types of blocks
+        // returning a large struct differ from the function types
+        let mut block_tys = Vec::new();
+        for function in module.functions_iter().filter(|f| !f.is_extern()).map(|f| f.unwrap_local()) {
+            for block in function.blocks_iter() {
+                block_tys.push(block.full_type());
+                for instr in &block.body {
+                    if let InstrK::Switch { cases: _, default } = &instr.kind {
+                        // the type of the wasm blocks entering the cases (interned by the Verifier)
+                        let params = function.get_block(*default).unwrap().params().clone();
+                        if params.is_empty() { continue }
+                        block_tys.push(module.intern_type(Type::Func { args: params.clone(), ret: params }));
+                    }
+                }
+            }
+        }
+        for ty in block_tys {
+            if let Type::Func { args, ret } = &*ty {
+                if returns_indirectly::<A>(module, ret) && !self.block_types.contains_key(&ty) {
+                    self.type_sec.function(flat_val_types::<A>(module, args), flat_val_types::<A>(module, ret));
+                    self.block_types.insert(ty, self.type_sec.len() - 1);
+                }
+            }
+        }
     }
 
     fn compile_func(&mut self, module: &Module<'ctx>, func: &Function<'ctx>) {
         // First actually compile the function
         // the locals passed to wasm::Function are only additional locals, WITHOUT the arguments
 
+        // Struct locals are flattened into several wasm locals,
+        // the hidden return pointer is the last argument
+        let (arg_tys, local_tys) = func.all_locals_ty().split_at(func.arg_count());
+        let mut wasm_local_count = 0;
+        self.local_indices.clear();
+        for ty in arg_tys {
+            let len = flatten_type::<A>(module, *ty).len() as u32;
+            self.local_indices.push(wasm_local_count..(wasm_local_count + len));
+            wasm_local_count += len;
+        }
+        self.return_pointer = None;
+        if returns_indirectly::<A>(module, func.ret_tys()) {
+            self.return_pointer = Some(wasm_local_count);
+            wasm_local_count += 1;
+        }
+        for ty in local_tys {
+            let len = flatten_type::<A>(module, *ty).len() as u32;
+            self.local_indices.push(wasm_local_count..(wasm_local_count + len));
+            wasm_local_count += len;
+        }
+
+        let scratch_local_count = func.blocks_iter()
+            .flat_map(|b| b.body.iter())
+            .map(|i| match &i.kind {
+                InstrK::Switch { cases: _, default: _ } => 1,
+                InstrK::MemCopy | InstrK::MemFill if !module.conf.enable_bulk_memory => 3,
+                // the pointer to a struct value
+                InstrK::Read { ty } | InstrK::Write { ty } if ty.is_struct() => 1,
+                // the function pointer, the return pointer is pushed below it
+                InstrK::CallIndirect | InstrK::TailCallIndirect
+                    if returns_indirectly::<A>(module, &func_ty_rets(i.meta.retrieve_ty(key!("ty")).unwrap())) => 1,
+                _ => 0
+            })
+            .max()
+            .unwrap_or(0);
+        self.scratch_local = wasm_local_count;
+
+        // The values of the stack manipulation instructions are stored in scratch locals,
+        // two for every type (the types were inserted by the Verifier).
+        // Struct values written to memory need one for every scalar
+        let mut stack_scratch_counts: BTreeMap<wasm::ValType, u32> = BTreeMap::new();
+        let mut need_scratch = |ty: Ty<'ctx>, count: u32| {
+            let c = stack_scratch_counts.entry(A::compile_type(ty)).or_insert(0);
+            *c = (*c).max(count);
+        };
+        for instr in func.blocks_iter().flat_map(|b| b.body.iter()) {
+            match &instr.kind {
+                InstrK::Dup | InstrK::Swap | InstrK::Over => {
+                    [instr.meta.retrieve_ty(key!("ty")), instr.meta.retrieve_ty(key!("ty2"))]
+                        .iter()
+                        .flatten()
+                        .for_each(|ty| need_scratch(*ty, 2));
+                }
+                InstrK::Write { ty } if ty.is_struct() => {
+                    struct_scratch_counts::<A>(module, *ty).into_iter().for_each(|(ty, n)| need_scratch(ty, n));
+                }
+                _ => {}
+            }
+        }
+        if self.return_pointer.is_some() {
+            struct_scratch_counts::<A>(module, func.ret_tys()[0]).into_iter().for_each(|(ty, n)| need_scratch(ty, n));
+        }
+        let mut next_stack_scratch_local = self.scratch_local + scratch_local_count;
+        self.stack_scratch_locals = stack_scratch_counts.iter()
+            .map(|(t, n)| {
+                next_stack_scratch_local += n;
+                (*t, next_stack_scratch_local - n)
+            })
+            .collect();
+
+        // Every StackAlloc and every call returning a large struct gets its own place in the frame,
+        // the blocks are sorted so the layout doesn't depend on the order of the HashMap
+        let mut blocks: Vec<&InstrBlock<'ctx>> = func.blocks_iter().collect();
+        blocks.sort_by_key(|b| b.idx);
+        let mut frame_offsets = HashMap::new();
+        let mut frame_size: usize = 0;
+        for block in blocks {
+            for (i, instr) in block.body.iter().enumerate() {
+                if let Some(ty) = frame_slot_type::<A>(module, instr) {
+                    let layout = module.layout_of::<A>(ty);
+                    let offset = frame_size.next_multiple_of(1 << layout.alignment);
+                    frame_offsets.insert((block.idx, i), offset as u32);
+                    frame_size = offset + layout.size;
+                }
+            }
+        }
+        // The frame pointer is the last local
+        self.frame = (!frame_offsets.is_empty()).then(|| StackFrame {
+            size: frame_size.next_multiple_of(16) as u32,
+            pointer: next_stack_scratch_local,
+            offsets: frame_offsets
+        });
+
         let mut out_f = wasm::Function::new_with_locals_types(
-            func.all_locals_ty().iter()
-                .skip(func.arg_count())
-                .map(|t| A::compile_type(*t)));
+            flat_val_types::<A>(module, local_tys).into_iter()
+                .chain(std::iter::repeat_n(wasm::ValType::I32, scratch_local_count as usize))
+                .chain(stack_scratch_counts.iter().flat_map(|(t, n)| std::iter::repeat_n(*t, *n as usize)))
+                .chain(self.frame.as_ref().map(|_| wasm::ValType::I32)));
 
+        // Exceptions thrown through the function must also pop its frame,
+        // so the main block is wrapped in (try <main> catch_all <teardown> rethrow)
+        let catch_exceptions = self.frame.is_some() && module.conf.enable_exceptions;
+        self.emit_frame_setup(&mut out_f);
+        if catch_exceptions {
+            out_f.instruction(&wasm::Instruction::Try(self.block_type(module, func.entry_block())));
+        }
         self.compile_block(
             module, 
             func, 
             func.entry_block(), 
             &mut out_f);
+        if catch_exceptions {
+            out_f.instruction(&wasm::Instruction::CatchAll);
+            self.emit_frame_teardown(&mut out_f);
+            out_f.instruction(&wasm::Instruction::Rethrow(0));
+            out_f.instruction(&wasm::Instruction::End);
+        }
+        // The end of the function returns the values left by the main block
+        self.emit_indirect_return(module, func, &mut out_f);
+        self.emit_frame_teardown(&mut out_f);
         out_f.instruction(&wasm::Instruction::End);
 
         // Then add to the sections
@@ -103,57 +306,260 @@ impl<'ctx, A: Abi<BackendType = wasm::ValType>> WasmEmitter<'ctx, A> {
         self.code_sec.function(&out_f);
     }
 
+    /// The scratch locals for the two values used by `Swap` and `Over`,
+    /// the first one for the value below the top of the stack
+    fn stack_scratch_pair(&self, below: Ty<'ctx>, top: Ty<'ctx>) -> (u32, u32) {
+        let below_local = self.stack_scratch_locals[&A::compile_type(below)];
+        let top_local = self.stack_scratch_locals[&A::compile_type(top)];
+        if below_local == top_local {
+            // Both values have the same type, use both locals of the type
+            (below_local, top_local + 1)
+        } else {
+            (below_local, top_local)
+        }
+    }
+
+    /// The wasm type of a block
+    fn block_type(&self, module: &Module<'ctx>, block: &InstrBlock<'ctx>) -> wasm::BlockType {
+        if !block.params().is_empty() {
+            // Blocks with parameters always need a function type
+            return wasm::BlockType::FunctionType(self.block_type_index(block.full_type()))
+        }
+        match flat_val_types::<A>(module, block.returns()).as_slice() {
+            [] => wasm::BlockType::Empty,
+            // If the block returns only a single value, prefer
+            // to compile it as returning that one value
+            // rather than a function type
+            [result] => wasm::BlockType::Result(*result),
+            _ => wasm::BlockType::FunctionType(self.block_type_index(block.full_type()))
+        }
+    }
+
+    /// The wasm index of a global of the module, which is shifted by the imported stack pointer
+    fn global_index(&self, module: &Module<'ctx>, name: &str) -> u32 {
+        module.get_global(name).unwrap().idx() as u32 + self.stack_pointer_imported as u32
+    }
+
+    /// The index of the wasm type of a block with the (function) type `ty`
+    fn block_type_index(&self, ty: Ty<'ctx>) -> u32 {
+        self.block_types.get(&ty).copied().unwrap_or_else(|| self.function_types[&ty])
+    }
+
+    /// Pop a struct value off the stack into scratch locals,
+    /// return the locals of the scalars
+    fn emit_struct_spill(&self, module: &Module<'ctx>, ty: Ty<'ctx>, out_f: &mut wasm::Function) -> Vec<u32> {
+        // The Nth scalar of a type uses the Nth scratch local of the type
+        let mut used: HashMap<wasm::ValType, u32> = HashMap::new();
+        let locals: Vec<u32> = flatten_type::<A>(module, ty).iter()
+            .map(|(scalar, _)| {
+                let val_ty = A::compile_type(*scalar);
+                let n = used.entry(val_ty).or_insert(0);
+                *n += 1;
+                self.stack_scratch_locals[&val_ty] + *n - 1
+            })
+            .collect();
+        // the last scalar is on the top of the stack
+        for local in locals.iter().rev() {
+            out_f.instruction(&wasm::Instruction::LocalSet(*local));
+        }
+        locals
+    }
+
+    /// Write a struct value spilled by [`Self::emit_struct_spill`] to memory,
+    /// `ptr` is the instruction which pushes the address
+    fn emit_struct_store(&self, module: &Module<'ctx>, ty: Ty<'ctx>, locals: &[u32], ptr: &wasm::Instruction, out_f: &mut wasm::Function) {
+        for ((scalar, offset), local) in flatten_type::<A>(module, ty).into_iter().zip(locals) {
+            out_f.instruction(ptr);
+            out_f.instruction(&wasm::Instruction::LocalGet(*local));
+            out_f.instruction(&scalar_store::<A>(module, scalar, offset as u64));
+        }
+    }
+
+    /// Read a struct value from memory scalar by scalar,
+    /// `ptr` is the instruction which pushes the address, the struct starts `base_offset` bytes after it
+    fn emit_struct_load(&self, module: &Module<'ctx>, ty: Ty<'ctx>, ptr: &wasm::Instruction, base_offset: u32, out_f: &mut wasm::Function) {
+        for (scalar, offset) in flatten_type::<A>(module, ty) {
+            out_f.instruction(ptr);
+            out_f.instruction(&scalar_load::<A>(module, scalar, (base_offset as usize + offset) as u64));
+        }
+    }
+
+    /// Push the address of the place in the frame of the instruction at index `i` of the block
+    fn emit_frame_slot_address(&self, block: BlockId, i: usize, out_f: &mut wasm::Function) {
+        let frame = self.frame.as_ref().unwrap();
+        out_f.instruction(&wasm::Instruction::LocalGet(frame.pointer));
+        let offset = frame.offsets[&(block, i)];
+        if offset != 0 {
+            out_f.instruction(&wasm::Instruction::I32Const(offset as i32));
+            out_f.instruction(&wasm::Instruction::I32Add);
+        }
+    }
+
+    /// Read the struct result of the call at index `i` of the block from its place in the frame
+    fn emit_frame_slot_load(&self, module: &Module<'ctx>, ty: Ty<'ctx>, block: BlockId, i: usize, out_f: &mut wasm::Function) {
+        let frame = self.frame.as_ref().unwrap();
+        let offset = frame.offsets[&(block, i)];
+        self.emit_struct_load(module, ty, &wasm::Instruction::LocalGet(frame.pointer), offset, out_f);
+    }
+
+    /// Push the frame of the function being compiled on the shadow stack
+    fn emit_frame_setup(&self, out_f: &mut wasm::Function) {
+        if let Some(frame) = &self.frame {
+            let stack_pointer = self.stack_pointer.unwrap();
+            out_f.instruction(&wasm::Instruction::GlobalGet(stack_pointer));
+            out_f.instruction(&wasm::Instruction::I32Const(frame.size as i32));
+            out_f.instruction(&wasm::Instruction::I32Sub);
+            out_f.instruction(&wasm::Instruction::LocalTee(frame.pointer));
+            out_f.instruction(&wasm::Instruction::GlobalSet(stack_pointer));
+        }
+    }
+
+    /// Pop the frame of the function being compiled off the shadow stack,
+    /// this must be done on every path which exits the function
+    fn emit_frame_teardown(&self, out_f: &mut wasm::Function) {
+        if let Some(frame) = &self.frame {
+            out_f.instruction(&wasm::Instruction::LocalGet(frame.pointer));
+            out_f.instruction(&wasm::Instruction::I32Const(frame.size as i32));
+            out_f.instruction(&wasm::Instruction::I32Add);
+            out_f.instruction(&wasm::Instruction::GlobalSet(self.stack_pointer.unwrap()));
+        }
+    }
+
+    /// If the function returns a large struct, write the struct on the top of the stack
+    /// to where the return pointer points
+    fn emit_indirect_return(&self, module: &Module<'ctx>, function: &Function<'ctx>, out_f: &mut wasm::Function) {
+        if let Some(return_pointer) = self.return_pointer {
+            let ty = function.ret_tys()[0];
+            let locals = self.emit_struct_spill(module, ty, out_f);
+            self.emit_struct_store(module, ty, &locals, &wasm::Instruction::LocalGet(return_pointer), out_f);
+        }
+    }
+
     fn compile_block(
         &mut self, 
         module: &Module<'ctx>, 
         function: &Function<'ctx>, 
         block: &InstrBlock<'ctx>, 
         out_f: &mut wasm::Function) {
-        for instr in &block.body {
+        for (i, instr) in block.body.iter().enumerate() {
             match &instr.kind {
-                InstrK::LdInt(val, _) => { out_f.instruction(&wasm::Instruction::I32Const(*val as i32)); },
+                InstrK::LdInt(val, ty) => if ty.is_int64() {
+                    out_f.instruction(&wasm::Instruction::I64Const(*val as i64));
+                } else {
+                    out_f.instruction(&wasm::Instruction::I32Const(*val as i32));
+                },
                 InstrK::LdFloat(val) => { out_f.instruction(&wasm::Instruction::F32Const(*val)); },
-                InstrK::FAdd => { out_f.instruction(&wasm::Instruction::F32Add); },
-                InstrK::FSub => { out_f.instruction(&wasm::Instruction::F32Sub); },
-                InstrK::FMul => { out_f.instruction(&wasm::Instruction::F32Mul); },
-                InstrK::FDiv => { out_f.instruction(&wasm::Instruction::F32Div); },
+                InstrK::LdFloat64(val) => { out_f.instruction(&wasm::Instruction::F64Const(*val)); },
+                InstrK::FAdd | InstrK::FSub | InstrK::FMul | InstrK::FDiv | InstrK::FCmp(_) |
+                InstrK::FMin | InstrK::FMax | InstrK::FCopysign |
+                InstrK::FSqrt | InstrK::FAbs | InstrK::FNeg | InstrK::FFloor |
+                InstrK::FCeil | InstrK::FTrunc | InstrK::FNearest => {
+                    // meta["ty"] injected by the Verifier
+                    let is_f64 = matches!(&*instr.meta.retrieve_ty(key!("ty")).unwrap(), Type::Float64);
+                    out_f.instruction(&match (&instr.kind, is_f64) {
+                        (InstrK::FAdd, false) => wasm::Instruction::F32Add,
+                        (InstrK::FSub, false) => wasm::Instruction::F32Sub,
+                        (InstrK::FMul, false) => wasm::Instruction::F32Mul,
+                        (InstrK::FDiv, false) => wasm::Instruction::F32Div,
+                        (InstrK::FCmp(Cmp::Eq), false) => wasm::Instruction::F32Eq,
+                        (InstrK::FCmp(Cmp::Ne), false) => wasm::Instruction::F32Neq,
+                        (InstrK::FCmp(Cmp::Lt), false) => wasm::Instruction::F32Lt,
+                        (InstrK::FCmp(Cmp::Le), false) => wasm::Instruction::F32Le,
+                        (InstrK::FCmp(Cmp::Gt), false) => wasm::Instruction::F32Gt,
+                        (InstrK::FCmp(Cmp::Ge), false) => wasm::Instruction::F32Ge,
+                        (InstrK::FMin, false) => wasm::Instruction::F32Min,
+                        (InstrK::FMax, false) => wasm::Instruction::F32Max,
+                        (InstrK::FCopysign, false) => wasm::Instruction::F32Copysign,
+                        (InstrK::FSqrt, false) => wasm::Instruction::F32Sqrt,
+                        (InstrK::FAbs, false) => wasm::Instruction::F32Abs,
+                        (InstrK::FNeg, false) => wasm::Instruction::F32Neg,
+                        (InstrK::FFloor, false) => wasm::Instruction::F32Floor,
+                        (InstrK::FCeil, false) => wasm::Instruction::F32Ceil,
+                        (InstrK::FTrunc, false) => wasm::Instruction::F32Trunc,
+                        (InstrK::FNearest, false) => wasm::Instruction::F32Nearest,
+                        (InstrK::FAdd, true) => wasm::Instruction::F64Add,
+                        (InstrK::FSub, true) => wasm::Instruction::F64Sub,
+                        (InstrK::FMul, true) => wasm::Instruction::F64Mul,
+                        (InstrK::FDiv, true) => wasm::Instruction::F64Div,
+                        (InstrK::FCmp(Cmp::Eq), true) => wasm::Instruction::F64Eq,
+                        (InstrK::FCmp(Cmp::Ne), true) => wasm::Instruction::F64Neq,
+                        (InstrK::FCmp(Cmp::Lt), true) => wasm::Instruction::F64Lt,
+                        (InstrK::FCmp(Cmp::Le), true) => wasm::Instruction::F64Le,
+                        (InstrK::FCmp(Cmp::Gt), true) => wasm::Instruction::F64Gt,
+                        (InstrK::FCmp(Cmp::Ge), true) => wasm::Instruction::F64Ge,
+                        (InstrK::FMin, true) => wasm::Instruction::F64Min,
+                        (InstrK::FMax, true) => wasm::Instruction::F64Max,
+                        (InstrK::FCopysign, true) => wasm::Instruction::F64Copysign,
+                        (InstrK::FSqrt, true) => wasm::Instruction::F64Sqrt,
+                        (InstrK::FAbs, true) => wasm::Instruction::F64Abs,
+                        (InstrK::FNeg, true) => wasm::Instruction::F64Neg,
+                        (InstrK::FFloor, true) => wasm::Instruction::F64Floor,
+                        (InstrK::FCeil, true) => wasm::Instruction::F64Ceil,
+                        (InstrK::FTrunc, true) => wasm::Instruction::F64Trunc,
+                        (InstrK::FNearest, true) => wasm::Instruction::F64Nearest,
+                        _ => unreachable!()
+                    });
+                },
+                InstrK::VSplat(shape) => { out_f.instruction(&match shape {
+                    VecShape::I32x4 => wasm::Instruction::I32x4Splat,
+                    VecShape::F32x4 => wasm::Instruction::F32x4Splat,
+                }); }
+                InstrK::VExtractLane { shape, lane } => { out_f.instruction(&match shape {
+                    VecShape::I32x4 => wasm::Instruction::I32x4ExtractLane { lane: *lane },
+                    VecShape::F32x4 => wasm::Instruction::F32x4ExtractLane { lane: *lane },
+                }); }
+                InstrK::VReplaceLane { shape, lane } => { out_f.instruction(&match shape {
+                    VecShape::I32x4 => wasm::Instruction::I32x4ReplaceLane { lane: *lane },
+                    VecShape::F32x4 => wasm::Instruction::F32x4ReplaceLane { lane: *lane },
+                }); }
+                InstrK::VAdd(shape) => { out_f.instruction(&match shape {
+                    VecShape::I32x4 => wasm::Instruction::I32x4Add,
+                    VecShape::F32x4 => wasm::Instruction::F32x4Add,
+                }); }
+                InstrK::VSub(shape) => { out_f.instruction(&match shape {
+                    VecShape::I32x4 => wasm::Instruction::I32x4Sub,
+                    VecShape::F32x4 => wasm::Instruction::F32x4Sub,
+                }); }
+                InstrK::VMul(shape) => { out_f.instruction(&match shape {
+                    VecShape::I32x4 => wasm::Instruction::I32x4Mul,
+                    VecShape::F32x4 => wasm::Instruction::F32x4Mul,
+                }); }
+                InstrK::FPromote => { out_f.instruction(&wasm::Instruction::F64PromoteF32); },
+                InstrK::FDemote => { out_f.instruction(&wasm::Instruction::F32DemoteF64); },
                 // these are all numerics WITH metadata
-                InstrK::IAdd | InstrK::ISub | InstrK::IMul | InstrK::IDiv |
-                InstrK::Itof | InstrK::ICmp(_) | InstrK::IConv { target: _ } => {
+                InstrK::IAdd | InstrK::ISub | InstrK::IMul | InstrK::IDiv | InstrK::IRem | InstrK::INeg |
+                InstrK::Itof { float_ty: _ } | InstrK::ICmp(_) | InstrK::IConv { target: _ } |
+                InstrK::Not | InstrK::BitAnd | InstrK::BitOr | InstrK::BitXor | InstrK::BitNot |
+                InstrK::Shl | InstrK::Shr => {
                     let bws = instr.meta.retrieve_copied(key!("bws")).unwrap();
-                    let instrs = emit_numeric_instr::<A>(&instr.kind, bws, module.conf.use_saturating_ftoi);
+                    let instrs = emit_numeric_instr::<A>(instr, bws, module.conf.use_saturating_ftoi);
                     for i in instrs { out_f.instruction(&i); }
                 },
                 InstrK::Ftoi { int_ty } => { 
                     // numeric without metadata, calculate bws from the explicit type
                     let bws = type_to_bws(*int_ty).unwrap();
-                    let instrs = emit_numeric_instr::<A>(&instr.kind, bws, module.conf.use_saturating_ftoi);
+                    let instrs = emit_numeric_instr::<A>(instr, bws, module.conf.use_saturating_ftoi);
                     for i in instrs { out_f.instruction(&i); }
                 },
-                InstrK::FCmp(cmp) => { match cmp {
-                    Cmp::Eq => out_f.instruction(&wasm::Instruction::F32Eq),
-                    Cmp::Ne => out_f.instruction(&wasm::Instruction::F32Neq),
-                    Cmp::Lt => out_f.instruction(&wasm::Instruction::F32Lt),
-                    Cmp::Le => out_f.instruction(&wasm::Instruction::F32Le),
-                    Cmp::Gt => out_f.instruction(&wasm::Instruction::F32Gt),
-                    Cmp::Ge => out_f.instruction(&wasm::Instruction::F32Ge),
-                }; }
-                InstrK::Not => {
-                    // !x is the same as (x == 0)
-                    out_f.instruction(&wasm::Instruction::I32Eqz);
-                }
-                InstrK::BitAnd => {
-                    out_f.instruction(&wasm::Instruction::I32And);
-                }
-                InstrK::BitOr => {
-                    out_f.instruction(&wasm::Instruction::I32Or);
-                }
+                // A large struct result is written to the place of the call in the caller's frame
                 InstrK::CallDirect { func_name } => {
-                    let func_idx = module.get_function(func_name).unwrap().idx();
-                    out_f.instruction(&wasm::Instruction::Call(func_idx.try_into().unwrap()));
+                    let callee = module.get_function(func_name).unwrap();
+                    let indirect = returns_indirectly::<A>(module, callee.ret_tys());
+                    if indirect {
+                        self.emit_frame_slot_address(block.idx, i, out_f);
+                    }
+                    out_f.instruction(&wasm::Instruction::Call(callee.idx().try_into().unwrap()));
+                    if indirect {
+                        self.emit_frame_slot_load(module, callee.ret_tys()[0], block.idx, i, out_f);
+                    }
+                },
+                // struct locals are flattened into several wasm locals
+                InstrK::LdLocal { idx } => for local in self.local_indices[*idx].clone() {
+                    out_f.instruction(&wasm::Instruction::LocalGet(local));
+                },
+                InstrK::StLocal { idx } => for local in self.local_indices[*idx].clone().rev() {
+                    out_f.instruction(&wasm::Instruction::LocalSet(local));
                 },
-                InstrK::LdLocal { idx } => { out_f.instruction(&wasm::Instruction::LocalGet(*idx as u32)); },
-                InstrK::StLocal { idx } => { out_f.instruction(&wasm::Instruction::LocalSet(*idx as u32)); },
                 InstrK::LdGlobalFunc { func_name } => {
                     let func_idx = module.get_function(func_name).unwrap().idx();
                     // the index must be shifted by one - see the description of [`emit_global_function_table`]
@@ -162,10 +568,45 @@ impl<'ctx, A: Abi<BackendType = wasm::ValType>> WasmEmitter<'ctx, A> {
                 InstrK::CallIndirect => {
                     // meta["ty"] injected by the Verifier
                     let function_ty = instr.meta.retrieve_ty(key!("ty")).unwrap();
+                    let indirect = returns_indirectly::<A>(module, &func_ty_rets(function_ty));
+                    if indirect {
+                        // the return pointer goes below the function pointer
+                        out_f.instruction(&wasm::Instruction::LocalSet(self.scratch_local));
+                        self.emit_frame_slot_address(block.idx, i, out_f);
+                        out_f.instruction(&wasm::Instruction::LocalGet(self.scratch_local));
+                    }
                     out_f.instruction(&wasm::Instruction::CallIndirect {
                         ty: self.function_types[&function_ty],
                         table: 0 // the GFT is the only one and it's at index zero
                     });
+                    if indirect {
+                        self.emit_frame_slot_load(module, func_ty_rets(function_ty)[0], block.idx, i, out_f);
+                    }
+                },
+                // The tail call proposal isn't supported by wasm_encoder yet,
+                // the instructions are encoded by hand.
+                // A callee returning a large struct writes it where the caller's result goes
+                InstrK::TailCallDirect { func_name } => {
+                    let func_idx = module.get_function(func_name).unwrap().idx();
+                    if let Some(return_pointer) = self.return_pointer {
+                        out_f.instruction(&wasm::Instruction::LocalGet(return_pointer));
+                    }
+                    self.emit_frame_teardown(out_f);
+                    out_f.raw([RETURN_CALL_OPCODE]);
+                    out_f.raw(wasm::encoders::u32(func_idx.try_into().unwrap()));
+                },
+                InstrK::TailCallIndirect => {
+                    // meta["ty"] injected by the Verifier
+                    let function_ty = instr.meta.retrieve_ty(key!("ty")).unwrap();
+                    if let Some(return_pointer) = self.return_pointer {
+                        out_f.instruction(&wasm::Instruction::LocalSet(self.scratch_local));
+                        out_f.instruction(&wasm::Instruction::LocalGet(return_pointer));
+                        out_f.instruction(&wasm::Instruction::LocalGet(self.scratch_local));
+                    }
+                    self.emit_frame_teardown(out_f);
+                    out_f.raw([RETURN_CALL_INDIRECT_OPCODE]);
+                    out_f.raw(wasm::encoders::u32(self.function_types[&function_ty]));
+                    out_f.raw(wasm::encoders::u32(0)); // the GFT
                 },
                 InstrK::Bitcast { target } => {
                     // meta["from"] injected by the Verifier
@@ -173,7 +614,15 @@ impl<'ctx, A: Abi<BackendType = wasm::ValType>> WasmEmitter<'ctx, A> {
                     let from_wasm = A::compile_type(from);
                     let to_wasm = A::compile_type(*target);
                     match (from_wasm, to_wasm) {
-                        (wasm::ValType::I32, wasm::ValType::I32) | (wasm::ValType::F32, wasm::ValType::F32) => { /* no-op */ }
+                        (wasm::ValType::I32, wasm::ValType::I32) | (wasm::ValType::F32, wasm::ValType::F32) |
+                        (wasm::ValType::I64, wasm::ValType::I64) | (wasm::ValType::F64, wasm::ValType::F64) |
+                        (wasm::ValType::V128, wasm::ValType::V128) => { /* no-op */ }
+                        (wasm::ValType::I64, wasm::ValType::F64) => {
+                            out_f.instruction(&wasm::Instruction::F64ReinterpretI64);
+                        }
+                        (wasm::ValType::F64, wasm::ValType::I64) => {
+                            out_f.instruction(&wasm::Instruction::I64ReinterpretF64);
+                        }
                         (wasm::ValType::I32, wasm::ValType::F32) => {
                             out_f.instruction(&wasm::Instruction::F32ReinterpretI32);
                         }
@@ -186,18 +635,7 @@ impl<'ctx, A: Abi<BackendType = wasm::ValType>> WasmEmitter<'ctx, A> {
                 }
                 InstrK::IfElse { then, r#else } => {
                     let block = function.get_block(*then).unwrap();
-                    let block_type = 
-                        if block.returns().is_empty() {
-                            wasm::BlockType::Empty
-                        } else if block.returns().len() == 1 {
-                            // If the block returns only a single value, prefer
-                            // to compile it as returning that one value
-                            // rather than a function type
-                            wasm::BlockType::Result(A::compile_type(block.returns()[0]))
-                        } else {
-                            wasm::BlockType::FunctionType(self.function_types[&block.full_type()])
-                        };
-                    out_f.instruction(&wasm::Instruction::If(block_type));
+                    out_f.instruction(&wasm::Instruction::If(self.block_type(module, block)));
                     // compile the `then` block
                     // according to wasm spec, it doesn't need the end instruction
                     self.compile_block(module, function, block, out_f);
@@ -210,89 +648,126 @@ impl<'ctx, A: Abi<BackendType = wasm::ValType>> WasmEmitter<'ctx, A> {
                     }
                     out_f.instruction(&wasm::Instruction::End);
                 }
+                InstrK::Switch { cases, default } => {
+                    // The index is saved in the scratch local and loaded inside the blocks,
+                    // the parameters of the cases are passed through them
+                    out_f.instruction(&wasm::Instruction::LocalSet(self.scratch_local));
+                    // We emit
+                    // (block $end (block $default (block $case_K-1 ... (block $case_0
+                    //        (br_table $case_0 ... $case_K-1 $default (local.get $scratch)))
+                    //      <case 0> (br $end))
+                    //    ...
+                    //    <case K-1> (br $end))
+                    //  <default>)
+                    // The ControlFlowVerifier relies on this nesting for the innermost loop distances
+                    let default_block = function.get_block(*default).unwrap();
+                    out_f.instruction(&wasm::Instruction::Block(self.block_type(module, default_block)));
+                    let case_entry_type = if default_block.params().is_empty() {
+                        wasm::BlockType::Empty
+                    } else {
+                        // The type was interned by the Verifier
+                        let params = default_block.params().clone();
+                        let ty = module.intern_type(Type::Func { args: params.clone(), ret: params });
+                        wasm::BlockType::FunctionType(self.block_type_index(ty))
+                    };
+                    for _ in 0..=cases.len() {
+                        out_f.instruction(&wasm::Instruction::Block(case_entry_type));
+                    }
+                    out_f.instruction(&wasm::Instruction::LocalGet(self.scratch_local));
+                    let targets: Vec<u32> = (0..cases.len() as u32).collect();
+                    out_f.instruction(&wasm::Instruction::BrTable(Cow::Owned(targets), cases.len() as u32));
+                    for (n, case) in cases.iter().enumerate() {
+                        out_f.instruction(&wasm::Instruction::End);
+                        self.compile_block(module, function, function.get_block(*case).unwrap(), out_f);
+                        out_f.instruction(&wasm::Instruction::Br((cases.len() - n) as u32));
+                    }
+                    out_f.instruction(&wasm::Instruction::End);
+                    self.compile_block(module, function, default_block, out_f);
+                    out_f.instruction(&wasm::Instruction::End);
+                }
+                InstrK::TryCatch { body, catches, catch_all } => {
+                    // We emit (try <body> catch $tag <handler> ... catch_all <handler>)
+                    // the handlers start with the payload on the stack just like in the IR
+                    let body_block = function.get_block(*body).unwrap();
+                    out_f.instruction(&wasm::Instruction::Try(self.block_type(module, body_block)));
+                    self.compile_block(module, function, body_block, out_f);
+                    for (tag, handler) in catches {
+                        let tag_idx = module.get_exception_tag(tag).unwrap().idx();
+                        out_f.instruction(&wasm::Instruction::Catch(tag_idx as u32));
+                        self.compile_block(module, function, function.get_block(*handler).unwrap(), out_f);
+                    }
+                    if let Some(handler) = catch_all {
+                        out_f.instruction(&wasm::Instruction::CatchAll);
+                        self.compile_block(module, function, function.get_block(*handler).unwrap(), out_f);
+                    }
+                    out_f.instruction(&wasm::Instruction::End);
+                }
+                InstrK::Throw { tag } => {
+                    let tag_idx = module.get_exception_tag(tag).unwrap().idx();
+                    out_f.instruction(&wasm::Instruction::Throw(tag_idx as u32));
+                }
+                InstrK::Read { ty } if ty.is_struct() => {
+                    // the scalars are read one by one from the same pointer
+                    out_f.instruction(&wasm::Instruction::LocalSet(self.scratch_local));
+                    self.emit_struct_load(module, *ty, &wasm::Instruction::LocalGet(self.scratch_local), 0, out_f);
+                }
+                InstrK::Write { ty } if ty.is_struct() => {
+                    // the struct value is on top of the pointer
+                    let locals = self.emit_struct_spill(module, *ty, out_f);
+                    out_f.instruction(&wasm::Instruction::LocalSet(self.scratch_local));
+                    self.emit_struct_store(module, *ty, &locals, &wasm::Instruction::LocalGet(self.scratch_local), out_f);
+                }
                 InstrK::Read { ty } => {
                     if ty.is_int() {
                         // use the "numeric" module functions for compilation
                         let bws = type_to_bws(*ty).unwrap();
-                        let instrs = emit_numeric_instr::<A>(&instr.kind, bws, module.conf.use_saturating_ftoi);
+                        let instrs = emit_numeric_instr::<A>(instr, bws, module.conf.use_saturating_ftoi);
                         for i in instrs { out_f.instruction(&i); }
                         continue
                     }
 
-                    let mem_arg = wasm::MemArg {
-                        offset: 0,
-                        align: A::type_alignment(*ty) as u32,
-                        memory_index: 0,
-                    };
-
-                    match A::compile_type(*ty) {
-                        wasm::ValType::F32 => {
-                            out_f.instruction(&wasm::Instruction::F32Load(mem_arg));
-                        },
-                        _ => unimplemented!()
-                    }
+                    // floats, vectors, pointers and function pointers
+                    out_f.instruction(&scalar_load::<A>(module, *ty, 0));
                 }
                 InstrK::Write { ty } => {
                     if ty.is_int() {
                         // use the "numeric" module functions for compilation
                         let bws = type_to_bws(*ty).unwrap();
-                        let instrs = emit_numeric_instr::<A>(&instr.kind, bws, module.conf.use_saturating_ftoi);
+                        let instrs = emit_numeric_instr::<A>(instr, bws, module.conf.use_saturating_ftoi);
                         for i in instrs { out_f.instruction(&i); }
                         continue
                     }
 
-                    let mem_arg = wasm::MemArg {
-                        offset: 0,
-                        align: A::type_alignment(*ty) as u32,
-                        memory_index: 0,
-                    };
-
-                    match A::compile_type(*ty) {
-                        wasm::ValType::F32 => {
-                            out_f.instruction(&wasm::Instruction::F32Store(mem_arg));
-                        },
-                        _ => unimplemented!()
-                    }
+                    out_f.instruction(&scalar_store::<A>(module, *ty, 0));
                 }
                 InstrK::Offset { ty } => {
                     // we need to calculate stack(0) * sizeof(ty) + stack(1)
-                    // the sequence we'll use is:
-                    // LdInt sizeof(ty)
-                    // IMul
-                    // IAdd
-                    // but because the sizes are often powers of two, for optimization
-                    // purposes we'll replace the multiplications with left-shifts:
-                    match A::type_sizeof(*ty) {
-                        1 => {}, // no multiplication
-                        2 => {
-                            out_f.instruction(&wasm::Instruction::I32Const(1));
-                            out_f.instruction(&wasm::Instruction::I32Shl);
-                        }
-                        4 => {
-                            out_f.instruction(&wasm::Instruction::I32Const(2));
-                            out_f.instruction(&wasm::Instruction::I32Shl);
-                        }
-                        8 => {
-                            out_f.instruction(&wasm::Instruction::I32Const(3));
-                            out_f.instruction(&wasm::Instruction::I32Shl);
-                        }
-                        other => {
-                            out_f.instruction(&wasm::Instruction::I32Const(other as i32));
-                            out_f.instruction(&wasm::Instruction::I32Mul);
-                        }
+                    emit_scaled_add(module.sizeof::<A>(*ty), out_f);
+                }
+                InstrK::OffsetConst { ty, n } => {
+                    // The offset is folded into a constant at compile-time,
+                    // the address arithmetic wraps the same way as with Offset
+                    let offset = (*n as u32).wrapping_mul(module.sizeof::<A>(*ty) as u32);
+                    // opt: an offset of zero doesn't change the pointer
+                    if offset != 0 {
+                        out_f.instruction(&wasm::Instruction::I32Const(offset as i32));
+                        out_f.instruction(&wasm::Instruction::I32Add);
                     }
-                    // finally the `IAdd`
-                    out_f.instruction(&wasm::Instruction::I32Add);
                 }
+                InstrK::GetElemPtr { array_ty } => {
+                    // same as Offset, but the elements are `stride` bytes apart
+                    let elem = match &**array_ty {
+                        Type::Array { elem, len: _ } => *elem,
+                        _ => unreachable!()
+                    };
+                    emit_scaled_add(module.layout_of::<A>(elem).stride(), out_f);
+                }
+                InstrK::StackAlloc { ty: _ } => self.emit_frame_slot_address(block.idx, i, out_f),
                 InstrK::GetFieldPtr { struct_ty, field_idx } => {
                     // The `GetFieldPtr` instruction is basically
                     // just an addition with a correct offset
                     // Calculate the offset
-                    let struct_fields = match &**struct_ty {
-                        Type::Struct { fields } => fields,
-                        _ => unreachable!()
-                    };
-                    let field_offset = A::struct_field_offset(struct_fields, *field_idx);
+                    let field_offset = module.field_offset::<A>(*struct_ty, *field_idx);
                     // emit the addition
                     // opt: if the field_offset is zero, we don't need to emit I32Const(0) followed by IAdd
                     if field_offset != 0 {
@@ -300,30 +775,118 @@ impl<'ctx, A: Abi<BackendType = wasm::ValType>> WasmEmitter<'ctx, A> {
                         out_f.instruction(&wasm::Instruction::I32Add);
                     }
                 },
-                InstrK::Discard => { out_f.instruction(&wasm::Instruction::Drop); }
+                InstrK::GetTag { union_ty: _ } => {
+                    // the tag is an uint32 at the start of the union
+                    out_f.instruction(&wasm::Instruction::I32Load(wasm::MemArg {
+                        offset: 0,
+                        align: A::type_alignment(module.uint32t()) as u32,
+                        memory_index: 0
+                    }));
+                }
+                InstrK::SetTag { union_ty: _, variant_idx } => {
+                    out_f.instruction(&wasm::Instruction::I32Const(*variant_idx as i32));
+                    out_f.instruction(&wasm::Instruction::I32Store(wasm::MemArg {
+                        offset: 0,
+                        align: A::type_alignment(module.uint32t()) as u32,
+                        memory_index: 0
+                    }));
+                }
+                InstrK::GetVariantPtr { union_ty, variant_idx } => {
+                    let payload_offset = module.field_offset::<A>(*union_ty, *variant_idx);
+                    out_f.instruction(&wasm::Instruction::I32Const(payload_offset as i32));
+                    out_f.instruction(&wasm::Instruction::I32Add);
+                }
+                InstrK::Discard => {
+                    // meta["ty"] injected by the Verifier, a struct value is dropped scalar by scalar
+                    let count = instr.meta.retrieve_ty(key!("ty"))
+                        .map_or(1, |ty| flatten_type::<A>(module, ty).len());
+                    for _ in 0..count {
+                        out_f.instruction(&wasm::Instruction::Drop);
+                    }
+                }
+                InstrK::Dup => {
+                    let ty = instr.meta.retrieve_ty(key!("ty")).unwrap();
+                    let local = self.stack_scratch_locals[&A::compile_type(ty)];
+                    out_f.instruction(&wasm::Instruction::LocalTee(local));
+                    out_f.instruction(&wasm::Instruction::LocalGet(local));
+                }
+                InstrK::Swap => {
+                    let (below, top) = self.stack_scratch_pair(
+                        instr.meta.retrieve_ty(key!("ty")).unwrap(),
+                        instr.meta.retrieve_ty(key!("ty2")).unwrap());
+                    out_f.instruction(&wasm::Instruction::LocalSet(top));
+                    out_f.instruction(&wasm::Instruction::LocalSet(below));
+                    out_f.instruction(&wasm::Instruction::LocalGet(top));
+                    out_f.instruction(&wasm::Instruction::LocalGet(below));
+                }
+                InstrK::Over => {
+                    let (below, top) = self.stack_scratch_pair(
+                        instr.meta.retrieve_ty(key!("ty")).unwrap(),
+                        instr.meta.retrieve_ty(key!("ty2")).unwrap());
+                    out_f.instruction(&wasm::Instruction::LocalSet(top));
+                    out_f.instruction(&wasm::Instruction::LocalTee(below));
+                    out_f.instruction(&wasm::Instruction::LocalGet(top));
+                    out_f.instruction(&wasm::Instruction::LocalGet(below));
+                }
+                InstrK::Select => {
+                    // Reference types need the typed `select`
+                    let ty = instr.meta.retrieve_ty(key!("ty")).unwrap();
+                    if ty.is_extern_ref() {
+                        out_f.instruction(&wasm::Instruction::TypedSelect(wasm::ValType::ExternRef));
+                    } else {
+                        out_f.instruction(&wasm::Instruction::Select);
+                    }
+                }
                 InstrK::Return => { 
+                    self.emit_indirect_return(module, function, out_f);
+                    self.emit_frame_teardown(out_f);
                     out_f.instruction(&wasm::Instruction::Return);
                 }
                 InstrK::MemorySize => { out_f.instruction(&wasm::Instruction::MemorySize(0)); }
                 InstrK::MemoryGrow => { out_f.instruction(&wasm::Instruction::MemoryGrow(0)); }
+                InstrK::AtomicRead { ty: _ } | InstrK::AtomicWrite { ty: _ } | InstrK::AtomicRmw { op: _, ty: _ } |
+                InstrK::AtomicCmpxchg { ty: _ } | InstrK::AtomicWait { ty: _ } | InstrK::AtomicNotify => {
+                    emit_atomic_instr::<A>(module, instr, out_f);
+                }
+                InstrK::MemCopy => if module.conf.enable_bulk_memory {
+                    out_f.instruction(&wasm::Instruction::MemoryCopy { src: 0, dst: 0 });
+                } else {
+                    emit_mem_copy_loop(self.scratch_local, out_f);
+                }
+                InstrK::MemFill => if module.conf.enable_bulk_memory {
+                    out_f.instruction(&wasm::Instruction::MemoryFill(0));
+                } else {
+                    emit_mem_fill_loop(self.scratch_local, out_f);
+                }
                 InstrK::LdGlobal(name) => {
-                    out_f.instruction(&wasm::Instruction::GlobalGet(module.get_global(name).unwrap().idx() as u32));
+                    out_f.instruction(&wasm::Instruction::GlobalGet(self.global_index(module, name)));
                 }
                 InstrK::StGlobal(name) => {
-                    out_f.instruction(&wasm::Instruction::GlobalSet(module.get_global(name).unwrap().idx() as u32));
+                    out_f.instruction(&wasm::Instruction::GlobalSet(self.global_index(module, name)));
+                }
+                InstrK::LdNullRef => { out_f.instruction(&wasm::Instruction::RefNull(wasm::ValType::ExternRef)); }
+                InstrK::IsNullRef => { out_f.instruction(&wasm::Instruction::RefIsNull); }
+                InstrK::Fail => {
+                    // the module may still be used after the trap
+                    self.emit_frame_teardown(out_f);
+                    out_f.instruction(&wasm::Instruction::Unreachable);
                 }
-                InstrK::Fail => { out_f.instruction(&wasm::Instruction::Unreachable); }
                 InstrK::Loop(body) => {
-                    // The loop body's type is always () -> ()
-                    debug_assert!(function.get_block(*body).unwrap().returns().is_empty());
-                    let body_block_type = wasm::BlockType::Empty;
+                    let body_block = function.get_block(*body).unwrap();
                     // We emit (block (loop <body> br 0))
-                    out_f.instruction(&wasm::Instruction::Block(body_block_type));
-                    out_f.instruction(&wasm::Instruction::Loop(body_block_type));
-                    self.compile_block(module, function, function.get_block(*body).unwrap(), out_f);
+                    // The values produced by the loop are carried by the breaks
+                    // to the outer `block`, the `loop` itself never has any
+                    out_f.instruction(&wasm::Instruction::Block(self.block_type(module, body_block)));
+                    out_f.instruction(&wasm::Instruction::Loop(wasm::BlockType::Empty));
+                    self.compile_block(module, function, body_block, out_f);
                     // This `br 0` is what ensures the looping
                     out_f.instruction(&wasm::Instruction::Br(0));
                     out_f.instruction(&wasm::Instruction::End);
+                    if !body_block.returns().is_empty() {
+                        // The end of the `loop` is never reached, but wasm validation
+                        // needs to know that the values of the `block` don't come from there
+                        out_f.instruction(&wasm::Instruction::Unreachable);
+                    }
                     out_f.instruction(&wasm::Instruction::End);
                 }
                 InstrK::Break => {
@@ -332,24 +895,56 @@ impl<'ctx, A: Abi<BackendType = wasm::ValType>> WasmEmitter<'ctx, A> {
                     let ilp: usize = block.meta.retrieve_copied(key!("innermost_loop_distance")).unwrap();
                     out_f.instruction(&wasm::Instruction::Br((ilp + 1) as u32));
                 }
+                InstrK::BreakLoop(target) => {
+                    // The `block` wrapping the loop is one label further than the `loop`
+                    let depth = loop_label_depth(function, block, *target);
+                    out_f.instruction(&wasm::Instruction::Br((depth + 1) as u32));
+                }
+                InstrK::ContinueLoop(target) => {
+                    let depth = loop_label_depth(function, block, *target);
+                    out_f.instruction(&wasm::Instruction::Br(depth as u32));
+                }
                 InstrK::LdStaticMemPtr(item) => {
                     out_f.instruction(&wasm::Instruction::I32Const(
                         self.static_memory_addresses[item] as i32));
                 }
-                InstrK::Intrinsic(_i) => {
-                    // TODO: alter the ReadAtOffset and WriteAtOffset instruction to work with other integral types
-                    unimplemented!()
+                InstrK::Intrinsic(i) => match &i.0 {
+                    // the offset goes into the memory argument
+                    Intrinsics::ReadAtOffset { offset, ty } => {
+                        out_f.instruction(&scalar_load::<A>(module, *ty, *offset as u64));
+                    }
+                    Intrinsics::WriteAtOffset { offset, ty } => {
+                        out_f.instruction(&scalar_store::<A>(module, *ty, *offset as u64));
+                    }
                 }
             };
         }
     } 
 
-    fn emit_memory_section(&mut self, initial_memory_size: u32) {
-        self.memory_sec.memory(wasm::MemoryType {
-            minimum: initial_memory_size as u64,
-            maximum: None, // TODO
-            memory64: false,
-        });
+    fn emit_memory_section(&mut self, conf: &WasmModuleConf) -> Result<(), EmitError> {
+        if conf.shared_memory {
+            // The shared memory is imported, so that the instances on all the threads use the same one
+            let maximum = conf.maximum_memory_size.ok_or(EmitError::SharedMemoryWithoutMaximum)?;
+            self.shared_memory = Some((conf.initial_memory_size, maximum));
+        } else {
+            self.memory_sec.memory(wasm::MemoryType {
+                minimum: conf.initial_memory_size as u64,
+                maximum: conf.maximum_memory_size.map(|max| max as u64),
+                memory64: false,
+            });
+        }
+        Ok(())
+    }
+
+    /// With shared memory, the start function initializes the static memory,
+    /// it's the last function
+    fn emit_shared_memory_init(&mut self, module: &Module<'ctx>) {
+        if let Some((flag, data_len)) = self.shared_memory_init {
+            self.type_sec.function([], []);
+            self.func_sec.function(self.type_sec.len() - 1);
+            self.code_sec.function(&emit_shared_memory_init(flag, data_len));
+            self.start_function = Some(module.function_count() as u32);
+        }
     }
 
     /// The global function table is a table which contains funcrefs
@@ -379,10 +974,21 @@ impl<'ctx, A: Abi<BackendType = wasm::ValType>> WasmEmitter<'ctx, A> {
             wasm::Elements::Functions(&functions_indexes));
     }
 
+    fn emit_exception_tags(&mut self, module: &Module<'ctx>) {
+        for tag in module.exception_tags_iter() {
+            self.tag_sec.tag(wasm::TagType {
+                kind: wasm::TagKind::Exception,
+                func_type_idx: self.function_types[&tag.ty]
+            });
+        }
+    }
+
     fn emit_globals(&mut self, module: &Module<'ctx>) {
         for glob in module.globals_iter() {
             let init_expr = if glob.is_int() {
                 wasm::Instruction::I32Const(glob.get_int_value())
+            } else if glob.is_null_ref() {
+                wasm::Instruction::RefNull(wasm::ValType::ExternRef)
             } else {
                 wasm::Instruction::F32Const(glob.get_float_value())
             };
@@ -413,29 +1019,86 @@ impl<'ctx, A: Abi<BackendType = wasm::ValType>> WasmEmitter<'ctx, A> {
         }
     }
 
-    pub fn compile_static_memory(&mut self, module: &Module<'ctx>) {
+    pub fn compile_static_memory(&mut self, module: &Module<'ctx>) -> Result<(), EmitError> {
+        let mut static_len = 0;
         if let Some(mem) = module.get_static_memory() {
             let compiled_mem = CompiledStaticMemory::compile::<A>(module, mem);
-            self.data_sec.active(
-                0, 
-                &wasm::Instruction::I32Const(0), // no offset
-                compiled_mem.buf);
+            static_len = compiled_mem.buf.len();
+            if module.conf.shared_memory {
+                // the initialization flag follows the static memory
+                let flag = static_len.next_multiple_of(4) as u32;
+                self.shared_memory_init = Some((flag, static_len as u32));
+                self.data_sec.passive(compiled_mem.buf);
+            } else {
+                self.data_sec.active(
+                    0, 
+                    &wasm::Instruction::I32Const(0), // no offset
+                    compiled_mem.buf);
+            }
             // Assign the addresses
             self.static_memory_addresses = compiled_mem.addresses;
         }
+        // The shadow stack follows the static memory and grows down from its end,
+        // it's never at the null address
+        let uses_stack = module.functions_iter()
+            .filter(|f| !f.is_extern())
+            .flat_map(|f| f.unwrap_local().blocks_iter())
+            .flat_map(|b| b.body.iter())
+            .any(|i| frame_slot_type::<A>(module, i).is_some());
+        let stack_pointer_ty = wasm::GlobalType { val_type: wasm::ValType::I32, mutable: true };
+        if uses_stack && module.conf.shared_memory {
+            self.import_sec.import("env", Some("__stack_pointer"), wasm::EntityType::Global(stack_pointer_ty));
+            self.stack_pointer = Some(0);
+            self.stack_pointer_imported = true;
+        } else if uses_stack {
+            let stack_base = static_len.max(1).next_multiple_of(16) as u64;
+            // the whole stack must be in the initial memory
+            let stack_end = stack_base + module.conf.stack_size as u64;
+            let memory_size = module.conf.initial_memory_size as u64 * WASM_PAGE_SIZE;
+            if stack_end > memory_size {
+                return Err(EmitError::StackOutOfMemory { stack_end, memory_size })
+            }
+            self.global_sec.global(
+                stack_pointer_ty,
+                &wasm::Instruction::I32Const(stack_end as i32));
+            // it's after all the module's globals
+            self.stack_pointer = Some(self.global_sec.len() - 1);
+        }
+        Ok(())
     }
 
     pub fn finish(mut self) -> Vec<u8> {
         // Emit the sections in correct order
+        self.module.section(&self.type_sec);
+        match self.shared_memory {
+            Some((minimum, maximum)) => self.module.section(&wasm::RawSection {
+                id: wasm::SectionId::Import as u8,
+                data: &encode_import_section_with_shared_memory(&self.import_sec, minimum, maximum)
+            }),
+            None => self.module.section(&self.import_sec)
+        };
         self.module
-            .section(&self.type_sec)
-            .section(&self.import_sec)
             .section(&self.func_sec)
-            .section(&self.table_sec)
-            .section(&self.memory_sec)
+            .section(&self.table_sec);
+        if self.shared_memory.is_none() {
+            self.module.section(&self.memory_sec);
+        }
+        // Validators reject even an empty tag section without exception handling
+        if self.tag_sec.len() > 0 {
+            self.module.section(&self.tag_sec);
+        }
+        self.module
             .section(&self.global_sec)
-            .section(&self.export_sec)
-            .section(&self.elem_sec)
+            .section(&self.export_sec);
+        if let Some(function_index) = self.start_function {
+            self.module.section(&wasm::StartSection { function_index });
+        }
+        self.module.section(&self.elem_sec);
+        // `memory.init` and `data.drop` require the data count section
+        if self.shared_memory_init.is_some() {
+            self.module.section(&wasm::DataCountSection { count: self.data_sec.len() });
+        }
+        self.module
             .section(&self.code_sec)
             .section(&self.data_sec)
             .section(&self.name_sec);
@@ -444,16 +1107,18 @@ impl<'ctx, A: Abi<BackendType = wasm::ValType>> WasmEmitter<'ctx, A> {
 }
 
 impl<'ctx, A: Abi<BackendType = wasm::ValType>> FunctionPass<'ctx> for WasmEmitter<'ctx, A> {
-    type Error = (); // TODO some error
+    type Error = EmitError;
     type Output = ();
 
     fn visit_module(&mut self, module: &Module<'ctx>) -> Result<(), Self::Error> {
         // this must be done before visiting the functions
         self.encode_types(module);
+        // emit the exception tags, they use the function types
+        self.emit_exception_tags(module);
         // emit globals' definitions
         self.emit_globals(module);
         // compile the static memory - must happen after globals
-        self.compile_static_memory(module);
+        self.compile_static_memory(module)?;
         // emit external (i.e. imported) definitions
         self.emit_externs(module);
         Ok(())
@@ -469,8 +1134,280 @@ impl<'ctx, A: Abi<BackendType = wasm::ValType>> FunctionPass<'ctx> for WasmEmitt
     }
 
     fn end_module(&mut self, module: &Module<'ctx>) -> Result<(), Self::Error> {
-        self.emit_memory_section(module.conf.initial_memory_size);
+        self.emit_memory_section(&module.conf)?;
+        self.emit_shared_memory_init(module);
         self.emit_global_function_table(module);
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug)]
+pub enum EmitError {
+    SharedMemoryWithoutMaximum,
+    /// The static memory followed by the shadow stack, which ends at `stack_end`,
+    /// doesn't fit into the initial memory
+    StackOutOfMemory { stack_end: u64, memory_size: u64 },
+}
+
+/// Calculate the `br` depth of the `loop` label of the loop whose body is `target`
+/// from inside `block`, which the loop encloses.
+///
+/// Starts with the innermost loop distance of the block and while the innermost loop
+/// is not the target, steps out of the loop (its `loop` and `block` labels)
+/// and continues with the innermost loop distance of the block containing the loop.
+fn loop_label_depth(function: &Function<'_>, block: &InstrBlock<'_>, target: BlockId) -> usize {
+    // Both metadata injected by the ControlFlowVerifier
+    let mut depth: usize = block.meta.retrieve_copied(key!("innermost_loop_distance")).unwrap();
+    let mut innermost_loop: BlockId = block.meta.retrieve_copied(key!("innermost_loop")).unwrap();
+    while innermost_loop != target {
+        let loop_block = function.get_block(innermost_loop).unwrap();
+        let parent: BlockId = loop_block.meta.retrieve_copied(key!("parent")).unwrap();
+        let parent = function.get_block(parent).unwrap();
+        depth += 2 + parent.meta.retrieve_copied::<usize>(key!("innermost_loop_distance")).unwrap();
+        innermost_loop = parent.meta.retrieve_copied(key!("innermost_loop")).unwrap();
+    }
+    depth
+}
+
+/// Emit `local += delta` for an `i32` local
+fn emit_local_add(local: u32, delta: i32, out_f: &mut wasm::Function) {
+    out_f.instruction(&wasm::Instruction::LocalGet(local));
+    out_f.instruction(&wasm::Instruction::I32Const(delta));
+    out_f.instruction(&wasm::Instruction::I32Add);
+    out_f.instruction(&wasm::Instruction::LocalSet(local));
+}
+
+/// Emit a `memory.copy` replacement copying one byte at a time,
+/// the three `i32` scratch locals starting at `scratch` hold the destination, source and length.
+///
+/// Like `memory.copy`, the regions may overlap: if the destination is after the source,
+/// the bytes are copied from the last one
+fn emit_mem_copy_loop(scratch: u32, out_f: &mut wasm::Function) {
+    let (dst, src, len) = (scratch, scratch + 1, scratch + 2);
+    out_f.instruction(&wasm::Instruction::LocalSet(len));
+    out_f.instruction(&wasm::Instruction::LocalSet(src));
+    out_f.instruction(&wasm::Instruction::LocalSet(dst));
+
+    out_f.instruction(&wasm::Instruction::LocalGet(dst));
+    out_f.instruction(&wasm::Instruction::LocalGet(src));
+    out_f.instruction(&wasm::Instruction::I32GtU);
+    out_f.instruction(&wasm::Instruction::If(wasm::BlockType::Empty));
+    // (block (loop (br_if 1 (len == 0)) len -= 1; dst[len] = src[len]; br 0))
+    out_f.instruction(&wasm::Instruction::Block(wasm::BlockType::Empty));
+    out_f.instruction(&wasm::Instruction::Loop(wasm::BlockType::Empty));
+    out_f.instruction(&wasm::Instruction::LocalGet(len));
+    out_f.instruction(&wasm::Instruction::I32Eqz);
+    out_f.instruction(&wasm::Instruction::BrIf(1));
+    emit_local_add(len, -1, out_f);
+    out_f.instruction(&wasm::Instruction::LocalGet(dst));
+    out_f.instruction(&wasm::Instruction::LocalGet(len));
+    out_f.instruction(&wasm::Instruction::I32Add);
+    out_f.instruction(&wasm::Instruction::LocalGet(src));
+    out_f.instruction(&wasm::Instruction::LocalGet(len));
+    out_f.instruction(&wasm::Instruction::I32Add);
+    out_f.instruction(&wasm::Instruction::I32Load8_U(BYTE_MEMARG));
+    out_f.instruction(&wasm::Instruction::I32Store8(BYTE_MEMARG));
+    out_f.instruction(&wasm::Instruction::Br(0));
+    out_f.instruction(&wasm::Instruction::End);
+    out_f.instruction(&wasm::Instruction::End);
+    out_f.instruction(&wasm::Instruction::Else);
+    // (block (loop (br_if 1 (len == 0)) *dst = *src; dst += 1; src += 1; len -= 1; br 0))
+    out_f.instruction(&wasm::Instruction::Block(wasm::BlockType::Empty));
+    out_f.instruction(&wasm::Instruction::Loop(wasm::BlockType::Empty));
+    out_f.instruction(&wasm::Instruction::LocalGet(len));
+    out_f.instruction(&wasm::Instruction::I32Eqz);
+    out_f.instruction(&wasm::Instruction::BrIf(1));
+    out_f.instruction(&wasm::Instruction::LocalGet(dst));
+    out_f.instruction(&wasm::Instruction::LocalGet(src));
+    out_f.instruction(&wasm::Instruction::I32Load8_U(BYTE_MEMARG));
+    out_f.instruction(&wasm::Instruction::I32Store8(BYTE_MEMARG));
+    emit_local_add(dst, 1, out_f);
+    emit_local_add(src, 1, out_f);
+    emit_local_add(len, -1, out_f);
+    out_f.instruction(&wasm::Instruction::Br(0));
+    out_f.instruction(&wasm::Instruction::End);
+    out_f.instruction(&wasm::Instruction::End);
+    out_f.instruction(&wasm::Instruction::End);
+}
+
+/// Emit a `memory.fill` replacement setting one byte at a time,
+/// the three `i32` scratch locals starting at `scratch` hold the destination, value and length
+fn emit_mem_fill_loop(scratch: u32, out_f: &mut wasm::Function) {
+    let (dst, val, len) = (scratch, scratch + 1, scratch + 2);
+    out_f.instruction(&wasm::Instruction::LocalSet(len));
+    out_f.instruction(&wasm::Instruction::LocalSet(val));
+    out_f.instruction(&wasm::Instruction::LocalSet(dst));
+
+    // (block (loop (br_if 1 (len == 0)) *dst = val; dst += 1; len -= 1; br 0))
+    out_f.instruction(&wasm::Instruction::Block(wasm::BlockType::Empty));
+    out_f.instruction(&wasm::Instruction::Loop(wasm::BlockType::Empty));
+    out_f.instruction(&wasm::Instruction::LocalGet(len));
+    out_f.instruction(&wasm::Instruction::I32Eqz);
+    out_f.instruction(&wasm::Instruction::BrIf(1));
+    out_f.instruction(&wasm::Instruction::LocalGet(dst));
+    out_f.instruction(&wasm::Instruction::LocalGet(val));
+    out_f.instruction(&wasm::Instruction::I32Store8(BYTE_MEMARG));
+    emit_local_add(dst, 1, out_f);
+    emit_local_add(len, -1, out_f);
+    out_f.instruction(&wasm::Instruction::Br(0));
+    out_f.instruction(&wasm::Instruction::End);
+    out_f.instruction(&wasm::Instruction::End);
+}
+
+/// The result types of a function type
+fn func_ty_rets<'ctx>(ty: Ty<'ctx>) -> Vec<Ty<'ctx>> {
+    match &*ty {
+        Type::Func { args: _, ret } => ret.clone(),
+        _ => unreachable!()
+    }
+}
+
+/// The type of the memory an instruction needs in the frame of its function, if any:
+/// the memory allocated by `StackAlloc` or the large struct returned by a call
+fn frame_slot_type<'ctx, A: Abi>(module: &Module<'ctx>, instr: &Instr<'ctx>) -> Option<Ty<'ctx>> {
+    let rets = match &instr.kind {
+        InstrK::StackAlloc { ty } => return Some(*ty),
+        InstrK::CallDirect { func_name } => module.get_function(func_name).unwrap().ret_tys().clone(),
+        // meta["ty"] injected by the Verifier
+        InstrK::CallIndirect => func_ty_rets(instr.meta.retrieve_ty(key!("ty")).unwrap()),
+        _ => return None
+    };
+    returns_indirectly::<A>(module, &rets).then(|| rets[0])
+}
+
+/// The wasm types of values of the IR types, struct values are flattened
+fn flat_val_types<'ctx, A: Abi<BackendType = wasm::ValType>>(module: &Module<'ctx>, tys: &[Ty<'ctx>]) -> Vec<wasm::ValType> {
+    tys.iter()
+        .flat_map(|ty| flatten_type::<A>(module, *ty))
+        .map(|(scalar, _)| A::compile_type(scalar))
+        .collect()
+}
+
+/// How many scalars of every wasm type a struct has,
+/// every wasm type is represented by one of its scalars' IR types
+fn struct_scratch_counts<'ctx, A: Abi<BackendType = wasm::ValType>>(module: &Module<'ctx>, ty: Ty<'ctx>) -> Vec<(Ty<'ctx>, u32)> {
+    let mut counts: Vec<(Ty<'ctx>, u32)> = Vec::new();
+    for (scalar, _) in flatten_type::<A>(module, ty) {
+        match counts.iter_mut().find(|(t, _)| A::compile_type(*t) == A::compile_type(scalar)) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((scalar, 1))
+        }
+    }
+    counts
+}
+
+/// The memory argument of a scalar at an offset from the pointer
+fn scalar_memarg<'ctx, A: Abi>(module: &Module<'ctx>, ty: Ty<'ctx>, offset: u64) -> wasm::MemArg {
+    wasm::MemArg { offset, align: module.alignment::<A>(ty) as u32, memory_index: 0 }
+}
+
+/// The load of a scalar (such as a struct field) at an offset from the pointer on the stack
+fn scalar_load<'a, 'ctx, A: Abi<BackendType = wasm::ValType>>(module: &Module<'ctx>, ty: Ty<'ctx>, offset: u64) -> wasm::Instruction<'a> {
+    let memarg = scalar_memarg::<A>(module, ty, offset);
+    if let Some(bws) = type_to_bws(ty) {
+        return match bws {
+            BitWidthSign::S64 | BitWidthSign::U64 => wasm::Instruction::I64Load(memarg),
+            BitWidthSign::S32 | BitWidthSign::U32 => wasm::Instruction::I32Load(memarg),
+            BitWidthSign::S16 => wasm::Instruction::I32Load16_S(memarg),
+            BitWidthSign::U16 => wasm::Instruction::I32Load16_U(memarg),
+            BitWidthSign::S8 => wasm::Instruction::I32Load8_S(memarg),
+            BitWidthSign::U8 => wasm::Instruction::I32Load8_U(memarg),
+        }
+    }
+    // pointers and function pointers are i32
+    match A::compile_type(ty) {
+        wasm::ValType::I32 => wasm::Instruction::I32Load(memarg),
+        wasm::ValType::F32 => wasm::Instruction::F32Load(memarg),
+        wasm::ValType::F64 => wasm::Instruction::F64Load(memarg),
+        wasm::ValType::V128 => wasm::Instruction::V128Load { memarg },
+        _ => unimplemented!()
+    }
+}
+
+/// The store of a scalar (such as a struct field) at an offset from the pointer below it on the stack
+fn scalar_store<'a, 'ctx, A: Abi<BackendType = wasm::ValType>>(module: &Module<'ctx>, ty: Ty<'ctx>, offset: u64) -> wasm::Instruction<'a> {
+    let memarg = scalar_memarg::<A>(module, ty, offset);
+    if let Some(bws) = type_to_bws(ty) {
+        return match bws {
+            BitWidthSign::S64 | BitWidthSign::U64 => wasm::Instruction::I64Store(memarg),
+            BitWidthSign::S32 | BitWidthSign::U32 => wasm::Instruction::I32Store(memarg),
+            BitWidthSign::S16 | BitWidthSign::U16 => wasm::Instruction::I32Store16(memarg),
+            BitWidthSign::S8 | BitWidthSign::U8 => wasm::Instruction::I32Store8(memarg),
+        }
+    }
+    match A::compile_type(ty) {
+        wasm::ValType::I32 => wasm::Instruction::I32Store(memarg),
+        wasm::ValType::F32 => wasm::Instruction::F32Store(memarg),
+        wasm::ValType::F64 => wasm::Instruction::F64Store(memarg),
+        wasm::ValType::V128 => wasm::Instruction::V128Store { memarg },
+        _ => unimplemented!()
+    }
+}
+
+/// The size of a WebAssembly memory page in bytes
+const WASM_PAGE_SIZE: u64 = 65536;
+
+/// The opcodes of `return_call` and `return_call_indirect`
+const RETURN_CALL_OPCODE: u8 = 0x12;
+const RETURN_CALL_INDIRECT_OPCODE: u8 = 0x13;
+
+/// The memory argument of single byte loads and stores
+const BYTE_MEMARG: wasm::MemArg = wasm::MemArg { offset: 0, align: 0, memory_index: 0 };
+
+/// Emit the multiplication of the value on top of the stack by `size`
+/// followed by an addition to the value below it.
+fn emit_scaled_add(size: usize, out_f: &mut wasm::Function) {
+    // the sequence we'll use is:
+    // LdInt size
+    // IMul
+    // IAdd
+    // but because the sizes are often powers of two, for optimization
+    // purposes we'll replace the multiplications with left-shifts:
+    match size {
+        1 => {}, // no multiplication
+        2 => {
+            out_f.instruction(&wasm::Instruction::I32Const(1));
+            out_f.instruction(&wasm::Instruction::I32Shl);
+        }
+        4 => {
+            out_f.instruction(&wasm::Instruction::I32Const(2));
+            out_f.instruction(&wasm::Instruction::I32Shl);
+        }
+        8 => {
+            out_f.instruction(&wasm::Instruction::I32Const(3));
+            out_f.instruction(&wasm::Instruction::I32Shl);
+        }
+        other => {
+            out_f.instruction(&wasm::Instruction::I32Const(other as i32));
+            out_f.instruction(&wasm::Instruction::I32Mul);
+        }
+    }
+    // finally the `IAdd`
+    out_f.instruction(&wasm::Instruction::I32Add);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{abi::Wasm32Abi, builder::{FunctionBuilder, InstrBuilder}, module::{Module, WasmModuleConf}, pipeline_verify_module, ty::Type};
+
+    use super::{EmitError, WasmEmitter};
+
+    #[test]
+    pub fn stack_out_of_memory_test() {
+        let mut m = Module::new(WasmModuleConf {
+            initial_memory_size: 1,
+            stack_size: 65536,
+            ..WasmModuleConf::default()
+        });
+
+        // the result of the call is placed in the frame of the function
+        let struct_t = m.intern_type(Type::Struct { fields: vec![m.int32t(); 5] });
+        let mut builder = FunctionBuilder::new("func".to_string(), [], [struct_t]);
+        builder.i_call("func".to_string());
+        builder.finish(&mut m);
+
+        pipeline_verify_module(&mut m);
+        let result = m.do_pass(&mut WasmEmitter::<Wasm32Abi>::new());
+        assert!(matches!(result, Err(EmitError::StackOutOfMemory { stack_end: 65552, memory_size: 65536 })));
+    }
+}
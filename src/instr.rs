@@ -4,10 +4,15 @@ use crate::{intrinsic::{Intrinsic, Intrinsics}, metadata::{Key, Metadata}, stati
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum InstrK<'ctx> {
-    /// Load a constant integer value onto the stack
-    LdInt(u32, Ty<'ctx>),
+    /// Load a constant integer value onto the stack.
+    ///
+    /// For integer types of 32 bits or less, only the lower 32 bits of the value are used
+    /// (negative values may also be specified sign-extended to 64 bits).
+    LdInt(u64, Ty<'ctx>),
     /// Load a constant floating-point value onto the stack
     LdFloat(f32),
+    /// Load a constant double-precision floating-point value onto the stack
+    LdFloat64(f64),
     /// Add two integers
     IAdd,
     /// Subtract two integers
@@ -16,6 +21,12 @@ pub enum InstrK<'ctx> {
     IMul,
     /// Signed divide two integers. The result is undefined if the divisor is zero
     IDiv,
+    /// The remainder of dividing two integers. The result has the sign of the dividend.
+    /// The result is undefined if the divisor is zero
+    IRem,
+    /// Negate an integer (two's complement).
+    /// Negating the smallest value of a signed type wraps around to itself.
+    INeg,
     /// Add two floating-point numbers.
     ///
     /// For precise semantics, see <https://webassembly.github.io/spec/core/exec/numerics.html#op-fadd>
@@ -32,20 +43,86 @@ pub enum InstrK<'ctx> {
     ///
     /// For precise semantics, see <https://webassembly.github.io/spec/core/exec/numerics.html#op-fdiv>
     FDiv,
-    /// Convert a signed integer to a floating-point number.
+    /// The smaller of two floating-point numbers. If either is a NaN, the result is a NaN.
+    ///
+    /// For precise semantics, see <https://webassembly.github.io/spec/core/exec/numerics.html#op-fmin>
+    FMin,
+    /// The larger of two floating-point numbers. If either is a NaN, the result is a NaN.
+    ///
+    /// For precise semantics, see <https://webassembly.github.io/spec/core/exec/numerics.html#op-fmax>
+    FMax,
+    /// Pop two floating-point numbers and push the first one (deeper on the stack)
+    /// with the sign of the second one (on top of the stack).
+    ///
+    /// For precise semantics, see <https://webassembly.github.io/spec/core/exec/numerics.html#op-fcopysign>
+    FCopysign,
+    /// The square root of a floating-point number.
+    ///
+    /// For precise semantics, see <https://webassembly.github.io/spec/core/exec/numerics.html#op-fsqrt>
+    FSqrt,
+    /// The absolute value of a floating-point number.
+    ///
+    /// For precise semantics, see <https://webassembly.github.io/spec/core/exec/numerics.html#op-fabs>
+    FAbs,
+    /// Negate a floating-point number.
+    ///
+    /// For precise semantics, see <https://webassembly.github.io/spec/core/exec/numerics.html#op-fneg>
+    FNeg,
+    /// Round a floating-point number down to an integral value.
     ///
-    /// Compiles to the `f32.convert_i32_s` instruction.
-    Itof,
-    /// Convert a floating-point number to a signed integer.
+    /// For precise semantics, see <https://webassembly.github.io/spec/core/exec/numerics.html#op-ffloor>
+    FFloor,
+    /// Round a floating-point number up to an integral value.
     ///
-    /// Compiles to the `i32.trunc_f32_s` or `i32.trunc_sat_f32_s` instruction
-    /// depending on the IR Module configuration.
+    /// For precise semantics, see <https://webassembly.github.io/spec/core/exec/numerics.html#op-fceil>
+    FCeil,
+    /// Round a floating-point number towards zero to an integral value.
+    ///
+    /// For precise semantics, see <https://webassembly.github.io/spec/core/exec/numerics.html#op-ftrunc>
+    FTrunc,
+    /// Round a floating-point number to the nearest integral value, ties to even.
+    ///
+    /// For precise semantics, see <https://webassembly.github.io/spec/core/exec/numerics.html#op-fnearest>
+    FNearest,
+    /// Convert an integer to a floating-point number.
+    ///
+    /// Compiles to the `fN.convert_iM_s` or `fN.convert_iM_u` instruction,
+    /// depending on the sign of the integer type.
+    Itof { float_ty: Ty<'ctx> },
+    /// Convert a floating-point number to an integer.
+    ///
+    /// Compiles to the `iN.trunc_fM_s`/`iN.trunc_fM_u` or `iN.trunc_sat_fM_s`/`iN.trunc_sat_fM_u` instruction
+    /// depending on the sign of the integer type and the IR Module configuration.
+    /// When saturating, conversions to 8- and 16-bit types saturate at the bounds of the type,
+    /// otherwise out-of-range values wrap around.
     /// For precise semantics, see <https://webassembly.github.io/spec/core/exec/numerics.html#op-trunc-sat-s>
     Ftoi { int_ty: Ty<'ctx> },
     /// Compare two signed integers. The result is an integer.
     ICmp(Cmp),
     /// Compare two floating-point values. The result is an integer.
     FCmp(Cmp),
+    /// Create a vector with all lanes equal to the scalar value popped off the stack.
+    VSplat(VecShape),
+    /// Pop a vector off the stack and push the value of its Nth lane.
+    VExtractLane { shape: VecShape, lane: u8 },
+    /// Pop a scalar value and a vector off the stack
+    /// and push back the vector with the Nth lane replaced by the scalar.
+    VReplaceLane { shape: VecShape, lane: u8 },
+    /// Lane-wise addition of two vectors
+    VAdd(VecShape),
+    /// Lane-wise subtraction of two vectors
+    VSub(VecShape),
+    /// Lane-wise multiplication of two vectors
+    VMul(VecShape),
+    /// Convert a float32 to a float64.
+    ///
+    /// Compiles to the `f64.promote_f32` instruction.
+    FPromote,
+    /// Convert a float64 to a float32.
+    ///
+    /// Compiles to the `f32.demote_f64` instruction.
+    /// For precise semantics, see <https://webassembly.github.io/spec/core/exec/numerics.html#op-demote>
+    FDemote,
     /// A boolean not operation on integers.
     /// Returns 1 if the value is 0 and 0 otherwise.
     Not,
@@ -53,6 +130,17 @@ pub enum InstrK<'ctx> {
     BitAnd,
     /// Bitwise or.
     BitOr,
+    /// Bitwise exclusive or.
+    BitXor,
+    /// Bitwise complement, flips all bits of an integer.
+    BitNot,
+    /// Shift an integer left by an amount of the same type.
+    /// The result wraps around to the bit width of the type.
+    Shl,
+    /// Shift an integer right by an amount of the same type.
+    ///
+    /// The shift is arithmetic for signed types and logical for unsigned types.
+    Shr,
     /// Convert an integer to another integer type.
     ///
     /// For precise semantics, see the *numerics* draft, which contains a detailed description
@@ -71,6 +159,19 @@ pub enum InstrK<'ctx> {
     /// Call a function pointer on top of the stack.
     /// Pop arguments off the stack.
     CallIndirect,
+    /// Call a global function by name and return its results from the current function,
+    /// reusing the current function's stack frame.
+    /// Pop arguments off the stack.
+    ///
+    /// The function must return the same types as the current function.
+    /// This instruction terminates a block, like Return.
+    ///
+    /// Tail calls require tail calls to be enabled. A function which uses `StackAlloc`
+    /// can't make tail calls, its frame is popped before the called function runs.
+    TailCallDirect { func_name: String },
+    /// Call a function pointer on top of the stack and return its results
+    /// from the current function, see TailCallDirect
+    TailCallIndirect,
     /// Cast a value to another type without any value conversions.
     /// equivalent to `*((T*)&expr)` in C.
     ///
@@ -79,6 +180,28 @@ pub enum InstrK<'ctx> {
     /// Pop a value off the stack. If the value is non-zero, jump
     /// to the `then` block, otherwise jump to the `else` block (if there's one)
     IfElse { then: BlockId, r#else: Option<BlockId> },
+    /// Pop an integer index off the stack and jump to the block `cases[index]`,
+    /// or to the `default` block if the index is out of bounds.
+    ///
+    /// All the blocks must have the same type, their results are pushed onto the stack.
+    Switch { cases: Vec<BlockId>, default: BlockId },
+    /// Execute the `body` block. If an exception thrown inside it (including in called functions)
+    /// has the tag of one of the `catches`, execution continues with that handler block,
+    /// which starts with the payload values of the exception on the stack.
+    /// Exceptions with other tags are handled by the `catch_all` block if there's one,
+    /// otherwise they're propagated further.
+    ///
+    /// All the blocks must have the same type, their results are pushed onto the stack.
+    ///
+    /// Exceptions require exception handling to be enabled.
+    TryCatch { body: BlockId, catches: Vec<(String, BlockId)>, catch_all: Option<BlockId> },
+    /// Pop the payload values of the exception tag off the stack
+    /// and throw an exception with the tag.
+    ///
+    /// This instruction terminates a block, like Return.
+    ///
+    /// Exceptions require exception handling to be enabled.
+    Throw { tag: String },
     /// Pop a pointer off the stack and read a value of this type at the address of the pointer
     Read { ty: Ty<'ctx> },
     /// Pop a value and a pointer off the stack and write the value
@@ -88,11 +211,49 @@ pub enum InstrK<'ctx> {
     /// Pops an integer `n` off the stack  and a pointer `ptr` and pushes a pointer
     /// whose address is equal to `(int)ptr + n * sizeof(T)`
     Offset { ty: Ty<'ctx> },
+    /// Same as Offset, except the integer `n` is known at compile-time.
+    /// Pops a pointer `ptr` and pushes a pointer whose address
+    /// is equal to `(int)ptr + n * sizeof(T)`
+    OffsetConst { ty: Ty<'ctx>, n: usize },
+    /// Allocate memory for a value of type `ty` in the frame of the current call
+    /// on the shadow stack and push a pointer to it.
+    ///
+    /// The memory lives until the function exits. Every `StackAlloc` instruction
+    /// has its own place in the frame, so executing it again (in a loop)
+    /// returns the same pointer.
+    StackAlloc { ty: Ty<'ctx> },
     /// Pop a pointer off the stack which points to `struct_ty`
     /// and push back a pointer which points to the Nth field of the struct
     GetFieldPtr { struct_ty: Ty<'ctx>, field_idx: usize },
+    /// Pop an integer index and a pointer which points to `array_ty` off the stack
+    /// and push back a pointer which points to the element at that index.
+    ///
+    /// The index is not bounds-checked.
+    GetElemPtr { array_ty: Ty<'ctx> },
+    /// Pop a pointer off the stack which points to `union_ty`
+    /// and push the tag of the union, which is an uint32.
+    GetTag { union_ty: Ty<'ctx> },
+    /// Pop a pointer off the stack which points to `union_ty`
+    /// and set the tag of the union to `variant_idx`
+    SetTag { union_ty: Ty<'ctx>, variant_idx: usize },
+    /// Pop a pointer off the stack which points to `union_ty`
+    /// and push back a pointer which points to the payload of the Nth variant.
+    ///
+    /// The tag is not checked.
+    GetVariantPtr { union_ty: Ty<'ctx>, variant_idx: usize },
     /// Pop a value off the stack and discard it
     Discard,
+    /// Duplicate the value on the top of the stack: [a] -> [a, a]
+    Dup,
+    /// Swap the two values on the top of the stack: [a, b] -> [b, a]
+    Swap,
+    /// Copy the second value from the top of the stack to the top: [a, b] -> [a, b, a]
+    Over,
+    /// Pop a condition and two values of the same type off the stack.
+    /// If the condition is non-zero, push the first value, otherwise push the second one.
+    ///
+    /// Unlike IfElse, both values are always evaluated.
+    Select,
     /// Return immediately from the current function.
     ///
     /// The stack must contain _exactly_ the number of values the function returns.
@@ -107,10 +268,52 @@ pub enum InstrK<'ctx> {
     ///
     /// Pops an integer off the stack and pushes a new one.
     MemoryGrow,
+    /// Pop a length, a source pointer and a destination pointer off the stack
+    /// and copy `length` bytes from the source to the destination.
+    ///
+    /// The memory regions may overlap.
+    MemCopy,
+    /// Pop a length, an integer value and a destination pointer off the stack
+    /// and set `length` bytes at the destination to the lowest byte of the value.
+    MemFill,
+    /// Pop a pointer off the stack, atomically read an integer of type `ty`
+    /// from memory at the address of the pointer and push it onto the stack.
+    ///
+    /// Atomic instructions require shared memory to be enabled.
+    AtomicRead { ty: Ty<'ctx> },
+    /// Pop an integer of type `ty` and a pointer off the stack and atomically
+    /// write the integer into memory at the address of the pointer
+    AtomicWrite { ty: Ty<'ctx> },
+    /// Pop an integer of type `ty` and a pointer off the stack, atomically
+    /// modify the integer in memory at the address of the pointer by the operation
+    /// with the popped integer and push the original integer in memory
+    AtomicRmw { op: AtomicRmwOp, ty: Ty<'ctx> },
+    /// Pop a replacement and an expected integer of type `ty` and a pointer off the stack.
+    /// Atomically compare the integer in memory at the address of the pointer with
+    /// the expected integer and if they're equal, replace it. Push the original integer in memory.
+    AtomicCmpxchg { ty: Ty<'ctx> },
+    /// Pop a timeout in nanoseconds (an int64, negative means no timeout),
+    /// an expected integer of type `ty` and a pointer off the stack.
+    /// If the integer in memory at the address of the pointer equals the expected one,
+    /// block the thread until it's woken up by AtomicNotify or until the timeout passes.
+    ///
+    /// Pushes an int32: 0 if the thread was woken up, 1 if the integers weren't equal,
+    /// 2 if the timeout passed.
+    ///
+    /// The type must be a 32 or a 64-bit integer type.
+    AtomicWait { ty: Ty<'ctx> },
+    /// Pop an int32 count and a pointer off the stack, wake up at most `count` threads waiting
+    /// at the address of the pointer and push the number of woken up threads as an int32.
+    AtomicNotify,
     /// Load the value of a global and push it onto the stack
     LdGlobal(String),
     /// Pop a value off the stack and store it into a global
     StGlobal(String),
+    /// Push a null `externref`
+    LdNullRef,
+    /// Pop an `externref` off the stack and push 1 if it's null, 0 otherwise.
+    /// The result is an int32.
+    IsNullRef,
     /// Fail.
     ///
     /// This instruction is always assumed to produce stack values
@@ -123,10 +326,24 @@ pub enum InstrK<'ctx> {
     Fail,
     /// Repeatedly execute a block of code.
     /// 
-    /// The block must have a void type ([] -> [])
+    /// The block's return types are the values the loop produces.
+    /// They are left on the stack by the breaks out of the loop,
+    /// the end of the block itself must leave the stack empty
+    /// as it continues with the next iteration.
     Loop(BlockId),
     /// Break from the innermost loop.
+    ///
+    /// The values the loop produces must be on the stack.
     Break,
+    /// Break from the loop whose body is this block.
+    ///
+    /// The loop must enclose this instruction
+    /// and the values it produces must be on the stack.
+    BreakLoop(BlockId),
+    /// Jump to the start of the loop whose body is this block.
+    ///
+    /// The loop must enclose this instruction.
+    ContinueLoop(BlockId),
     /// Load a pointer to an item in static memory.
     /// Returns type [`ptr`].
     LdStaticMemPtr(SMItemRef),
@@ -145,6 +362,36 @@ pub enum Cmp {
     Ge
 }
 
+/// The operation performed by an AtomicRmw instruction
+#[repr(C)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum AtomicRmwOp {
+    Add,
+    Sub,
+    And,
+    Or,
+    /// Replace the integer in memory with the popped one
+    Xchg
+}
+
+/// How the lanes of a `v128` vector are interpreted
+#[repr(C)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum VecShape {
+    /// Four 32-bit integers
+    I32x4,
+    /// Four 32-bit floating-point numbers
+    F32x4
+}
+
+impl VecShape {
+    pub fn lane_count(self) -> u8 {
+        match self {
+            VecShape::I32x4 | VecShape::F32x4 => 4
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Instr<'ctx> {
     pub kind: InstrK<'ctx>,
@@ -167,16 +414,18 @@ impl<'ctx> Instr<'ctx> {
     /// Return true if this instruction is a "load" instruction.
     /// A "load" instruction is an instruction which pops no values off the stack and pushes exactly one value.
     ///
-    /// Namely this includes LdInt, LdFloat, LdLocal, LdGlobalFunc
+    /// Namely this includes LdInt, LdFloat, LdFloat64, LdLocal, LdGlobalFunc, LdNullRef
     pub fn is_load(&self) -> bool {
-        matches!(self.kind, InstrK::LdInt(_, _) | InstrK::LdFloat(_) | InstrK::LdLocal { idx: _ } | InstrK::LdGlobalFunc { func_name: _ })
+        matches!(self.kind, InstrK::LdInt(_, _) | InstrK::LdFloat(_) | InstrK::LdFloat64(_) | InstrK::LdLocal { idx: _ } | InstrK::LdGlobalFunc { func_name: _ } | InstrK::LdNullRef)
     }
 
     /// Return true if this instruction is a "diverging" instruction.
     /// 
-    /// Namely this includes Return, Fail and Break
+    /// Namely this includes Return, TailCallDirect, TailCallIndirect, Throw, Fail, Break, BreakLoop and ContinueLoop
     pub fn is_diverging(&self) -> bool {
-        matches!(self.kind, InstrK::Return | InstrK::Fail | InstrK::Break)
+        matches!(self.kind,
+            InstrK::Return | InstrK::TailCallDirect { func_name: _ } | InstrK::TailCallIndirect |
+            InstrK::Throw { tag: _ } | InstrK::Fail | InstrK::Break | InstrK::BreakLoop(_) | InstrK::ContinueLoop(_))
     }
 }
 
@@ -216,6 +465,12 @@ pub enum BlockTag {
     IfElse,
     /// A block which is used as the body of a Loop instruction
     Loop,
+    /// A block which is used as one of the cases (or the default case) of a Switch instruction
+    Switch,
+    /// A block which is used as the body of a TryCatch instruction
+    Try,
+    /// A block which is used as one of the handlers (or the catch_all handler) of a TryCatch instruction
+    Catch,
 }
 
 /// A block is a series of instructions
@@ -227,6 +482,8 @@ pub enum BlockTag {
 /// * If tag = *main*, then the function returns
 /// * If tag = *ifelse*, then the execution jumps to the instruction one after the corresponding `if_else` instruction
 /// * If tag = *loop*, then the execution jumps to the start of the loop block
+/// * If tag = *switch*, then the execution jumps to the instruction one after the corresponding `switch` instruction
+/// * If tag = *try* or *catch*, then the execution jumps to the instruction one after the corresponding `try` instruction
 /// Otherwise, the behavior is not specified.
 pub struct InstrBlock<'ctx> {
     /// A unique index of the block inside a function.
@@ -234,8 +491,10 @@ pub struct InstrBlock<'ctx> {
     pub(crate) idx: BlockId,
     pub(crate) tag: BlockTag,
     pub body: Vec<Instr<'ctx>>,
-    /// Every block has a type - it must be a function type with no arguments.
+    /// Every block has a type - it must be a function type.
     ///
+    /// The arguments of the function type are the block parameters, values taken
+    /// from the stack of the branch instruction, the block starts with them on its stack.
     /// The `return` of the function type describes what types are left on the stack
     /// once the block is exited.
     block_ty: Ty<'ctx>,
@@ -245,9 +504,6 @@ pub struct InstrBlock<'ctx> {
 impl<'ctx> InstrBlock<'ctx> {
     pub fn new(idx: BlockId, block_ty: Ty<'ctx>, tag: BlockTag) -> Self {
         assert!(block_ty.is_func());
-        if let Type::Func { args, ret: _ } = &*block_ty {
-            assert!(args.is_empty());
-        }
 
         InstrBlock { idx, tag, body: Vec::new(), meta: Metadata::new(), block_ty }
     }
@@ -268,6 +524,14 @@ impl<'ctx> InstrBlock<'ctx> {
         }
     }
 
+    /// The types of the values the block takes from the stack
+    pub fn params(&self) -> &Vec<Ty<'ctx>> {
+        match &*self.block_ty {
+            Type::Func { args, ret: _ } => args,
+            _ => unreachable!()
+        }
+    }
+
     #[inline]
     pub fn is_main(&self) -> bool {
         self.idx == BlockId(0)
@@ -385,4 +649,11 @@ impl<'ctx> Function<'ctx> {
     pub fn is_local_an_arg(&self, n: usize) -> bool {
         n < self.arg_count()
     }
+
+    /// Returns true if the function allocates memory on the shadow stack with `StackAlloc`
+    pub fn uses_stack_alloc(&self) -> bool {
+        self.blocks_iter()
+            .flat_map(|b| b.body.iter())
+            .any(|i| matches!(i.kind, InstrK::StackAlloc { ty: _ }))
+    }
 }
\ No newline at end of file
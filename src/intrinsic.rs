@@ -4,7 +4,8 @@
 //! in the public API.
 //!
 //! The list of currently used instrinsic instructions is: (for orientation)
-//! * ReadOffset - merges a GetElementPtr and Read instruction into one for more efficient compilation
+//! * ReadAtOffset - merges a GetFieldPtr (or a constant Offset) and Read instruction into one for more efficient compilation
+//! * WriteAtOffset - the same for Write, the value written is loaded between the two instructions
 
 use crate::ty::Ty;
 
@@ -18,6 +19,8 @@ pub(crate) enum Intrinsics<'ctx> {
     // WebAssembly allows for Read/Write (resp. load/store) instructions to specify an offset.
     // For simplicity, SwarmIR does not offer memory instructions with an explicit offset.
     // These two instructions replace the GetFieldPtr & Read/Write combination
+    // (and OffsetConst or LdInt & Offset with a non-negative constant).
+    // They're only used for scalar types
     /// Pop a memory address and read a value of type [`ty`] at memory_address + [`offset`]
     ReadAtOffset{ offset: usize, ty: Ty<'ctx> },
     /// Pop a value and a memory address and write the value of type [`ty`] at memory_address + [`offset`]
@@ -2,14 +2,24 @@ use std::{collections::HashMap, iter::Peekable};
 
 use logos::{Logos, SpannedIter};
 
-use crate::{instr::{BlockId, BlockTag, Cmp, Function, Instr, InstrBlock, InstrK}, module::Module, ty::{Ty, Type}};
+use crate::{instr::{AtomicRmwOp, BlockId, BlockTag, Cmp, Function, Instr, InstrBlock, InstrK, VecShape}, module::Module, ty::{Ty, Type}};
 
 #[derive(Logos, PartialEq, Debug)]
 pub enum IrToken {
+    #[token("int64")]
+    Int64,
+    #[token("uint64")]
+    UInt64,
     #[token("int32")]
     Int32,
     #[token("float32")]
     Float32,
+    #[token("float64")]
+    Float64,
+    #[token("v128")]
+    V128,
+    #[token("externref")]
+    ExternRef,
     #[token("uint32")]
     UInt32,
     #[token("int16")]
@@ -24,6 +34,8 @@ pub enum IrToken {
     Ptr,
     #[token("struct")]
     Struct,
+    #[token("union")]
+    Union,
     #[token("func")]
     Func,
     #[regex(r#""([^"])*""#)]
@@ -38,6 +50,12 @@ pub enum IrToken {
     Arrow,
     #[token("{")]
     LBrace,
+    #[token("[")]
+    LBracket,
+    #[token("]")]
+    RBracket,
+    #[token(";")]
+    Semicolon,
     #[token("}")]
     RBrace,
     #[token(":")]
@@ -48,10 +66,12 @@ pub enum IrToken {
     Hash,
     #[regex("[0-9]+")]
     Int,
-    #[regex("[0-9]+'.'[0-9]+")]
+    #[regex(r"[0-9]+\.[0-9]+")]
     Float,
     #[regex("[a-zA-Z_][a-zA-Z0-9._-]*")] // identifiers may contain dots
     Identifier,
+    #[regex("%[a-zA-Z_][a-zA-Z0-9._-]*")]
+    NamedType,
     #[token("=")]
     Equals,
     #[regex(r"[ \n\r\t\f]+", logos::skip)]
@@ -117,8 +137,21 @@ impl<'a, 'ctx> IRParser<'a, 'ctx> {
         Ok(BlockId::from(block_id))
     }
 
+    fn parse_atomic_rmw(&mut self, op: AtomicRmwOp) -> Result<Instr<'ctx>, IrParseError> {
+        let ty = self.parse_type()?;
+        Ok(Instr::new(InstrK::AtomicRmw { op, ty }))
+    }
+
     fn parse_instr(&mut self) -> Result<Instr<'ctx>, IrParseError> {
         let i = match self.expect(IrToken::Identifier)? {
+            "ld.int64" => {
+                let n = self.expect(IrToken::Int)?.parse().unwrap();
+                Instr::new(InstrK::LdInt(n, self.module.int64t()))
+            }
+            "ld.uint64" => {
+                let n = self.expect(IrToken::Int)?.parse().unwrap();
+                Instr::new(InstrK::LdInt(n, self.module.uint64t()))
+            }
             "ld.int32" => {
                 let n = self.expect(IrToken::Int)?.parse().unwrap();
                 Instr::new(InstrK::LdInt(n, self.module.int32t()))
@@ -147,15 +180,36 @@ impl<'a, 'ctx> IRParser<'a, 'ctx> {
                 let f = self.expect(IrToken::Float)?.parse().unwrap();
                 Instr::new(InstrK::LdFloat(f))
             }
+            "ld.float64" => {
+                let f = self.expect(IrToken::Float)?.parse().unwrap();
+                Instr::new(InstrK::LdFloat64(f))
+            }
             "iadd" => Instr::new(InstrK::IAdd),
             "isub" => Instr::new(InstrK::ISub),
             "imul" => Instr::new(InstrK::IMul),
             "idiv" => Instr::new(InstrK::IDiv),
+            "irem" => Instr::new(InstrK::IRem),
+            "ineg" => Instr::new(InstrK::INeg),
             "fadd" => Instr::new(InstrK::FAdd),
             "fsub" => Instr::new(InstrK::FSub),
             "fmul" => Instr::new(InstrK::FMul),
             "fdiv" => Instr::new(InstrK::FDiv),
-            "itof" => Instr::new(InstrK::Itof),
+            "fmin" => Instr::new(InstrK::FMin),
+            "fmax" => Instr::new(InstrK::FMax),
+            "fcopysign" => Instr::new(InstrK::FCopysign),
+            "fsqrt" => Instr::new(InstrK::FSqrt),
+            "fabs" => Instr::new(InstrK::FAbs),
+            "fneg" => Instr::new(InstrK::FNeg),
+            "ffloor" => Instr::new(InstrK::FFloor),
+            "fceil" => Instr::new(InstrK::FCeil),
+            "ftrunc" => Instr::new(InstrK::FTrunc),
+            "fnearest" => Instr::new(InstrK::FNearest),
+            "itof" => {
+                let t = self.expect(IrToken::Identifier)?;
+                if t != "to" { return Err(IrParseError::MalformedIdentifier { got: t.to_owned() }) }
+                let float_ty = self.parse_type()?;
+                Instr::new(InstrK::Itof { float_ty })
+            },
             "ftoi" => {
                 let t = self.expect(IrToken::Identifier)?;
                 if t != "to" { return Err(IrParseError::MalformedIdentifier { got: t.to_owned() }) }
@@ -174,9 +228,39 @@ impl<'a, 'ctx> IRParser<'a, 'ctx> {
             "fcmp.le" => Instr::new(InstrK::FCmp(Cmp::Le)),
             "fcmp.gt" => Instr::new(InstrK::FCmp(Cmp::Gt)),
             "fcmp.ge" => Instr::new(InstrK::FCmp(Cmp::Ge)),
+            "vsplat.i32x4" => Instr::new(InstrK::VSplat(VecShape::I32x4)),
+            "vsplat.f32x4" => Instr::new(InstrK::VSplat(VecShape::F32x4)),
+            "vextract_lane.i32x4" => {
+                let lane = self.expect(IrToken::Int)?.parse().unwrap();
+                Instr::new(InstrK::VExtractLane { shape: VecShape::I32x4, lane })
+            }
+            "vextract_lane.f32x4" => {
+                let lane = self.expect(IrToken::Int)?.parse().unwrap();
+                Instr::new(InstrK::VExtractLane { shape: VecShape::F32x4, lane })
+            }
+            "vreplace_lane.i32x4" => {
+                let lane = self.expect(IrToken::Int)?.parse().unwrap();
+                Instr::new(InstrK::VReplaceLane { shape: VecShape::I32x4, lane })
+            }
+            "vreplace_lane.f32x4" => {
+                let lane = self.expect(IrToken::Int)?.parse().unwrap();
+                Instr::new(InstrK::VReplaceLane { shape: VecShape::F32x4, lane })
+            }
+            "vadd.i32x4" => Instr::new(InstrK::VAdd(VecShape::I32x4)),
+            "vadd.f32x4" => Instr::new(InstrK::VAdd(VecShape::F32x4)),
+            "vsub.i32x4" => Instr::new(InstrK::VSub(VecShape::I32x4)),
+            "vsub.f32x4" => Instr::new(InstrK::VSub(VecShape::F32x4)),
+            "vmul.i32x4" => Instr::new(InstrK::VMul(VecShape::I32x4)),
+            "vmul.f32x4" => Instr::new(InstrK::VMul(VecShape::F32x4)),
+            "fpromote" => Instr::new(InstrK::FPromote),
+            "fdemote" => Instr::new(InstrK::FDemote),
             "not" => Instr::new(InstrK::Not),
             "bitand" => Instr::new(InstrK::BitAnd),
             "bitor" => Instr::new(InstrK::BitOr),
+            "bitxor" => Instr::new(InstrK::BitXor),
+            "bitnot" => Instr::new(InstrK::BitNot),
+            "shl" => Instr::new(InstrK::Shl),
+            "shr" => Instr::new(InstrK::Shr),
             "iconv" => {
                 let t = self.expect(IrToken::Identifier)?;
                 if t != "to" { return Err(IrParseError::MalformedIdentifier { got: t.to_owned() }) }
@@ -194,6 +278,17 @@ impl<'a, 'ctx> IRParser<'a, 'ctx> {
                     Instr::new(InstrK::CallDirect { func_name })
                 }
             },
+            "tail_call" => {
+                if self.peek(IrToken::Identifier) {
+                    // tail_call indirect
+                    let t = self.expect(IrToken::Identifier)?;
+                    if t != "indirect" { return Err(IrParseError::MalformedIdentifier  { got: t.to_owned() }) }
+                    Instr::new(InstrK::TailCallIndirect)
+                } else {
+                    let func_name = self.expect(IrToken::String)?.strip('"').to_owned();
+                    Instr::new(InstrK::TailCallDirect { func_name })
+                }
+            },
             "ld.loc" => {
                 self.expect(IrToken::Hash)?;
                 let idx = self.expect(IrToken::Int)?.parse().unwrap();
@@ -226,10 +321,63 @@ impl<'a, 'ctx> IRParser<'a, 'ctx> {
                     Instr::new(InstrK::IfElse { then, r#else: None })
                 }
             }
+            "switch" => {
+                // switch b1 b2 ... default bN
+                let mut cases = vec![];
+                while self.peek_str(IrToken::Identifier) != Some("default") {
+                    cases.push(self.parse_block_id()?);
+                }
+                self.next(); // "default"
+                let default = self.parse_block_id()?;
+                Instr::new(InstrK::Switch { cases, default })
+            }
+            "try" => {
+                // try b1 catch "tag" b2 ... catch_all bN
+                let body = self.parse_block_id()?;
+                let mut catches = vec![];
+                while self.peek_str(IrToken::Identifier) == Some("catch") {
+                    self.next(); // "catch"
+                    let tag = self.expect(IrToken::String)?.strip('"').to_owned();
+                    catches.push((tag, self.parse_block_id()?));
+                }
+                let catch_all = if self.peek_str(IrToken::Identifier) == Some("catch_all") {
+                    self.next(); // "catch_all"
+                    Some(self.parse_block_id()?)
+                } else {
+                    None
+                };
+                Instr::new(InstrK::TryCatch { body, catches, catch_all })
+            }
+            "throw" => {
+                let tag = self.expect(IrToken::String)?.strip('"').to_owned();
+                Instr::new(InstrK::Throw { tag })
+            }
             "read" => {
                 let ty = self.parse_type()?;
                 Instr::new(InstrK::Read { ty })
             }
+            "atomic.read" => {
+                let ty = self.parse_type()?;
+                Instr::new(InstrK::AtomicRead { ty })
+            }
+            "atomic.write" => {
+                let ty = self.parse_type()?;
+                Instr::new(InstrK::AtomicWrite { ty })
+            }
+            "atomic.rmw.add" => self.parse_atomic_rmw(AtomicRmwOp::Add)?,
+            "atomic.rmw.sub" => self.parse_atomic_rmw(AtomicRmwOp::Sub)?,
+            "atomic.rmw.and" => self.parse_atomic_rmw(AtomicRmwOp::And)?,
+            "atomic.rmw.or" => self.parse_atomic_rmw(AtomicRmwOp::Or)?,
+            "atomic.rmw.xchg" => self.parse_atomic_rmw(AtomicRmwOp::Xchg)?,
+            "atomic.cmpxchg" => {
+                let ty = self.parse_type()?;
+                Instr::new(InstrK::AtomicCmpxchg { ty })
+            }
+            "atomic.wait" => {
+                let ty = self.parse_type()?;
+                Instr::new(InstrK::AtomicWait { ty })
+            }
+            "atomic.notify" => Instr::new(InstrK::AtomicNotify),
             "write" => {
                 let ty = self.parse_type()?;
                 Instr::new(InstrK::Write { ty })
@@ -238,15 +386,48 @@ impl<'a, 'ctx> IRParser<'a, 'ctx> {
                 let ty = self.parse_type()?;
                 Instr::new(InstrK::Offset { ty })
             }
+            "offset_const" => {
+                let n = self.expect(IrToken::Int)?.parse().unwrap();
+                let ty = self.parse_type()?;
+                Instr::new(InstrK::OffsetConst { ty, n })
+            }
+            "stack_alloc" => {
+                let ty = self.parse_type()?;
+                Instr::new(InstrK::StackAlloc { ty })
+            }
             "get_field_ptr" => {
                 let field_idx = self.expect(IrToken::Int)?.parse().unwrap();
                 let struct_ty = self.parse_type()?;
                 Instr::new(InstrK::GetFieldPtr { struct_ty, field_idx })
             }
+            "get_tag" => {
+                let union_ty = self.parse_type()?;
+                Instr::new(InstrK::GetTag { union_ty })
+            }
+            "set_tag" => {
+                let variant_idx = self.expect(IrToken::Int)?.parse().unwrap();
+                let union_ty = self.parse_type()?;
+                Instr::new(InstrK::SetTag { union_ty, variant_idx })
+            }
+            "get_variant_ptr" => {
+                let variant_idx = self.expect(IrToken::Int)?.parse().unwrap();
+                let union_ty = self.parse_type()?;
+                Instr::new(InstrK::GetVariantPtr { union_ty, variant_idx })
+            }
+            "get_elem_ptr" => {
+                let array_ty = self.parse_type()?;
+                Instr::new(InstrK::GetElemPtr { array_ty })
+            }
             "discard" => Instr::new(InstrK::Discard),
+            "dup" => Instr::new(InstrK::Dup),
+            "swap" => Instr::new(InstrK::Swap),
+            "over" => Instr::new(InstrK::Over),
+            "select" => Instr::new(InstrK::Select),
             "return" => Instr::new(InstrK::Return),
             "memory.size" => Instr::new(InstrK::MemorySize),
             "memory.grow" => Instr::new(InstrK::MemoryGrow),
+            "memory.copy" => Instr::new(InstrK::MemCopy),
+            "memory.fill" => Instr::new(InstrK::MemFill),
             "ld.global" => {
                 let name = self.expect(IrToken::String)?.strip('"').to_owned();
                 Instr::new(InstrK::LdGlobal(name))
@@ -256,11 +437,21 @@ impl<'a, 'ctx> IRParser<'a, 'ctx> {
                 Instr::new(InstrK::StGlobal(name))
             }
             "fail" => Instr::new(InstrK::Fail),
+            "ld.null_ref" => Instr::new(InstrK::LdNullRef),
+            "is_null_ref" => Instr::new(InstrK::IsNullRef),
             "loop" => {
                 let body = self.parse_block_id()?;
                 Instr::new(InstrK::Loop(body))
             }
             "break" => Instr::new(InstrK::Break),
+            "break_loop" => {
+                let body = self.parse_block_id()?;
+                Instr::new(InstrK::BreakLoop(body))
+            }
+            "continue_loop" => {
+                let body = self.parse_block_id()?;
+                Instr::new(InstrK::ContinueLoop(body))
+            }
             _ => return Err(IrParseError::InvalidInstructionName)
         };
         Ok(i)
@@ -281,6 +472,9 @@ impl<'a, 'ctx> IRParser<'a, 'ctx> {
             "main" => BlockTag::Main,
             "if_else" => BlockTag::IfElse,
             "loop" => BlockTag::Loop,
+            "switch" => BlockTag::Switch,
+            "try" => BlockTag::Try,
+            "catch" => BlockTag::Catch,
             other => return Err(IrParseError::MalformedIdentifier { got: other.to_owned() })
         };
 
@@ -304,6 +498,66 @@ impl<'a, 'ctx> IRParser<'a, 'ctx> {
         Ok(block)
     }
 
+    /// Parse a named struct type definition, such as
+    /// `type %Name = struct{int32, ptr}` or `type %Name = opaque`
+    pub fn parse_type_definition(&mut self) -> Result<Ty<'ctx>, IrParseError> {
+        let t = self.expect(IrToken::Identifier)?;
+        if t != "type" { return Err(IrParseError::MalformedIdentifier { got: t.to_owned() }) }
+        let named_ty = self.parse_type()?;
+        if !matches!(&*named_ty, Type::NamedStruct { name: _, body: _ }) {
+            return Err(IrParseError::GeneralUnexpectedToken)
+        }
+        self.expect(IrToken::Equals)?;
+        if self.peek_str(IrToken::Identifier) == Some("opaque") {
+            self.next(); // 'opaque'
+            return Ok(named_ty)
+        }
+        if named_ty.struct_fields().is_some() {
+            return Err(IrParseError::NamedStructRedefinition { ty: format!("{:?}", named_ty) })
+        }
+        let body_ty = self.parse_type()?;
+        match &*body_ty {
+            // the struct can only refer to itself through a pointer
+            Type::Struct { fields: _ } if body_ty.contains_by_value(named_ty) =>
+                Err(IrParseError::RecursiveNamedStruct { ty: format!("{:?}", named_ty) }),
+            Type::Struct { fields } => {
+                self.module.set_named_struct_body(named_ty, fields.clone());
+                Ok(named_ty)
+            }
+            _ => Err(IrParseError::GeneralUnexpectedToken)
+        }
+    }
+
+    /// Parse an exception tag definition and add it to the module, such as
+    /// `tag "name" (int32, float64)`
+    pub fn parse_tag_definition(&mut self) -> Result<(), IrParseError> {
+        let t = self.expect(IrToken::Identifier)?;
+        if t != "tag" { return Err(IrParseError::MalformedIdentifier { got: t.to_owned() }) }
+        let name = self.expect(IrToken::String)?.strip('"').to_owned();
+        self.expect(IrToken::LParen)?;
+        let mut payload = vec![];
+        while !self.peek(IrToken::RParen) {
+            if !payload.is_empty() {
+                self.expect(IrToken::Comma)?;
+            }
+            payload.push(self.parse_type()?);
+        }
+        self.next(); // ')'
+        self.module.new_exception_tag(name, payload);
+        Ok(())
+    }
+
+    /// Parse all the type and exception tag definitions preceding a function
+    pub fn parse_definitions(&mut self) -> Result<(), IrParseError> {
+        loop {
+            match self.peek_str(IrToken::Identifier) {
+                Some("type") => { self.parse_type_definition()?; }
+                Some("tag") => self.parse_tag_definition()?,
+                _ => return Ok(())
+            }
+        }
+    }
+
     pub fn parse_function(&mut self) -> Result<Function<'ctx>, IrParseError> {
         self.expect(IrToken::Func)?;
         let func_name = self.expect(IrToken::String)?.strip('"').to_owned();
@@ -332,7 +586,13 @@ impl<'a, 'ctx> IRParser<'a, 'ctx> {
     }
 
     fn parse_type(&mut self) -> Result<Ty<'ctx>, IrParseError> {
-        if self.peek(IrToken::Int32) {
+        if self.peek(IrToken::Int64) {
+            self.next();
+            Ok(self.module.int64t())
+        } else if self.peek(IrToken::UInt64) {
+            self.next();
+            Ok(self.module.uint64t())
+        } else if self.peek(IrToken::Int32) {
             self.next();
             Ok(self.module.int32t())
         } else if self.peek(IrToken::UInt32) {
@@ -353,6 +613,15 @@ impl<'a, 'ctx> IRParser<'a, 'ctx> {
         } else if self.peek(IrToken::Float32) {
             self.next();
             Ok(self.module.float32t())
+        } else if self.peek(IrToken::Float64) {
+            self.next();
+            Ok(self.module.float64t())
+        } else if self.peek(IrToken::V128) {
+            self.next();
+            Ok(self.module.v128t())
+        } else if self.peek(IrToken::ExternRef) {
+            self.next();
+            Ok(self.module.extern_ref_t())
         } else if self.peek(IrToken::Ptr) {
             self.next();
             Ok(self.module.ptr_t())
@@ -391,8 +660,35 @@ impl<'a, 'ctx> IRParser<'a, 'ctx> {
                 fields.push(self.parse_type()?);
                 if self.peek(IrToken::Comma) { self.next(); }
             }
+            self.next(); // '}'
 
             Ok(self.module.intern_type(Type::Struct { fields }))
+        } else if self.peek(IrToken::Union) {
+            // a tagged union type
+            self.next(); // 'union'
+            self.expect(IrToken::LBrace)?;
+            let mut variants = vec![];
+            while !self.peek(IrToken::RBrace) {
+                variants.push(self.parse_type()?);
+                if self.peek(IrToken::Comma) { self.next(); }
+            }
+            self.next(); // '}'
+
+            Ok(self.module.intern_type(Type::TaggedUnion { variants }))
+        } else if self.peek(IrToken::NamedType) {
+            // a named struct type
+            // it's declared if it hasn't been yet, the body may be defined later
+            let name = &self.next().unwrap().1[1..]; // strip the '%'
+            Ok(self.module.declare_named_struct(name))
+        } else if self.peek(IrToken::LBracket) {
+            // an array type
+            self.next(); // '['
+            let elem = self.parse_type()?;
+            self.expect(IrToken::Semicolon)?;
+            let len = self.expect(IrToken::Int)?.parse().unwrap();
+            self.expect(IrToken::RBracket)?;
+
+            Ok(self.module.intern_type(Type::Array { elem, len }))
         } else { 
             Err(IrParseError::GeneralUnexpectedToken) 
         }
@@ -420,5 +716,57 @@ pub enum IrParseError {
     UnexpectedToken { expected: IrToken, got: IrToken },
     GeneralUnexpectedToken,
     MalformedIdentifier { got: String },
-    InvalidInstructionName
-}
\ No newline at end of file
+    InvalidInstructionName,
+    NamedStructRedefinition { ty: String },
+    RecursiveNamedStruct { ty: String }
+}
+#[cfg(test)]
+mod tests {
+    use crate::{irprint::IRPrint, module::Module, ty::Type};
+
+    use super::*;
+
+    #[test]
+    fn named_struct_test() {
+        let mut m = Module::default();
+
+        let source = r#"
+            type %Visitor = opaque
+            type %Node = struct{int32, ptr, (%Node, %Visitor) -> ()}
+            type %Visitor = struct{(%Node) -> int32}
+            type %Other = struct{int32, ptr, (%Node, %Visitor) -> ()}
+        "#;
+        let mut parser = IRParser::new(&mut m, source);
+        let visitor_t = parser.parse_type_definition().unwrap();
+        let node_t = parser.parse_type_definition().unwrap();
+        assert_eq!(parser.parse_type_definition().unwrap(), visitor_t);
+        let other_t = parser.parse_type_definition().unwrap();
+
+        // named structs are nominal, same layout doesn't mean same type
+        assert_ne!(node_t, other_t);
+        assert_eq!(node_t, m.declare_named_struct("Node"));
+        assert_eq!(node_t.struct_fields().unwrap()[2], m.intern_type(Type::Func {
+            args: vec![node_t, visitor_t], ret: vec![]
+        }));
+        assert!(!visitor_t.is_opaque());
+
+        // a body can't be set twice
+        let mut parser = IRParser::new(&mut m, "type %Node = struct{int32}");
+        assert!(matches!(parser.parse_type_definition(), Err(IrParseError::NamedStructRedefinition { ty: _ })));
+
+        // a struct can't contain itself by value, not even through another named struct or an array
+        let mut parser = IRParser::new(&mut m, r#"
+            type %A = opaque
+            type %B = struct{int32, [%A; 2]}
+            type %A = struct{ptr, %B}
+        "#);
+        parser.parse_type_definition().unwrap();
+        parser.parse_type_definition().unwrap();
+        assert!(matches!(parser.parse_type_definition(), Err(IrParseError::RecursiveNamedStruct { ty: _ })));
+
+        let mut s = String::new();
+        m.ir_print(&mut s).unwrap();
+        assert!(s.contains("type %Node = struct{int32, ptr, (%Node, %Visitor) -> ()}"));
+        assert!(s.contains("type %Visitor = struct{(%Node) -> int32}"));
+    }
+}
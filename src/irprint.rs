@@ -1,4 +1,4 @@
-use crate::{instr::{BlockId, BlockTag, Cmp, Function, Instr, InstrBlock, InstrK}, module::{ExternFunction, FuncDef, Functional, Global, Module}, numerics::BitWidthSign, ty::{Ty, Type}};
+use crate::{instr::{AtomicRmwOp, BlockId, BlockTag, Cmp, Function, Instr, InstrBlock, InstrK, VecShape}, module::{ExceptionTag, ExternFunction, FuncDef, Functional, Global, Module}, numerics::BitWidthSign, ty::{Ty, Type}};
 
 pub trait IRPrint {
     fn ir_print(&self, w: &mut dyn std::fmt::Write) -> std::fmt::Result;
@@ -7,6 +7,8 @@ pub trait IRPrint {
 impl<'ctx> IRPrint for Type<'ctx> {
     fn ir_print(&self, w: &mut dyn std::fmt::Write) -> std::fmt::Result {
         match self {
+            Type::Int64 => write!(w, "int64"),
+            Type::UInt64 => write!(w, "uint64"),
             Type::Int32 => write!(w, "int32"),
             Type::UInt32 => write!(w, "uint32"),
             Type::Int16 => write!(w, "int16"),
@@ -14,6 +16,9 @@ impl<'ctx> IRPrint for Type<'ctx> {
             Type::Int8 => write!(w, "int8"),
             Type::UInt8 => write!(w, "uint8"),
             Type::Float32 => write!(w, "float32"),
+            Type::Float64 => write!(w, "float64"),
+            Type::V128 => write!(w, "v128"),
+            Type::ExternRef => write!(w, "externref"),
             Type::Ptr => write!(w, "ptr"),
             Type::Func { args, ret: rets } => {
                 if args.is_empty() {
@@ -42,13 +47,20 @@ impl<'ctx> IRPrint for Type<'ctx> {
                     write!(w, ")")
                 }
             },
-            Type::Struct { fields} => {
-                write!(w, "struct{{")?;
-                for (i, field) in fields.iter().enumerate() {
+            Type::Struct { fields} => print_struct_fields(fields, w),
+            Type::Array { elem, len } => {
+                write!(w, "[")?;
+                elem.ir_print(w)?;
+                write!(w, "; {}]", len)
+            }
+            Type::NamedStruct { name, body: _ } => write!(w, "%{}", name),
+            Type::TaggedUnion { variants } => {
+                write!(w, "union{{")?;
+                for (i, variant) in variants.iter().enumerate() {
                     if i != 0 {
                         write!(w, ", ")?;
                     }
-                    field.ir_print(w)?;
+                    variant.ir_print(w)?;
                 }
                 write!(w, "}}")
             }
@@ -70,15 +82,31 @@ impl<'ctx> IRPrint for Instr<'ctx> {
                 write!(w, " {}", n)
             }
             InstrK::LdFloat(f) => write!(w, "ld.float {}", f),
+            InstrK::LdFloat64(f) => write!(w, "ld.float64 {}", f),
             InstrK::IAdd => write!(w, "iadd"),
             InstrK::ISub => write!(w, "isub"),
             InstrK::IMul => write!(w, "imul"),
             InstrK::IDiv => write!(w, "idiv"),
+            InstrK::IRem => write!(w, "irem"),
+            InstrK::INeg => write!(w, "ineg"),
             InstrK::FAdd => write!(w, "fadd"),
             InstrK::FSub => write!(w, "fsub"),
             InstrK::FMul => write!(w, "fmul"),
             InstrK::FDiv => write!(w, "fdiv"),
-            InstrK::Itof => write!(w, "itof"),
+            InstrK::FMin => write!(w, "fmin"),
+            InstrK::FMax => write!(w, "fmax"),
+            InstrK::FCopysign => write!(w, "fcopysign"),
+            InstrK::FSqrt => write!(w, "fsqrt"),
+            InstrK::FAbs => write!(w, "fabs"),
+            InstrK::FNeg => write!(w, "fneg"),
+            InstrK::FFloor => write!(w, "ffloor"),
+            InstrK::FCeil => write!(w, "fceil"),
+            InstrK::FTrunc => write!(w, "ftrunc"),
+            InstrK::FNearest => write!(w, "fnearest"),
+            InstrK::Itof { float_ty } => {
+                write!(w, "itof to ")?;
+                float_ty.ir_print(w)
+            },
             InstrK::Ftoi { int_ty } => {
                 write!(w, "ftoi to ")?;
                 int_ty.ir_print(w)
@@ -99,6 +127,14 @@ impl<'ctx> IRPrint for Instr<'ctx> {
                 Cmp::Gt => write!(w, "fcmp.gt"),
                 Cmp::Ge => write!(w, "fcmp.ge"),
             },
+            InstrK::VSplat(shape) => write!(w, "vsplat.{}", vec_shape_name(*shape)),
+            InstrK::VExtractLane { shape, lane } => write!(w, "vextract_lane.{} {}", vec_shape_name(*shape), lane),
+            InstrK::VReplaceLane { shape, lane } => write!(w, "vreplace_lane.{} {}", vec_shape_name(*shape), lane),
+            InstrK::VAdd(shape) => write!(w, "vadd.{}", vec_shape_name(*shape)),
+            InstrK::VSub(shape) => write!(w, "vsub.{}", vec_shape_name(*shape)),
+            InstrK::VMul(shape) => write!(w, "vmul.{}", vec_shape_name(*shape)),
+            InstrK::FPromote => write!(w, "fpromote"),
+            InstrK::FDemote => write!(w, "fdemote"),
             InstrK::IConv { target } => {
                 write!(w, "iconv to ")?;
                 target.ir_print(w)
@@ -106,11 +142,17 @@ impl<'ctx> IRPrint for Instr<'ctx> {
             InstrK::Not => write!(w, "not"),
             InstrK::BitAnd => write!(w, "bitand"),
             InstrK::BitOr => write!(w, "bitor"),
+            InstrK::BitXor => write!(w, "bitxor"),
+            InstrK::BitNot => write!(w, "bitnot"),
+            InstrK::Shl => write!(w, "shl"),
+            InstrK::Shr => write!(w, "shr"),
             InstrK::CallDirect { func_name } => write!(w, "call \"{}\"", func_name),
             InstrK::LdLocal { idx } => write!(w, "ld.loc #{}", idx),
             InstrK::StLocal { idx } => write!(w, "st.loc #{}", idx),
             InstrK::LdGlobalFunc { func_name } => write!(w, "ld_glob_func \"{}\"", func_name),
             InstrK::CallIndirect => write!(w, "call indirect"),
+            InstrK::TailCallDirect { func_name } => write!(w, "tail_call \"{}\"", func_name),
+            InstrK::TailCallIndirect => write!(w, "tail_call indirect"),
             InstrK::Bitcast { target } => {
                 write!(w, "bitcast to ")?;
                 target.ir_print(w)
@@ -122,10 +164,56 @@ impl<'ctx> IRPrint for Instr<'ctx> {
                 }
                 Ok(())
             }
+            InstrK::Switch { cases, default } => {
+                write!(w, "switch")?;
+                for case in cases {
+                    write!(w, " b{}", case.id())?
+                }
+                write!(w, " default b{}", default.id())
+            }
+            InstrK::TryCatch { body, catches, catch_all } => {
+                write!(w, "try b{}", body.id())?;
+                for (tag, handler) in catches {
+                    write!(w, " catch \"{}\" b{}", tag, handler.id())?
+                }
+                if let Some(handler) = catch_all {
+                    write!(w, " catch_all b{}", handler.id())?
+                }
+                Ok(())
+            }
+            InstrK::Throw { tag } => write!(w, "throw \"{}\"", tag),
             InstrK::Read { ty } => {
                 write!(w, "read ")?;
                 ty.ir_print(w)
             }
+            InstrK::AtomicRead { ty } => {
+                write!(w, "atomic.read ")?;
+                ty.ir_print(w)
+            }
+            InstrK::AtomicWrite { ty } => {
+                write!(w, "atomic.write ")?;
+                ty.ir_print(w)
+            }
+            InstrK::AtomicRmw { op, ty } => {
+                let op = match op {
+                    AtomicRmwOp::Add => "add",
+                    AtomicRmwOp::Sub => "sub",
+                    AtomicRmwOp::And => "and",
+                    AtomicRmwOp::Or => "or",
+                    AtomicRmwOp::Xchg => "xchg",
+                };
+                write!(w, "atomic.rmw.{} ", op)?;
+                ty.ir_print(w)
+            }
+            InstrK::AtomicCmpxchg { ty } => {
+                write!(w, "atomic.cmpxchg ")?;
+                ty.ir_print(w)
+            }
+            InstrK::AtomicWait { ty } => {
+                write!(w, "atomic.wait ")?;
+                ty.ir_print(w)
+            }
+            InstrK::AtomicNotify => write!(w, "atomic.notify"),
             InstrK::Write { ty } => {
                 write!(w, "write ")?;
                 ty.ir_print(w)
@@ -134,19 +222,53 @@ impl<'ctx> IRPrint for Instr<'ctx> {
                 write!(w, "offset ")?;
                 ty.ir_print(w)
             }
+            InstrK::OffsetConst { ty, n } => {
+                write!(w, "offset_const {} ", n)?;
+                ty.ir_print(w)
+            }
+            InstrK::StackAlloc { ty } => {
+                write!(w, "stack_alloc ")?;
+                ty.ir_print(w)
+            }
             InstrK::GetFieldPtr { struct_ty, field_idx } => {
                 write!(w, "get_field_ptr {} ", field_idx)?;
                 struct_ty.ir_print(w)
             }
+            InstrK::GetTag { union_ty } => {
+                write!(w, "get_tag ")?;
+                union_ty.ir_print(w)
+            }
+            InstrK::SetTag { union_ty, variant_idx } => {
+                write!(w, "set_tag {} ", variant_idx)?;
+                union_ty.ir_print(w)
+            }
+            InstrK::GetVariantPtr { union_ty, variant_idx } => {
+                write!(w, "get_variant_ptr {} ", variant_idx)?;
+                union_ty.ir_print(w)
+            }
+            InstrK::GetElemPtr { array_ty } => {
+                write!(w, "get_elem_ptr ")?;
+                array_ty.ir_print(w)
+            }
             InstrK::Discard => write!(w, "discard"),
+            InstrK::Dup => write!(w, "dup"),
+            InstrK::Swap => write!(w, "swap"),
+            InstrK::Over => write!(w, "over"),
+            InstrK::Select => write!(w, "select"),
             InstrK::Return => write!(w, "return"),
             InstrK::MemorySize => write!(w, "memory.size"),
             InstrK::MemoryGrow => write!(w, "memory.grow"),
+            InstrK::MemCopy => write!(w, "memory.copy"),
+            InstrK::MemFill => write!(w, "memory.fill"),
             InstrK::LdGlobal(name) => write!(w, "ld.global \"{}\"", name),
             InstrK::StGlobal(name) => write!(w, "st.global \"{}\"", name),
+            InstrK::LdNullRef => write!(w, "ld.null_ref"),
+            InstrK::IsNullRef => write!(w, "is_null_ref"),
             InstrK::Fail => write!(w, "fail"),
             InstrK::Loop(body) => write!(w, "loop b{}", body.id()),
             InstrK::Break => write!(w, "break"),
+            InstrK::BreakLoop(body) => write!(w, "break_loop b{}", body.id()),
+            InstrK::ContinueLoop(body) => write!(w, "continue_loop b{}", body.id()),
             InstrK::LdStaticMemPtr(_) => unimplemented!(), // FIXME (also fix in irparse)
             InstrK::Intrinsic(_) => write!(w, "intrinsic ?"), // TODO
         }?;
@@ -175,6 +297,9 @@ impl<'ctx> IRPrint for InstrBlock<'ctx> {
             BlockTag::Main => "main",
             BlockTag::IfElse => "if_else",
             BlockTag::Loop => "loop",
+            BlockTag::Switch => "switch",
+            BlockTag::Try => "try",
+            BlockTag::Catch => "catch",
         })?;
 
         if !self.meta.is_empty() {
@@ -224,6 +349,8 @@ impl<'ctx> IRPrint for Global<'ctx> {
         write!(w, "global \"{}\" = ", self.name)?;
         if self.is_int() {
             write!(w, "int32 {}", self.get_int_value())?;
+        } else if self.is_null_ref() {
+            write!(w, "externref null")?;
         } else {
             write!(w, "float32 {}", self.get_float_value())?;
         }
@@ -231,11 +358,61 @@ impl<'ctx> IRPrint for Global<'ctx> {
     }
 }
 
+impl<'ctx> IRPrint for ExceptionTag<'ctx> {
+    fn ir_print(&self, w: &mut dyn std::fmt::Write) -> std::fmt::Result {
+        write!(w, "tag \"{}\" (", self.name)?;
+        for (i, ty) in self.payload().iter().enumerate() {
+            if i != 0 {
+                write!(w, ", ")?;
+            }
+            ty.ir_print(w)?;
+        }
+        writeln!(w, ")")
+    }
+}
+
+fn vec_shape_name(shape: VecShape) -> &'static str {
+    match shape {
+        VecShape::I32x4 => "i32x4",
+        VecShape::F32x4 => "f32x4",
+    }
+}
+
+fn print_struct_fields(fields: &[Ty<'_>], w: &mut dyn std::fmt::Write) -> std::fmt::Result {
+    write!(w, "struct{{")?;
+    for (i, field) in fields.iter().enumerate() {
+        if i != 0 {
+            write!(w, ", ")?;
+        }
+        field.ir_print(w)?;
+    }
+    write!(w, "}}")
+}
+
 impl<'ctx> IRPrint for Module<'ctx> {
     fn ir_print(&self, w: &mut dyn std::fmt::Write) -> std::fmt::Result {
+        // named struct definitions
+        let mut result = Ok(());
+        self.for_all_types_iter(|t| {
+            if let Type::NamedStruct { name, body } = &*t {
+                result = result.and_then(|_| {
+                    write!(w, "type %{} = ", name)?;
+                    match body.get() {
+                        Some(fields) => print_struct_fields(fields, w)?,
+                        None => write!(w, "opaque")?
+                    }
+                    writeln!(w)
+                });
+            }
+        });
+        result?;
+        writeln!(w)?;
         for g in self.globals_iter() {
             g.ir_print(w)?;
         }
+        for t in self.exception_tags_iter() {
+            t.ir_print(w)?;
+        }
         writeln!(w)?;
         for f in self.functions_iter() {
             f.ir_print(w)?;
@@ -247,6 +424,8 @@ impl<'ctx> IRPrint for Module<'ctx> {
 impl IRPrint for BitWidthSign {
     fn ir_print(&self, w: &mut dyn std::fmt::Write) -> std::fmt::Result {
         match self {
+            BitWidthSign::S64 => write!(w, "s64"),
+            BitWidthSign::U64 => write!(w, "u64"),
             BitWidthSign::S32 => write!(w, "s32"),
             BitWidthSign::U32 => write!(w, "u32"),
             BitWidthSign::S16 => write!(w, "s16"),
@@ -0,0 +1,120 @@
+use std::{any::TypeId, cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{abi::{Abi, struct_calc_algorithm, union_calc_algorithm}, ty::{Ty, Type}};
+
+/// The memory layout of a type
+#[derive(Debug, PartialEq, Eq)]
+pub struct TypeLayout {
+    /// The size of the type in bytes
+    pub size: usize,
+    /// The alignment of the type, expressed as an exponent of two
+    /// (see [`Abi::type_alignment`])
+    pub alignment: usize,
+    /// For structs, the offsets at which the fields start.
+    /// For tagged unions, the offsets at which the payloads of the variants start
+    /// (which is the same offset for all of them).
+    ///
+    /// Empty for all other types.
+    pub field_offsets: Vec<usize>,
+}
+
+impl TypeLayout {
+    /// The size rounded up to the alignment, which is the distance
+    /// between two consecutive elements of an array of this type
+    pub fn stride(&self) -> usize {
+        let align = 1 << self.alignment;
+        self.size.div_ceil(align) * align
+    }
+}
+
+/// Computes the layouts of types and remembers them,
+/// so that every type's layout is only computed once.
+///
+/// Every IR module has its own cache, see [`Module::layout_of`](crate::module::Module::layout_of)
+#[derive(Default)]
+pub struct LayoutCache<'ctx> {
+    /// Layouts depend on the ABI, so the ABI is a part of the key
+    layouts: RefCell<HashMap<(TypeId, Ty<'ctx>), Rc<TypeLayout>>>,
+}
+
+impl<'ctx> LayoutCache<'ctx> {
+    /// Get the layout of a type as defined by the ABI `A`.
+    ///
+    /// Panics if the type doesn't have a layout, that is if it's an opaque named struct
+    /// or a type which can't be stored in memory.
+    pub fn layout_of<A: Abi>(&self, ty: Ty<'ctx>) -> Rc<TypeLayout> {
+        let key = (TypeId::of::<A>(), ty);
+        if let Some(layout) = self.layouts.borrow().get(&key) {
+            return layout.clone()
+        }
+
+        // the borrow must not be held here, the layouts of the inner types are queried recursively
+        let layout = Rc::new(self.calc_layout::<A>(ty));
+        self.layouts.borrow_mut().insert(key, layout.clone());
+        layout
+    }
+
+    fn calc_layout<A: Abi>(&self, ty: Ty<'ctx>) -> TypeLayout {
+        let size_align = |t: Ty<'ctx>| {
+            let layout = self.layout_of::<A>(t);
+            (layout.size, layout.alignment)
+        };
+
+        match &*ty {
+            Type::Struct { fields: _ } | Type::NamedStruct { name: _, body: _ } => {
+                let fields = ty.struct_fields().expect("Opaque named structs don't have a layout");
+                let (field_offsets, size, alignment) = struct_calc_algorithm(fields, size_align);
+                TypeLayout { size, alignment, field_offsets }
+            }
+            Type::Array { elem, len } => {
+                let elem = self.layout_of::<A>(*elem);
+                TypeLayout { size: elem.stride() * len, alignment: elem.alignment, field_offsets: vec![] }
+            }
+            Type::TaggedUnion { variants } => {
+                let (payload_offset, size, alignment) = union_calc_algorithm(variants, size_align);
+                TypeLayout { size, alignment, field_offsets: vec![payload_offset; variants.len()] }
+            }
+            _ => TypeLayout { size: A::type_sizeof(ty), alignment: A::type_alignment(ty), field_offsets: vec![] }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::{abi::{Abi, Wasm32Abi}, module::Module, ty::Type};
+
+    #[test]
+    pub fn layout_cache_test() {
+        let m = Module::default();
+
+        let struct_t1 = m.intern_type(Type::Struct { fields: vec![
+            m.int16t(), m.int32t(), m.int8t(), m.uint8t()
+        ] });
+        let array_t1 = m.intern_type(Type::Array { elem: struct_t1, len: 3 });
+        let struct_t2 = m.intern_type(Type::Struct { fields: vec![
+            m.int8t(), array_t1, m.float64t()
+        ] });
+        let union_t1 = m.intern_type(Type::TaggedUnion { variants: vec![
+            m.int8t(), struct_t2
+        ] });
+
+        // the cached layouts agree with the ABI
+        for ty in [m.int8t(), m.float64t(), struct_t1, array_t1, struct_t2, union_t1] {
+            assert_eq!(m.sizeof::<Wasm32Abi>(ty), Wasm32Abi::type_sizeof(ty));
+            assert_eq!(m.alignment::<Wasm32Abi>(ty), Wasm32Abi::type_alignment(ty));
+        }
+
+        assert_eq!(m.layout_of::<Wasm32Abi>(struct_t1).field_offsets, vec![0, 4, 8, 9]);
+        assert_eq!(m.layout_of::<Wasm32Abi>(struct_t1).stride(), 12);
+        assert_eq!(m.sizeof::<Wasm32Abi>(array_t1), 36);
+        // int8, 3-byte padding, array, 4-byte padding, float64
+        assert_eq!(m.layout_of::<Wasm32Abi>(struct_t2).field_offsets, vec![0, 4, 40]);
+        assert_eq!(m.field_offset::<Wasm32Abi>(union_t1, 0), 8);
+        assert_eq!(m.field_offset::<Wasm32Abi>(union_t1, 1), 8);
+
+        // the second query doesn't compute the layout again
+        assert!(Rc::ptr_eq(&m.layout_of::<Wasm32Abi>(struct_t2), &m.layout_of::<Wasm32Abi>(struct_t2)));
+    }
+}
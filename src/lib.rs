@@ -12,6 +12,7 @@ pub mod irprint;
 pub mod correct;
 pub mod cf_verify;
 pub mod abi;
+pub mod layout;
 pub mod passes;
 #[cfg(feature = "c-api")]
 pub mod c_api;
@@ -19,6 +20,7 @@ pub mod intrinsic;
 #[cfg(feature = "ir-parse")]
 pub mod irparse;
 pub mod numerics;
+pub(crate) mod atomics;
 pub mod staticmem;
 
 /// Run the standard pipeline of passes on an IR module
@@ -39,7 +41,7 @@ pub fn pipeline_verify_module(module: &mut module::Module<'_>) {
 ///
 /// Panics if any kind of error happens while compiling/verifying etc.
 pub fn pipeline_compile_module_to_wasm(mut module: module::Module<'_>, opt: bool) -> Vec<u8> {
-    use pass::{MutableFunctionPass, FunctionPass};
+    use pass::FunctionPass;
 
     module.do_mut_pass(&mut correct::CorrectionPass{}).unwrap();
     module.do_mut_pass(&mut cf_verify::ControlFlowVerifier{}).unwrap();
@@ -49,14 +51,34 @@ pub fn pipeline_compile_module_to_wasm(mut module: module::Module<'_>, opt: bool
     if opt {
         for i in 0..module.function_count() {
             if module.function_get_by_idx(i).is_extern() { continue }
+            let result = passes::SelectOpt{}.visit_function(&module, module.function_get_by_idx(i).unwrap_local()).unwrap();
+            rewrite_function(&mut module, i, result);
+
             let result = passes::PeepholeOpt{}.visit_function(&module, module.function_get_by_idx(i).unwrap_local()).unwrap();
-            let mut rewrite_pass = passes::InstrRewritePass::new(i, result).unwrap();
-            rewrite_pass.visit_function(&module, module.function_get_by_idx(i).unwrap_local()).unwrap();
-            rewrite_pass.mutate_function(module.function_get_mut_by_idx(i).unwrap_local_mut(), ()).unwrap();
+            rewrite_function(&mut module, i, result);
+
+            // After the peephole optimization, which can turn indirect calls into direct ones
+            if module.conf.enable_tail_calls {
+                let result = passes::TailCallOpt{}.visit_function(&module, module.function_get_by_idx(i).unwrap_local()).unwrap();
+                rewrite_function(&mut module, i, result);
+            }
         }
     }
 
     let mut e: emit::WasmEmitter<abi::Wasm32Abi> = emit::WasmEmitter::new();
     module.do_pass(&mut e).unwrap();
     e.finish()
+}
+
+/// Apply the instruction rewrites computed by an optimization to the function with index `i`
+#[cfg(feature = "opt")]
+fn rewrite_function<'ctx>(
+    module: &mut module::Module<'ctx>,
+    i: usize,
+    modifications: std::collections::HashMap<instr::BlockId, Vec<passes::BlobRewriteData<'ctx>>>) {
+    use pass::MutableFunctionPass;
+
+    let mut rewrite_pass = passes::InstrRewritePass::new(i, modifications).unwrap();
+    rewrite_pass.visit_function(module, module.function_get_by_idx(i).unwrap_local()).unwrap();
+    rewrite_pass.mutate_function(module.function_get_mut_by_idx(i).unwrap_local_mut(), ()).unwrap();
 }
\ No newline at end of file
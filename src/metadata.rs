@@ -14,6 +14,8 @@ macro_rules! key {
     ("parent") => { crate::metadata::Key(2) };
     ("bws") => { crate::metadata::Key(3) };
     ("innermost_loop_distance") => { crate::metadata::Key(4) };
+    ("innermost_loop") => { crate::metadata::Key(5) };
+    ("ty2") => { crate::metadata::Key(6) };
 }
 
 // The opposite of the macro
@@ -25,6 +27,8 @@ impl From<&Key> for &'static str {
             2 => "parent",
             3 => "bws",
             4 => "innermost_loop_distance",
+            5 => "innermost_loop",
+            6 => "ty2",
             _ => unreachable!()
         }
     }
@@ -1,9 +1,9 @@
-use std::cell::{RefCell};
+use std::{cell::RefCell, rc::Rc};
 
 use indexmap::IndexMap;
 use libintern::Interner;
 
-use crate::{instr::Function, irprint::IRPrint, pass::{FunctionPass, MutableFunctionPass}, staticmem::{SMItem, SMItemRef, StaticMemory}, ty::{Ty, Type}};
+use crate::{abi::Abi, instr::Function, irprint::IRPrint, layout::{LayoutCache, TypeLayout}, pass::{FunctionPass, MutableFunctionPass}, staticmem::{SMItem, SMItemRef, StaticMemory}, ty::{NamedStructBody, Ty, Type}};
 
 pub struct Module<'ctx> {
     // this is not true anymore:
@@ -13,35 +13,91 @@ pub struct Module<'ctx> {
     // IndexMap ensures the functions (and globals) have a constant index (ordering)
     functions: IndexMap<String, FuncDef<'ctx>>,
     globals: IndexMap<String, Global<'ctx>>,
+    exception_tags: IndexMap<String, ExceptionTag<'ctx>>,
     /// We cache Ty<'ctx> of primitive types for faster access
     primitive_types_cache: PrimitiveTypeCache<'ctx>,
     /// Some configuration of the result webassembly module
     pub conf: WasmModuleConf,
     /// A module may or may not have static memory
     static_mem: Option<StaticMemory>,
+    /// Memory layouts of types, computed on demand
+    layout_cache: LayoutCache<'ctx>,
 }
 
 /// Configuration of the webassembly module
 pub struct WasmModuleConf {
     /// The initial WebAssembly memory size in units of pages
     pub initial_memory_size: u32,
+    /// The maximum WebAssembly memory size in units of pages, if any
+    pub maximum_memory_size: Option<u32>,
     /// If true, the Float-to-int conversions will be saturating
     /// Otherwise, they will trap on unexpected values
     ///
     /// For more details, see the WebAssembly documentation on `iNN.trunc_fNN` and `iNN.trunc_sat_fNN`.
     pub use_saturating_ftoi: bool,
+    /// If true, the `v128` type and vector instructions may be used.
+    ///
+    /// This requires the WebAssembly SIMD proposal to be supported by the runtime.
+    pub enable_simd: bool,
+    /// If true, `MemCopy` and `MemFill` compile to the `memory.copy` and `memory.fill` instructions.
+    /// Otherwise, they compile to a loop copying (or setting) one byte at a time.
+    ///
+    /// This requires the WebAssembly bulk memory proposal to be supported by the runtime.
+    pub enable_bulk_memory: bool,
+    /// If true, the memory is shared between threads (web workers)
+    /// and the atomic instructions may be used.
+    ///
+    /// Shared memory must have a maximum size. It's imported as `env.memory`,
+    /// so that the instances on all the threads use the same memory,
+    /// and the static memory is initialized only once by the start function.
+    /// Every thread needs its own shadow stack, so if there is one, the stack pointer
+    /// is imported as the mutable `i32` global `env.__stack_pointer`, which every instance
+    /// must initialize to the end of its own stack.
+    /// This requires the WebAssembly threads proposal to be supported by the runtime.
+    pub shared_memory: bool,
+    /// If true, the tail call instructions may be used
+    /// and when optimizing, calls directly followed by a return are replaced by tail calls.
+    ///
+    /// This requires the WebAssembly tail call proposal to be supported by the runtime.
+    pub enable_tail_calls: bool,
+    /// If true, exception tags, the `Throw` and `TryCatch` instructions may be used.
+    ///
+    /// This requires the WebAssembly exception handling proposal to be supported by the runtime.
+    pub enable_exceptions: bool,
+    /// The size of the shadow stack in bytes, which holds the memory allocated by `StackAlloc`
+    /// and the results of calls to functions returning a large struct.
+    ///
+    /// The stack is placed after the static memory and grows down,
+    /// the initial memory must be large enough to hold both, otherwise the compilation fails.
+    /// Unused with shared memory, where the stacks are allocated by the host.
+    pub stack_size: u32,
 }
 
 impl Default for WasmModuleConf {
     fn default() -> Self {
-        WasmModuleConf { initial_memory_size: 1, use_saturating_ftoi: true }
+        WasmModuleConf {
+            initial_memory_size: 1,
+            maximum_memory_size: None,
+            use_saturating_ftoi: true,
+            enable_simd: false,
+            enable_bulk_memory: false,
+            shared_memory: false,
+            enable_tail_calls: false,
+            enable_exceptions: false,
+            stack_size: 16 * 1024
+        }
     }
 }
 
 struct PrimitiveTypeCache<'ctx> {
+    int64: Ty<'ctx>,
+    uint64: Ty<'ctx>,
     int32: Ty<'ctx>,
     uint32: Ty<'ctx>,
     float32: Ty<'ctx>,
+    float64: Ty<'ctx>,
+    v128: Ty<'ctx>,
+    extern_ref: Ty<'ctx>,
     ptr: Ty<'ctx>,
     int16: Ty<'ctx>,
     uint16: Ty<'ctx>,
@@ -59,9 +115,14 @@ impl<'ctx> Module<'ctx> {
     pub fn new(wasm_module_conf: WasmModuleConf) -> Self {
         let mut type_ctx = Interner::new();
         let cache = PrimitiveTypeCache {
+            int64: type_ctx.intern(Type::Int64),
+            uint64: type_ctx.intern(Type::UInt64),
             int32: type_ctx.intern(Type::Int32),
             uint32: type_ctx.intern(Type::UInt32),
             float32: type_ctx.intern(Type::Float32),
+            float64: type_ctx.intern(Type::Float64),
+            v128: type_ctx.intern(Type::V128),
+            extern_ref: type_ctx.intern(Type::ExternRef),
             ptr: type_ctx.intern(Type::Ptr),
             int16: type_ctx.intern(Type::Int16),
             uint16: type_ctx.intern(Type::UInt16),
@@ -72,9 +133,11 @@ impl<'ctx> Module<'ctx> {
             type_ctx: RefCell::new(type_ctx),
             functions: IndexMap::new(),
             globals: IndexMap::new(),
+            exception_tags: IndexMap::new(),
             primitive_types_cache: cache,
             conf: wasm_module_conf,
-            static_mem: None
+            static_mem: None,
+            layout_cache: LayoutCache::default()
         }
     }
 
@@ -82,6 +145,58 @@ impl<'ctx> Module<'ctx> {
         self.type_ctx.borrow_mut().intern(ty)
     }
 
+    /// Declare a named struct type without a body.
+    ///
+    /// If a named struct with this name was already declared, the same type is returned.
+    pub fn declare_named_struct(&self, name: &str) -> Ty<'ctx> {
+        self.intern_type(Type::NamedStruct { name: name.to_owned(), body: NamedStructBody::default() })
+    }
+
+    /// Set the fields of a named struct type declared with [`Module::declare_named_struct`].
+    ///
+    /// A named struct can't contain itself by value, directly or through other types,
+    /// it can only refer to itself through a pointer.
+    ///
+    /// # Panics
+    /// Panics if the type is not a named struct, if its body was already set
+    /// or if any of the fields contains the struct itself (see [`Type::contains_by_value`]).
+    pub fn set_named_struct_body(&self, ty: Ty<'ctx>, fields: Vec<Ty<'ctx>>) {
+        match &*ty {
+            Type::NamedStruct { name, body: _ } if fields.iter().any(|f| *f == ty || f.contains_by_value(ty)) =>
+                panic!("The named struct {} contains itself by value", name),
+            Type::NamedStruct { name, body } => if !body.set(fields) {
+                panic!("The body of the named struct {} was already set", name)
+            },
+            _ => panic!("set_named_struct_body called on a non-named-struct type")
+        }
+    }
+
+    /// Get the memory layout of a type, as defined by the ABI `A`.
+    ///
+    /// Layouts are cached, so only the first query of a type computes its layout.
+    ///
+    /// Panics if the type doesn't have a layout, that is if it's an opaque named struct
+    /// or a type which can't be stored in memory.
+    pub fn layout_of<A: Abi>(&self, ty: Ty<'ctx>) -> Rc<TypeLayout> {
+        self.layout_cache.layout_of::<A>(ty)
+    }
+
+    /// The size of a type in bytes
+    pub fn sizeof<A: Abi>(&self, ty: Ty<'ctx>) -> usize {
+        self.layout_of::<A>(ty).size
+    }
+
+    /// The alignment of a type, expressed as an exponent of two
+    pub fn alignment<A: Abi>(&self, ty: Ty<'ctx>) -> usize {
+        self.layout_of::<A>(ty).alignment
+    }
+
+    /// The offset of the Nth field of a struct type
+    /// (or of the payload of the Nth variant of a tagged union)
+    pub fn field_offset<A: Abi>(&self, ty: Ty<'ctx>, field_idx: usize) -> usize {
+        self.layout_of::<A>(ty).field_offsets[field_idx]
+    }
+
     pub fn for_all_types_iter(&self, mut f: impl FnMut(Ty<'ctx>))  {
         for t in self.type_ctx.borrow().iter() {
             f(t)
@@ -130,6 +245,14 @@ impl<'ctx> Module<'ctx> {
         print!("{}", s);
     }
 
+    pub fn int64t(&self) -> Ty<'ctx> {
+        self.primitive_types_cache.int64
+    }
+
+    pub fn uint64t(&self) -> Ty<'ctx> {
+        self.primitive_types_cache.uint64
+    }
+
     pub fn int32t(&self) -> Ty<'ctx> {
         self.primitive_types_cache.int32
     }
@@ -158,6 +281,18 @@ impl<'ctx> Module<'ctx> {
         self.primitive_types_cache.float32
     }
 
+    pub fn float64t(&self) -> Ty<'ctx> {
+        self.primitive_types_cache.float64
+    }
+
+    pub fn v128t(&self) -> Ty<'ctx> {
+        self.primitive_types_cache.v128
+    }
+
+    pub fn extern_ref_t(&self) -> Ty<'ctx> {
+        self.primitive_types_cache.extern_ref
+    }
+
     pub fn ptr_t(&self) -> Ty<'ctx> {
         self.primitive_types_cache.ptr
     }
@@ -197,6 +332,12 @@ impl<'ctx> Module<'ctx> {
         self.new_global(global)
     }
 
+    /// Create a new global of the `externref` type, initialized to null
+    pub fn new_extern_ref_global(&mut self, name: String) {
+        let global = Global { name, ty: self.extern_ref_t(), value: GlobalValueInit::NullRef, idx: 0 };
+        self.new_global(global)
+    }
+
     fn new_global(&mut self, mut g: Global<'ctx>) {
         let idx = self.globals.len();
         g.idx = idx;
@@ -211,6 +352,26 @@ impl<'ctx> Module<'ctx> {
         self.globals.get(name)
     }
 
+    /// Create a new exception tag, the exceptions thrown with it carry values of the `payload` types
+    pub fn new_exception_tag(&mut self, name: String, payload: Vec<Ty<'ctx>>) {
+        if self.exception_tags.contains_key(&name) {
+            panic!("Multiple exception tags with the same name") // TODO better handle
+        }
+        // WebAssembly describes the tag with a function type with no results,
+        // interning it makes sure the type gets emitted
+        let ty = self.intern_type(Type::Func { args: payload, ret: vec![] });
+        let idx = self.exception_tags.len();
+        self.exception_tags.insert(name.clone(), ExceptionTag { name, ty, idx });
+    }
+
+    pub fn exception_tags_iter(&self) -> impl ExactSizeIterator<Item = &ExceptionTag<'ctx>> {
+        self.exception_tags.values()
+    }
+
+    pub fn get_exception_tag(&self, name: &str) -> Option<&ExceptionTag<'ctx>> {
+        self.exception_tags.get(name)
+    }
+
     /// Add a new external function definition.
     /// 
     /// **All external functions must be defined before ANY local functions**.
@@ -264,6 +425,10 @@ impl<'ctx> Global<'ctx> {
         matches!(self.value, GlobalValueInit::ConstFloat(_))
     }
 
+    pub(crate) fn is_null_ref(&self) -> bool {
+        matches!(self.value, GlobalValueInit::NullRef)
+    }
+
     pub(crate) fn get_int_value(&self) -> i32 {
         match self.value {
             GlobalValueInit::ConstInt(x) => x,
@@ -281,9 +446,30 @@ impl<'ctx> Global<'ctx> {
     pub(crate) fn idx(&self) -> usize { self.idx }
 }
 
+pub struct ExceptionTag<'ctx> {
+    pub(crate) name: String,
+    /// A function type whose arguments are the payload types
+    pub(crate) ty: Ty<'ctx>,
+    /// The tag's index, assigned by the module
+    idx: usize
+}
+
+impl<'ctx> ExceptionTag<'ctx> {
+    /// The types of the values an exception with this tag carries
+    pub fn payload(&self) -> &Vec<Ty<'ctx>> {
+        match &*self.ty {
+            Type::Func { args, ret: _ } => args,
+            _ => unreachable!()
+        }
+    }
+
+    pub(crate) fn idx(&self) -> usize { self.idx }
+}
+
 enum GlobalValueInit {
     ConstInt(i32),
     ConstFloat(f32),
+    NullRef,
     // TODO: ConstFunc (and other types)
 }
 
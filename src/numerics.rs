@@ -4,12 +4,14 @@
 
 use wasm_encoder::{Instruction, MemArg};
 
-use crate::{abi::Abi, instr::{Cmp, Instr, InstrK}, ty::Ty};
+use crate::{abi::Abi, instr::{Cmp, Instr, InstrK}, ty::{Ty, Type}};
 
 /// The metadata `bws` (BitWidth and Sign) associated
 /// with numeric instructions is of this type.
 #[derive(Clone, Copy)]
 pub(crate) enum BitWidthSign {
+    S64,
+    U64,
     S32,
     U32,
     S16,
@@ -20,38 +22,50 @@ pub(crate) enum BitWidthSign {
 
 impl BitWidthSign {
     fn is_unsigned(self) -> bool {
-        matches!(self, BitWidthSign::U32 | BitWidthSign::U16 | BitWidthSign::U8)
+        matches!(self, BitWidthSign::U64 | BitWidthSign::U32 | BitWidthSign::U16 | BitWidthSign::U8)
+    }
+
+    /// 64-bit integers are the only ones represented as `i64` in WebAssembly
+    fn is_64(self) -> bool {
+        matches!(self, BitWidthSign::S64 | BitWidthSign::U64)
     }
 }
 
 /// Emit WASM instructions for numeric IR instructions
 ///
 /// Based on the table(s) from the *Numeric* draft
-pub(crate) fn emit_numeric_instr<'a, A: Abi>(kind: &InstrK, bws: BitWidthSign, use_saturating_ftoi: bool) -> Vec<Instruction<'a>> {
+pub(crate) fn emit_numeric_instr<'a, A: Abi>(instr: &Instr, bws: BitWidthSign, use_saturating_ftoi: bool) -> Vec<Instruction<'a>> {
+    let kind = &instr.kind;
     match kind {
-        InstrK::IAdd | InstrK::ISub | InstrK::IMul => {
-            // these three instruction all compile down to:
-            // the-core-instr (iadd,isub,imul)
+        InstrK::IAdd | InstrK::ISub | InstrK::IMul | InstrK::Shl => {
+            // these instructions all compile down to:
+            // the-core-instr (iadd,isub,imul,shl)
             // the "and" idiom for small unsigned types
             // the "shift" idiom for small signed types
-            let core = match kind {
-                InstrK::IAdd => Instruction::I32Add,
-                InstrK::ISub => Instruction::I32Sub,
-                InstrK::IMul => Instruction::I32Mul,
+            let core = match (kind, bws.is_64()) {
+                (InstrK::IAdd, false) => Instruction::I32Add,
+                (InstrK::ISub, false) => Instruction::I32Sub,
+                (InstrK::IMul, false) => Instruction::I32Mul,
+                (InstrK::Shl, false) => Instruction::I32Shl,
+                (InstrK::IAdd, true) => Instruction::I64Add,
+                (InstrK::ISub, true) => Instruction::I64Sub,
+                (InstrK::IMul, true) => Instruction::I64Mul,
+                (InstrK::Shl, true) => Instruction::I64Shl,
                 _ => unreachable!()
             };
-            match bws {
-                BitWidthSign::S32 | BitWidthSign::U32 => {
-                    // no additional instructions for int32, uint32
-                    vec![core]
-                }
-                BitWidthSign::U16 => and(core, 65536),
-                BitWidthSign::S16 => shift(core, 16),
-                BitWidthSign::U8 => and(core, 255),
-                BitWidthSign::S8 => shift(core, 24),
-            }
+            wrap(core, bws)
+        },
+        // -x is the same as x * (-1), which wraps the same way as IMul
+        InstrK::INeg => if bws.is_64() {
+            vec![Instruction::I64Const(-1), Instruction::I64Mul]
+        } else {
+            let mut instrs = vec![Instruction::I32Const(-1)];
+            instrs.extend(wrap(Instruction::I32Mul, bws));
+            instrs
         },
         InstrK::IDiv => match bws {
+            BitWidthSign::U64 => vec![Instruction::I64DivU],
+            BitWidthSign::S64 => vec![Instruction::I64DivS],
             BitWidthSign::U32 | BitWidthSign::U16 | BitWidthSign::U8 => {
                 vec![Instruction::I32DivU]
             },
@@ -59,20 +73,107 @@ pub(crate) fn emit_numeric_instr<'a, A: Abi>(kind: &InstrK, bws: BitWidthSign, u
             BitWidthSign::S16 => shift(Instruction::I32DivS, 16),
             BitWidthSign::S8 => shift(Instruction::I32DivS, 24),
         }
-        InstrK::Itof => 
-            if bws.is_unsigned() {
-                vec![Instruction::F32ConvertI32U]
+        // The remainder is always smaller than the divisor, so it never overflows
+        InstrK::IRem => match bws {
+            BitWidthSign::U64 => vec![Instruction::I64RemU],
+            BitWidthSign::S64 => vec![Instruction::I64RemS],
+            BitWidthSign::U32 | BitWidthSign::U16 | BitWidthSign::U8 => vec![Instruction::I32RemU],
+            BitWidthSign::S32 | BitWidthSign::S16 | BitWidthSign::S8 => vec![Instruction::I32RemS],
+        }
+        // Small types are kept sign- or zero-extended to 32 bits,
+        // shifting right preserves that
+        InstrK::Shr => match bws {
+            BitWidthSign::U64 => vec![Instruction::I64ShrU],
+            BitWidthSign::S64 => vec![Instruction::I64ShrS],
+            BitWidthSign::U32 | BitWidthSign::U16 | BitWidthSign::U8 => vec![Instruction::I32ShrU],
+            BitWidthSign::S32 | BitWidthSign::S16 | BitWidthSign::S8 => vec![Instruction::I32ShrS],
+        }
+        InstrK::Itof { float_ty } => match (matches!(&**float_ty, Type::Float64), bws.is_64(), bws.is_unsigned()) {
+            (false, false, false) => vec![Instruction::F32ConvertI32S],
+            (false, false, true) => vec![Instruction::F32ConvertI32U],
+            (false, true, false) => vec![Instruction::F32ConvertI64S],
+            (false, true, true) => vec![Instruction::F32ConvertI64U],
+            (true, false, false) => vec![Instruction::F64ConvertI32S],
+            (true, false, true) => vec![Instruction::F64ConvertI32U],
+            (true, true, false) => vec![Instruction::F64ConvertI64S],
+            (true, true, true) => vec![Instruction::F64ConvertI64U],
+        },
+        InstrK::Ftoi { int_ty: _ } => {
+            // meta["ty"] (the source float type) injected by the Verifier
+            let from_f64 = matches!(&*instr.meta.retrieve_ty(key!("ty")).unwrap(), Type::Float64);
+            let core = if use_saturating_ftoi {
+                match (from_f64, bws.is_64(), bws.is_unsigned()) {
+                    (false, false, false) => Instruction::I32TruncSatF32S,
+                    (false, false, true) => Instruction::I32TruncSatF32U,
+                    (false, true, false) => Instruction::I64TruncSatF32S,
+                    (false, true, true) => Instruction::I64TruncSatF32U,
+                    (true, false, false) => Instruction::I32TruncSatF64S,
+                    (true, false, true) => Instruction::I32TruncSatF64U,
+                    (true, true, false) => Instruction::I64TruncSatF64S,
+                    (true, true, true) => Instruction::I64TruncSatF64U,
+                }
+            } else {
+                match (from_f64, bws.is_64(), bws.is_unsigned()) {
+                    (false, false, false) => Instruction::I32TruncF32S,
+                    (false, false, true) => Instruction::I32TruncF32U,
+                    (false, true, false) => Instruction::I64TruncF32S,
+                    (false, true, true) => Instruction::I64TruncF32U,
+                    (true, false, false) => Instruction::I32TruncF64S,
+                    (true, false, true) => Instruction::I32TruncF64U,
+                    (true, true, false) => Instruction::I64TruncF64S,
+                    (true, true, true) => Instruction::I64TruncF64U,
+                }
+            };
+            match bws {
+                BitWidthSign::S64 | BitWidthSign::U64 |
+                BitWidthSign::S32 | BitWidthSign::U32 => vec![core],
+                // The conversion to 32 bits saturates at the bounds of int32/uint32,
+                // so for small types the value is clamped to their bounds first
+                _ if use_saturating_ftoi => {
+                    let (min, max) = match bws {
+                        BitWidthSign::S16 => (i16::MIN as f64, i16::MAX as f64),
+                        BitWidthSign::U16 => (0.0, u16::MAX as f64),
+                        BitWidthSign::S8 => (i8::MIN as f64, i8::MAX as f64),
+                        BitWidthSign::U8 => (0.0, u8::MAX as f64),
+                        _ => unreachable!()
+                    };
+                    // NaN stays NaN after the clamping, which then converts to zero
+                    if from_f64 {
+                        vec![Instruction::F64Const(max), Instruction::F64Min,
+                             Instruction::F64Const(min), Instruction::F64Max, core]
+                    } else {
+                        vec![Instruction::F32Const(max as f32), Instruction::F32Min,
+                             Instruction::F32Const(min as f32), Instruction::F32Max, core]
+                    }
+                }
+                // Without saturation, values out of the range of small types wrap around
+                _ => wrap(core, bws)
+            }
+        },
+        InstrK::ICmp(cmp) if bws.is_64() => match *cmp {
+            Cmp::Eq => vec![Instruction::I64Eq],
+            Cmp::Ne => vec![Instruction::I64Neq],
+            Cmp::Lt => if bws.is_unsigned() {
+                vec![Instruction::I64LtU]
+            } else {
+                vec![Instruction::I64LtS]
+            },
+            Cmp::Le => if bws.is_unsigned() {
+                vec![Instruction::I64LeU]
+            } else {
+                vec![Instruction::I64LeS]
+            },
+            Cmp::Gt => if bws.is_unsigned() {
+                vec![Instruction::I64GtU]
             } else {
-                vec![Instruction::F32ConvertI32S]
+                vec![Instruction::I64GtS]
             },
-        InstrK::Ftoi { int_ty: _ } =>
-            if bws.is_unsigned() {
-                if use_saturating_ftoi { vec![Instruction::I32TruncSatF32U] }
-                else { vec![Instruction::I32TruncF32U] }
-            } else if use_saturating_ftoi { 
-                vec![Instruction::I32TruncSatF32S] 
-            } else { vec![Instruction::I32TruncF32S] }
-        ,
+            Cmp::Ge => if bws.is_unsigned() {
+                vec![Instruction::I64GeU]
+            } else {
+                vec![Instruction::I64GeS]
+            },
+        },
         InstrK::ICmp(cmp) => match *cmp {
             Cmp::Eq => vec![Instruction::I32Eq],
             Cmp::Ne => vec![Instruction::I32Neq],
@@ -98,6 +199,9 @@ pub(crate) fn emit_numeric_instr<'a, A: Abi>(kind: &InstrK, bws: BitWidthSign, u
             },
         },
         InstrK::Read { ty } => match bws {
+            BitWidthSign::U64 | BitWidthSign::S64 => {
+                vec![Instruction::I64Load(memarg::<A>(ty))]
+            }
             BitWidthSign::U32 | BitWidthSign::S32 => {
                 vec![Instruction::I32Load(memarg::<A>(ty))]
             }
@@ -115,6 +219,9 @@ pub(crate) fn emit_numeric_instr<'a, A: Abi>(kind: &InstrK, bws: BitWidthSign, u
             }
         },
         InstrK::Write { ty } => match bws {
+            BitWidthSign::U64 | BitWidthSign::S64 => {
+                vec![Instruction::I64Store(memarg::<A>(ty))]
+            }
             BitWidthSign::U32 | BitWidthSign::S32 => {
                 vec![Instruction::I32Store(memarg::<A>(ty))]
             }
@@ -125,23 +232,65 @@ pub(crate) fn emit_numeric_instr<'a, A: Abi>(kind: &InstrK, bws: BitWidthSign, u
                 vec![Instruction::I32Store8(memarg::<A>(ty))]
             }
         }
+        InstrK::Not => if bws.is_64() {
+            // `i64.eqz` produces an i32, which has to be extended back
+            vec![Instruction::I64Eqz, Instruction::I64ExtendI32U]
+        } else {
+            // !x is the same as (x == 0)
+            vec![Instruction::I32Eqz]
+        },
+        InstrK::BitAnd => if bws.is_64() { vec![Instruction::I64And] } else { vec![Instruction::I32And] },
+        InstrK::BitOr => if bws.is_64() { vec![Instruction::I64Or] } else { vec![Instruction::I32Or] },
+        InstrK::BitXor => if bws.is_64() { vec![Instruction::I64Xor] } else { vec![Instruction::I32Xor] },
+        // ~x is the same as (x ^ -1), the upper bits of small unsigned types have to be cleared again
+        InstrK::BitNot => if bws.is_64() {
+            vec![Instruction::I64Const(-1), Instruction::I64Xor]
+        } else {
+            let mut instrs = vec![Instruction::I32Const(-1)];
+            instrs.extend(match bws {
+                BitWidthSign::U16 => and(Instruction::I32Xor, 65535),
+                BitWidthSign::U8 => and(Instruction::I32Xor, 255),
+                _ => vec![Instruction::I32Xor]
+            });
+            instrs
+        },
+        // Conversions between 64-bit types are no-ops
+        InstrK::IConv { target } if target.is_int64() && bws.is_64() => vec![],
+        // Conversions to 64-bit types extend the value according to the sign of the source.
+        // All smaller types are already correctly extended to 32 bits.
+        InstrK::IConv { target } if target.is_int64() => if bws.is_unsigned() {
+            vec![Instruction::I64ExtendI32U]
+        } else {
+            vec![Instruction::I64ExtendI32S]
+        },
+        // Conversions from 64-bit types wrap the value to 32 bits first
+        // and then continue as if they were converting from an int32
+        InstrK::IConv { target: _ } if bws.is_64() => {
+            let mut instrs = vec![Instruction::I32WrapI64];
+            instrs.extend(emit_numeric_instr::<A>(instr, BitWidthSign::S32, use_saturating_ftoi));
+            instrs
+        }
         InstrK::IConv { target } => match type_to_bws(*target).unwrap() {
             // Conversions to i32, u32 are always no-ops
             BitWidthSign::S32 | BitWidthSign::U32 => vec![],
+            // Handled above
+            BitWidthSign::S64 | BitWidthSign::U64 => unreachable!(),
             BitWidthSign::S16 => match bws {
                 BitWidthSign::U16 | BitWidthSign::U32 | BitWidthSign::S32 => {
                     // shift(16)
                     vec![Instruction::I32Const(16), Instruction::I32Shl,
                          Instruction::I32Const(16), Instruction::I32ShrS]
                 }
-                BitWidthSign::S16 | BitWidthSign::U8 | BitWidthSign::S8 => vec![] // nop
+                BitWidthSign::S16 | BitWidthSign::U8 | BitWidthSign::S8 => vec![], // nop
+                BitWidthSign::S64 | BitWidthSign::U64 => unreachable!()
             },
             BitWidthSign::U16 => match bws {
                 BitWidthSign::S8 | BitWidthSign::S16 | BitWidthSign::U32 | BitWidthSign::S32 => {
-                    // and(65536)
-                    vec![Instruction::I32Const(65536), Instruction::I32And]
+                    // and(65535)
+                    vec![Instruction::I32Const(65535), Instruction::I32And]
                 }
-                BitWidthSign::U8 | BitWidthSign::U16 => vec![] // nop
+                BitWidthSign::U8 | BitWidthSign::U16 => vec![], // nop
+                BitWidthSign::S64 | BitWidthSign::U64 => unreachable!()
             },
             BitWidthSign::S8 => match bws {
                 BitWidthSign::S32 | BitWidthSign::U32 | BitWidthSign::S16 | BitWidthSign::U16 | BitWidthSign::U8 => {
@@ -150,6 +299,7 @@ pub(crate) fn emit_numeric_instr<'a, A: Abi>(kind: &InstrK, bws: BitWidthSign, u
                          Instruction::I32Const(24), Instruction::I32ShrS]
                 }
                 BitWidthSign::S8 => vec![], // nop
+                BitWidthSign::S64 | BitWidthSign::U64 => unreachable!()
             },
             BitWidthSign::U8 => match bws {
                 BitWidthSign::S32 | BitWidthSign::U32 | BitWidthSign::S16 | BitWidthSign::U16 | BitWidthSign::S8 => {
@@ -157,6 +307,7 @@ pub(crate) fn emit_numeric_instr<'a, A: Abi>(kind: &InstrK, bws: BitWidthSign, u
                     vec![Instruction::I32Const(255), Instruction::I32And]
                 }
                 BitWidthSign::U8 => vec![], // nop
+                BitWidthSign::S64 | BitWidthSign::U64 => unreachable!()
             },
         }
         _ => unreachable!()
@@ -171,6 +322,24 @@ fn memarg<A: Abi>(ty: &Ty<'_>) -> MemArg {
     }
 }
 
+/// Helper function. Emits `inner` followed by the idiom
+/// which wraps the result to the bit width of the type:
+/// the "and" idiom for small unsigned types
+/// and the "shift" idiom for small signed types
+fn wrap(inner: Instruction, bws: BitWidthSign) -> Vec<Instruction> {
+    match bws {
+        BitWidthSign::S64 | BitWidthSign::U64 |
+        BitWidthSign::S32 | BitWidthSign::U32 => {
+            // no additional instructions for int64, uint64, int32, uint32
+            vec![inner]
+        }
+        BitWidthSign::U16 => and(inner, 65535),
+        BitWidthSign::S16 => shift(inner, 16),
+        BitWidthSign::U8 => and(inner, 255),
+        BitWidthSign::S8 => shift(inner, 24),
+    }
+}
+
 /// Helper function. Emits the very common `and` idiom:
 /// * `i32.const N`
 /// * `i32.and`
@@ -205,14 +374,18 @@ fn shift(inner: Instruction, n: i32) -> Vec<Instruction> {
 #[allow(unused)]
 pub(crate) fn instr_needs_numeric_metadata(i: &Instr<'_>) -> bool {
     matches!(&i.kind, 
-        InstrK::IAdd | InstrK::ISub | InstrK::IMul | InstrK::IDiv |
-        InstrK::Itof | InstrK::ICmp(_) | InstrK::IConv { target: _ })
+        InstrK::IAdd | InstrK::ISub | InstrK::IMul | InstrK::IDiv | InstrK::IRem | InstrK::INeg |
+        InstrK::Itof { float_ty: _ } | InstrK::ICmp(_) | InstrK::IConv { target: _ } |
+        InstrK::Not | InstrK::BitAnd | InstrK::BitOr | InstrK::BitXor | InstrK::BitNot |
+        InstrK::Shl | InstrK::Shr)
 }
 
 /// Returns the BWS descriptor of a type.
 /// Returns None if it's not an integer type
 pub(crate) fn type_to_bws(t: Ty<'_>) -> Option<BitWidthSign> {
     match &*t {
+        crate::ty::Type::Int64 => Some(BitWidthSign::S64),
+        crate::ty::Type::UInt64 => Some(BitWidthSign::U64),
         crate::ty::Type::Int32 => Some(BitWidthSign::S32),
         crate::ty::Type::UInt32 => Some(BitWidthSign::U32),
         crate::ty::Type::Int16 => Some(BitWidthSign::S16),
@@ -226,6 +399,8 @@ pub(crate) fn type_to_bws(t: Ty<'_>) -> Option<BitWidthSign> {
 pub(crate) fn do_int_types_match(l: Ty<'_>, r: Ty<'_>) -> bool {
     debug_assert!(l.is_int() && r.is_int());
     matches!((&*l, &*r), 
+        (crate::ty::Type::Int64, crate::ty::Type::Int64)   | 
+        (crate::ty::Type::UInt64, crate::ty::Type::UInt64) |
         (crate::ty::Type::Int32, crate::ty::Type::Int32)   | 
         (crate::ty::Type::UInt32, crate::ty::Type::UInt32) |
         (crate::ty::Type::Int16, crate::ty::Type::Int16)   |
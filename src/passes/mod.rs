@@ -2,8 +2,16 @@
 mod instr_rewrite;
 #[cfg(feature = "opt")]
 mod peephole_opt;
+#[cfg(feature = "opt")]
+mod select_opt;
+#[cfg(feature = "opt")]
+mod tail_call_opt;
 
 #[cfg(feature = "opt")]
 pub use instr_rewrite::{InstrRewritePass, BlobRewriteData};
 #[cfg(feature = "opt")]
-pub use peephole_opt::PeepholeOpt;
\ No newline at end of file
+pub use peephole_opt::PeepholeOpt;
+#[cfg(feature = "opt")]
+pub use select_opt::SelectOpt;
+#[cfg(feature = "opt")]
+pub use tail_call_opt::TailCallOpt;
\ No newline at end of file
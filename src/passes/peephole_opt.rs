@@ -1,26 +1,66 @@
 use std::collections::HashMap;
 
-use crate::{abi::{Abi, Wasm32Abi}, instr::{BlockId, Instr, InstrK}, pass::{FunctionPass}, ty::{Ty, Type}};
+use crate::{abi::Wasm32Abi, instr::{BlockId, Instr, InstrK}, intrinsic::Intrinsics, module::Module, pass::{FunctionPass}, ty::Ty};
 
 use super::BlobRewriteData;
 
-// TODO: this is just copied from the emitter code
-fn calc_struct_field_offset(struct_ty: Ty, field_idx: usize) -> usize {
-    let struct_fields = match &*struct_ty {
-        Type::Struct { fields } => fields,
-        _ => unreachable!()
+/// The constant offset (in bytes) an instruction adds to the pointer on the top of the stack,
+/// if it can be folded into the offset of a memory instruction
+fn const_ptr_offset<'ctx>(module: &Module<'ctx>, i: &Instr<'ctx>) -> Option<usize> {
+    let offset = match &i.kind {
+        InstrK::GetFieldPtr { struct_ty, field_idx } => module.field_offset::<Wasm32Abi>(*struct_ty, *field_idx),
+        InstrK::OffsetConst { ty, n } => n.checked_mul(module.sizeof::<Wasm32Abi>(*ty))?,
+        _ => return None
     };
-    <Wasm32Abi as Abi>::struct_field_offset(struct_fields, field_idx)
+    // the offset of a wasm memory instruction is an u32
+    (offset <= u32::MAX as usize).then_some(offset)
+}
+
+/// The constant offset (in bytes) of [LdInt, Offset], see [`const_ptr_offset`]
+fn const_index_offset<'ctx>(module: &Module<'ctx>, i1: &Instr<'ctx>, i2: &Instr<'ctx>) -> Option<usize> {
+    match (&i1.kind, &i2.kind) {
+        (InstrK::LdInt(val, _), InstrK::Offset { ty }) if (*val as u32 as i32) >= 0 => {
+            const_ptr_offset(module, &Instr::new(InstrK::OffsetConst { ty: *ty, n: *val as u32 as usize }))
+        }
+        _ => None
+    }
+}
+
+/// Struct values are read and written scalar by scalar, only scalars are folded
+fn scalar_read_ty<'ctx>(i: &Instr<'ctx>) -> Option<Ty<'ctx>> {
+    match &i.kind {
+        InstrK::Read { ty } if !ty.is_struct() => Some(*ty),
+        _ => None
+    }
+}
+
+/// See [`scalar_read_ty`]
+fn scalar_write_ty<'ctx>(i: &Instr<'ctx>) -> Option<Ty<'ctx>> {
+    match &i.kind {
+        InstrK::Write { ty } if !ty.is_struct() => Some(*ty),
+        _ => None
+    }
+}
+
+fn read_at_offset<'ctx>(offset: usize, ty: Ty<'ctx>) -> Vec<Instr<'ctx>> {
+    vec![Instr::new_intrinsic(Intrinsics::ReadAtOffset { offset, ty })]
+}
+
+fn write_at_offset<'ctx>(load: &Instr<'ctx>, offset: usize, ty: Ty<'ctx>) -> Vec<Instr<'ctx>> {
+    vec![load.clone(), Instr::new_intrinsic(Intrinsics::WriteAtOffset { offset, ty })]
 }
 
 /// Replace two consecutive instructions with something new
-fn replace_2<'ctx>(i1: &Instr<'ctx>, i2: &Instr<'ctx>) -> Option<Vec<Instr<'ctx>>> {
+fn replace_2<'ctx>(module: &Module<'ctx>, i1: &Instr<'ctx>, i2: &Instr<'ctx>) -> Option<Vec<Instr<'ctx>>> {
     match (&i1.kind, &i2.kind) {
-        // [GetFieldPtr, Read] -> [ReadAtOffset]
-        /*(InstrK::GetFieldPtr { struct_ty, field_idx }, InstrK::Read { ty }) => {
-            let offset = calc_struct_field_offset(*struct_ty, *field_idx);
-            Some(vec![Instr::new_intrinsic(Intrinsics::ReadAtOffset { offset, ty: *ty })])
-        },*/
+        // [GetFieldPtr/OffsetConst, Read] -> [ReadAtOffset]
+        (InstrK::GetFieldPtr { struct_ty: _, field_idx: _ } | InstrK::OffsetConst { ty: _, n: _ }, InstrK::Read { ty: _ }) => {
+            Some(read_at_offset(const_ptr_offset(module, i1)?, scalar_read_ty(i2)?))
+        }
+        // [LdInt, Offset] -> [OffsetConst]
+        (InstrK::LdInt(val, _), InstrK::Offset { ty }) if (*val as u32 as i32) >= 0 => {
+            Some(vec![Instr::new(InstrK::OffsetConst { ty: *ty, n: *val as u32 as usize })])
+        }
         // [LoadGlobalFunc, CallIndirect] -> [CallDirect]
         (InstrK::LdGlobalFunc { func_name }, InstrK::CallIndirect) => {
             // CallIndirect has the type metadata
@@ -31,22 +71,37 @@ fn replace_2<'ctx>(i1: &Instr<'ctx>, i2: &Instr<'ctx>) -> Option<Vec<Instr<'ctx>
     }
 }
 
-/// Replace two consecutive instructions with something new
-fn replace_3<'ctx>(i1: &Instr<'ctx>, i2: &Instr<'ctx>, i3: &Instr<'ctx>) -> Option<Vec<Instr<'ctx>>> {
+/// Replace three consecutive instructions with something new
+fn replace_3<'ctx>(module: &Module<'ctx>, i1: &Instr<'ctx>, i2: &Instr<'ctx>, i3: &Instr<'ctx>) -> Option<Vec<Instr<'ctx>>> {
     match (&i1.kind, &i2.kind, &i3.kind) {
-        // [GetFieldPtr, load-instr, Write] -> [load-instr, WriteAtOffset]
-        /*(InstrK::GetFieldPtr { struct_ty, field_idx }, _, InstrK::Write { ty }) => {
-            if i2.is_load() {
-                let offset = calc_struct_field_offset(*struct_ty, *field_idx);
-                Some(vec![
-                    i2.clone(),
-                    Instr::new_intrinsic(Intrinsics::WriteAtOffset { offset, ty: *ty })])
-            } else { None }
-        }*/
+        // [LdInt, Offset, Read] -> [ReadAtOffset]
+        (InstrK::LdInt(_, _), InstrK::Offset { ty: _ }, InstrK::Read { ty: _ }) => {
+            Some(read_at_offset(const_index_offset(module, i1, i2)?, scalar_read_ty(i3)?))
+        }
+        // [GetFieldPtr/OffsetConst, load-instr, Write] -> [load-instr, WriteAtOffset]
+        (InstrK::GetFieldPtr { struct_ty: _, field_idx: _ } | InstrK::OffsetConst { ty: _, n: _ }, _, InstrK::Write { ty: _ })
+            if i2.is_load() => {
+            Some(write_at_offset(i2, const_ptr_offset(module, i1)?, scalar_write_ty(i3)?))
+        }
+        _ => None
+    }
+}
+
+/// Replace four consecutive instructions with something new
+fn replace_4<'ctx>(module: &Module<'ctx>, i1: &Instr<'ctx>, i2: &Instr<'ctx>, i3: &Instr<'ctx>, i4: &Instr<'ctx>) -> Option<Vec<Instr<'ctx>>> {
+    match (&i1.kind, &i2.kind, &i3.kind, &i4.kind) {
+        // [LdInt, Offset, load-instr, Write] -> [load-instr, WriteAtOffset]
+        (InstrK::LdInt(_, _), InstrK::Offset { ty: _ }, _, InstrK::Write { ty: _ }) if i3.is_load() => {
+            Some(write_at_offset(i3, const_index_offset(module, i1, i2)?, scalar_write_ty(i4)?))
+        }
         _ => None
     }
 }
 
+/// Small rewrites of a few consecutive instructions.
+///
+/// The longest matching sequence wins and the replaced sequences don't overlap,
+/// so e.g. [LdInt, Offset, Read] becomes a single ReadAtOffset rather than OffsetConst and Read.
 pub struct PeepholeOpt {}
 
 impl<'ctx> FunctionPass<'ctx> for PeepholeOpt {
@@ -55,31 +110,37 @@ impl<'ctx> FunctionPass<'ctx> for PeepholeOpt {
     type Output = HashMap<BlockId, Vec<BlobRewriteData<'ctx>>>;
 
     fn visit_function(
-        &mut self, 
-        _module: &crate::module::Module<'ctx>,
+        &mut self,
+        module: &crate::module::Module<'ctx>,
         function: &crate::instr::Function<'ctx>) -> Result<Self::Output, Self::Error> {
-        
+
         let mut rewrite_data = HashMap::new();
 
         for block in function.blocks_iter() {
             let mut this_block_replacements: Vec<BlobRewriteData<'ctx>> = Vec::new();
 
-            for i in 0..block.body.len() {
-                // Check if there's 2 consecutive instructions left
-                if (i + 1) < block.body.len() {
-                    if let Some(new_instrs) = replace_2(&block.body[i], &block.body[i+1]) {
-                        let range = i..(i + 2);
-                        this_block_replacements.push((range, new_instrs));
-                    }
-                }
-                // Check if there's 3 consecutive instructions left
-                if (i + 2) < block.body.len() {
-                    if let Some(new_instrs) = replace_3(&block.body[i], &block.body[i+1], &block.body[i+2]) {
-                        let range = i..(i + 3);
-                        this_block_replacements.push((range, new_instrs));
+            let mut i = 0;
+            while i < block.body.len() {
+                // Check if there's 4, 3 or 2 consecutive instructions left
+                let window = &block.body[i..];
+                let replacement = match window {
+                    [i1, i2, i3, i4, ..] => replace_4(module, i1, i2, i3, i4).map(|r| (4, r)),
+                    _ => None
+                }.or_else(|| match window {
+                    [i1, i2, i3, ..] => replace_3(module, i1, i2, i3).map(|r| (3, r)),
+                    _ => None
+                }).or_else(|| match window {
+                    [i1, i2, ..] => replace_2(module, i1, i2).map(|r| (2, r)),
+                    _ => None
+                });
+
+                match replacement {
+                    Some((len, new_instrs)) => {
+                        this_block_replacements.push((i..(i + len), new_instrs));
+                        i += len;
                     }
+                    None => i += 1
                 }
-                // TODO: replace 4 instructions etc.
             }
 
             if !this_block_replacements.is_empty() {
@@ -89,4 +150,4 @@ impl<'ctx> FunctionPass<'ctx> for PeepholeOpt {
 
         Ok(rewrite_data)
     }
-}
\ No newline at end of file
+}
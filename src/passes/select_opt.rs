@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use crate::{instr::{BlockId, Function, Instr, InstrK}, metadata::Metadata, pass::FunctionPass};
+
+use super::BlobRewriteData;
+
+/// If the block consists of a single load instruction and returns that one value,
+/// return the load. Struct values can't be selected, they consist of several values.
+/// The block can't have parameters, `Select` would leave them on the stack.
+fn trivial_arm<'a, 'ctx>(function: &'a Function<'ctx>, block: BlockId) -> Option<&'a Instr<'ctx>> {
+    let block = function.get_block(block)?;
+    if !block.params().is_empty() { return None }
+    match (block.body.as_slice(), block.returns().as_slice()) {
+        ([load], [ty]) if load.is_load() && !ty.is_struct() => Some(load),
+        _ => None
+    }
+}
+
+/// Turn an IfElse whose both branches are a single load into a Select.
+///
+/// Select evaluates both values before the condition, so the loads are moved
+/// in front of the instructions computing the condition. This is only done
+/// when those instructions are loads themselves, possibly compared:
+/// * [cond-load, IfElse] -> [then-load, else-load, cond-load, Select]
+/// * [load, load, ICmp/FCmp, IfElse] -> [then-load, else-load, load, load, ICmp/FCmp, Select]
+///
+/// Loads don't have side effects, so evaluating them in a different order
+/// and evaluating both of them doesn't change the semantics.
+pub struct SelectOpt {}
+
+impl<'ctx> FunctionPass<'ctx> for SelectOpt {
+    type Error = ();
+    // Returns a type suitable for InstrRewritePass
+    type Output = HashMap<BlockId, Vec<BlobRewriteData<'ctx>>>;
+
+    fn visit_function(
+        &mut self,
+        _module: &crate::module::Module<'ctx>,
+        function: &Function<'ctx>) -> Result<Self::Output, Self::Error> {
+
+        let mut rewrite_data = HashMap::new();
+
+        for block in function.blocks_iter() {
+            let mut this_block_replacements: Vec<BlobRewriteData<'ctx>> = Vec::new();
+
+            for (i, instr) in block.body.iter().enumerate() {
+                let (then, else_block) = match &instr.kind {
+                    InstrK::IfElse { then, r#else: Some(else_block) } => (*then, *else_block),
+                    _ => continue
+                };
+                let (then_load, else_load) = match (trivial_arm(function, then), trivial_arm(function, else_block)) {
+                    (Some(t), Some(e)) => (t, e),
+                    _ => continue
+                };
+
+                // Find the instructions computing the condition
+                let cond_start = if i >= 3
+                    && block.body[i - 3].is_load() && block.body[i - 2].is_load()
+                    && matches!(block.body[i - 1].kind, InstrK::ICmp(_) | InstrK::FCmp(_)) {
+                    i - 3
+                } else if i >= 1 && block.body[i - 1].is_load() {
+                    i - 1
+                } else {
+                    continue
+                };
+
+                // The emitter needs the type of the values, the same as
+                // the Verifier would insert
+                let mut meta = Metadata::new();
+                meta.insert_ty(key!("ty"), function.get_block(then).unwrap().returns()[0]);
+
+                let mut new_instrs = vec![then_load.clone(), else_load.clone()];
+                new_instrs.extend(block.body[cond_start..i].iter().cloned());
+                new_instrs.push(Instr::new_with_meta(InstrK::Select, meta));
+                this_block_replacements.push((cond_start..(i + 1), new_instrs));
+            }
+
+            if !this_block_replacements.is_empty() {
+                rewrite_data.insert(block.idx, this_block_replacements);
+            }
+        }
+
+        Ok(rewrite_data)
+    }
+}
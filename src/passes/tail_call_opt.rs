@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+
+use crate::{instr::{BlockId, BlockTag, Function, Instr, InstrBlock, InstrK}, module::{Functional, Module}, pass::FunctionPass, ty::{Ty, Type}};
+
+use super::BlobRewriteData;
+
+/// The result types of the function called by a call instruction
+fn callee_ret_tys<'ctx>(module: &Module<'ctx>, call: &Instr<'ctx>) -> Option<Vec<Ty<'ctx>>> {
+    match &call.kind {
+        InstrK::CallDirect { func_name } => Some(module.get_function(func_name)?.ret_tys().clone()),
+        InstrK::CallIndirect => {
+            // meta["ty"] injected by the Verifier
+            match &*call.meta.retrieve_ty(key!("ty"))? {
+                Type::Func { args: _, ret } => Some(ret.clone()),
+                _ => None
+            }
+        }
+        _ => None
+    }
+}
+
+/// The tail call variant of a call instruction
+fn tail_call<'ctx>(call: &Instr<'ctx>) -> Instr<'ctx> {
+    let kind = match &call.kind {
+        InstrK::CallDirect { func_name } => InstrK::TailCallDirect { func_name: func_name.clone() },
+        InstrK::CallIndirect => InstrK::TailCallIndirect,
+        _ => unreachable!()
+    };
+    // TailCallIndirect needs the type metadata of CallIndirect
+    Instr::new_with_meta(kind, call.meta.clone())
+}
+
+/// Returns true if the block is a `Try` body or is nested in one.
+///
+/// A tail call leaves the enclosing wasm `try`, so the exceptions thrown by the callee
+/// wouldn't be caught by its handlers anymore
+fn inside_try<'ctx>(function: &Function<'ctx>, block: &InstrBlock<'ctx>) -> bool {
+    let mut current = block;
+    loop {
+        if current.tag() == BlockTag::Try { return true }
+        // meta["parent"] injected by the ControlFlowVerifier, the main block has none
+        match current.meta.retrieve_copied::<BlockId>(key!("parent")) {
+            Some(parent) => current = function.get_block(parent).unwrap(),
+            None => return false
+        }
+    }
+}
+
+/// Replace calls whose results are immediately returned by tail calls:
+/// * [CallDirect/CallIndirect, Return] -> [TailCallDirect/TailCallIndirect]
+/// * a call at the end of the function's main block, whose results are returned implicitly
+///
+/// The called function must return the same types as the current function.
+/// Calls inside a `Try` body are not replaced, neither are calls in functions
+/// which use `StackAlloc` (see [`InstrK::TailCallDirect`]).
+/// Requires tail calls to be enabled in the module configuration.
+pub struct TailCallOpt {}
+
+impl<'ctx> FunctionPass<'ctx> for TailCallOpt {
+    type Error = ();
+    // Returns a type suitable for InstrRewritePass
+    type Output = HashMap<BlockId, Vec<BlobRewriteData<'ctx>>>;
+
+    fn visit_function(
+        &mut self,
+        module: &Module<'ctx>,
+        function: &Function<'ctx>) -> Result<Self::Output, Self::Error> {
+
+        let mut rewrite_data = HashMap::new();
+        if function.uses_stack_alloc() {
+            return Ok(rewrite_data)
+        }
+        let returns_same = |call: &Instr<'ctx>| {
+            callee_ret_tys(module, call).is_some_and(|rets| rets == *function.ret_tys())
+        };
+
+        for block in function.blocks_iter() {
+            if inside_try(function, block) { continue }
+            let mut this_block_replacements: Vec<BlobRewriteData<'ctx>> = Vec::new();
+
+            for (i, instr) in block.body.iter().enumerate() {
+                if !returns_same(instr) { continue }
+
+                let next = block.body.get(i + 1);
+                if matches!(next.map(|n| &n.kind), Some(InstrK::Return)) {
+                    this_block_replacements.push((i..(i + 2), vec![tail_call(instr)]));
+                } else if next.is_none() && block.idx == function.entry_block().idx {
+                    this_block_replacements.push((i..(i + 1), vec![tail_call(instr)]));
+                }
+            }
+
+            if !this_block_replacements.is_empty() {
+                rewrite_data.insert(block.idx, this_block_replacements);
+            }
+        }
+
+        Ok(rewrite_data)
+    }
+}
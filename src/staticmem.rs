@@ -57,8 +57,12 @@ pub enum SMValue {
     Int8(u8, Sign),
     Int16(u16, Sign),
     Int32(u32, Sign),
+    Int64(u64, Sign),
     Float(f32),
+    Float64(f64),
     Struct(Vec<SMValue>),
+    /// An array of values, which must all have the same type
+    Array(Vec<SMValue>),
     /// Arbitrary bytes
     Blob(Box<[u8]>),
     /// A pointer to another part of the static memory
@@ -88,8 +92,9 @@ impl CompiledStaticMemory {
         let mut curr_address = 8usize;
         for (i, item) in mem.items.iter().enumerate() {
             let ty = Self::get_item_type(&item.value, m);
-            let size = A::type_sizeof(ty);
-            let align = 2_usize.pow(A::type_alignment(ty) as u32);
+            let layout = m.layout_of::<A>(ty);
+            let size = layout.size;
+            let align = 2_usize.pow(layout.alignment as u32);
             if curr_address % align != 0 {
                 curr_address += align - (curr_address % align);
             }
@@ -127,6 +132,13 @@ impl CompiledStaticMemory {
                     place.write_all(&val.to_be_bytes()).unwrap();
                 }
             }
+            SMValue::Int64(val, _) => {
+                if A::is_little_endian() {
+                    place.write_all(&val.to_le_bytes()).unwrap();
+                } else {
+                    place.write_all(&val.to_be_bytes()).unwrap();
+                }
+            }
             SMValue::Float(val) => {
                 if A::is_little_endian() {
                     place.write_all(&val.to_bits().to_le_bytes()).unwrap();
@@ -134,20 +146,33 @@ impl CompiledStaticMemory {
                     place.write_all(&val.to_bits().to_be_bytes()).unwrap();
                 }
             },
+            SMValue::Float64(val) => {
+                if A::is_little_endian() {
+                    place.write_all(&val.to_bits().to_le_bytes()).unwrap();
+                } else {
+                    place.write_all(&val.to_bits().to_be_bytes()).unwrap();
+                }
+            },
             SMValue::Struct(items) => {
                 let start_of_struct = place.position();
-                // First compile types of fields
-                let mut fields_types = vec![];
-                for item in items {
-                    fields_types.push(Self::get_item_type(item, m));
-                }
-                // Then for every field, write the value to where it's supposed to be
+                let layout = m.layout_of::<A>(Self::get_item_type(item, m));
+                // For every field, write the value to where it's supposed to be
                 for (n, item) in items.iter().enumerate() {
-                    let offset = A::struct_field_offset(&fields_types, n);
+                    let offset = layout.field_offsets[n];
                     place.set_position(start_of_struct + offset as u64);
                     Self::write_to_memory::<A>(place, item, m, addresses);
                 }
             }
+            SMValue::Array(items) => {
+                let start_of_array = place.position();
+                if let Some(first) = items.first() {
+                    let stride = m.layout_of::<A>(Self::get_item_type(first, m)).stride();
+                    for (n, item) in items.iter().enumerate() {
+                        place.set_position(start_of_array + (n * stride) as u64);
+                        Self::write_to_memory::<A>(place, item, m, addresses);
+                    }
+                }
+            }
             SMValue::Blob(blob) => {
                 place.write_all(&*blob).unwrap();
             }
@@ -171,7 +196,10 @@ impl CompiledStaticMemory {
             SMValue::Int16(_, Sign::U) => m.uint16t(),
             SMValue::Int32(_, Sign::S) => m.int32t(),
             SMValue::Int32(_, Sign::U) => m.uint32t(),
+            SMValue::Int64(_, Sign::S) => m.int64t(),
+            SMValue::Int64(_, Sign::U) => m.uint64t(),
             SMValue::Float(_) => m.float32t(),
+            SMValue::Float64(_) => m.float64t(),
             SMValue::Struct(items) => {
                 let mut fields = vec![];
                 for item in items {
@@ -179,13 +207,14 @@ impl CompiledStaticMemory {
                 }
                 m.intern_type(Type::Struct { fields })
             }
-            // FIXME
-            // there's no "array" type or something like this
-            // so we simulate it by making a struct full of uint8 types
+            SMValue::Array(items) => {
+                // an empty array has no elements to take the type from, so it's an array of uint8
+                let elem = items.first().map(|item| Self::get_item_type(item, m)).unwrap_or_else(|| m.uint8t());
+                debug_assert!(items.iter().all(|item| Self::get_item_type(item, m) == elem));
+                m.intern_type(Type::Array { elem, len: items.len() })
+            }
             SMValue::Blob(blob) => {
-                m.intern_type(Type::Struct {
-                    fields: vec![m.uint8t(); blob.len()]
-                })
+                m.intern_type(Type::Array { elem: m.uint8t(), len: blob.len() })
             }
             SMValue::PtrTo(_) => m.ptr_t()
         }
@@ -248,4 +277,40 @@ mod tests {
             8, 0, 0, 0 // ptr to start of first struct
         ]);
     }
+
+    #[test]
+    fn staticmem_array_test() {
+        let top = Module::default();
+
+        let mut mem = StaticMemory::new();
+        let i1 = mem.add_item(SMItem {
+            value: SMValue::Array(vec![
+                SMValue::Struct(vec![SMValue::Int16(1, Sign::S), SMValue::Int8(2, Sign::S)]),
+                SMValue::Struct(vec![SMValue::Int16(3, Sign::S), SMValue::Int8(4, Sign::S)]),
+            ]),
+            mutability: Mutability::Const,
+            unique: true
+        });
+        mem.add_item(SMItem {
+            value: SMValue::Blob(Box::new([9, 9, 9])),
+            mutability: Mutability::Const,
+            unique: true
+        });
+
+        let compiled = CompiledStaticMemory::compile::<Wasm32Abi>(&top, &mem);
+        assert_eq!(compiled.buf, vec![
+            // The first eight empty bytes
+            0, 0, 0, 0, 0, 0, 0, 0,
+            1, 0, 2, 0, // first element + padding to the element stride
+            3, 0, 4, 0, // second element + padding to the element stride
+            9, 9, 9 // the blob
+        ]);
+        assert_eq!(
+            CompiledStaticMemory::get_item_type(&mem.items[i1.0].value, &top),
+            top.intern_type(Type::Array {
+                elem: top.intern_type(Type::Struct { fields: vec![top.int16t(), top.int8t()] }),
+                len: 2
+            })
+        );
+    }
 }
\ No newline at end of file
@@ -1,4 +1,4 @@
-use std::fmt::Debug;
+use std::{cell::OnceCell, fmt::Debug, hash::Hash};
 
 use libintern::Intern;
 
@@ -18,19 +18,82 @@ pub enum Type<'ctx> {
     Int32,
     /// An unsigned 32-bit integer
     UInt32,
+    /// A signed 64-bit integer
+    Int64,
+    /// An unsigned 64-bit integer
+    UInt64,
     Float32,
+    /// A double-precision floating-point number
+    Float64,
+    /// A 128-bit vector, interpreted by the vector instructions
+    /// according to their [`VecShape`](crate::instr::VecShape)
+    V128,
+    /// An opaque reference to a host object.
+    ///
+    /// It can only be passed around (in locals, globals and function arguments/returns)
+    /// and compared to null. It has no size and can't be stored in memory.
+    ExternRef,
     Func { args: Vec<Ty<'ctx>>, ret: Vec<Ty<'ctx>> },
     Ptr,
-    Struct { fields: Vec<Ty<'ctx>> }
+    Struct { fields: Vec<Ty<'ctx>> },
+    /// A fixed-size array of `len` elements of type `elem`
+    Array { elem: Ty<'ctx>, len: usize },
+    /// A nominal struct type.
+    ///
+    /// Unlike [`Type::Struct`], named structs are identified only by their name.
+    /// They're declared first and their body (fields) may be set later,
+    /// which allows them to refer to themselves (e.g. through function types).
+    /// A named struct whose body wasn't set yet is called opaque.
+    NamedStruct { name: String, body: NamedStructBody<'ctx> },
+    /// A tagged union (sum type), which holds a value of exactly one of the variants.
+    ///
+    /// It's laid out as a `uint32` tag (the index of the active variant) at offset zero,
+    /// followed by the payload, which is large enough to hold any of the variants.
+    TaggedUnion { variants: Vec<Ty<'ctx>> }
+}
+
+/// The fields of a named struct, which may be set after the type is interned.
+///
+/// It's ignored for comparisons and hashing, because named structs
+/// are identified only by their name.
+#[derive(Default)]
+pub struct NamedStructBody<'ctx>(OnceCell<Vec<Ty<'ctx>>>);
+
+impl<'ctx> NamedStructBody<'ctx> {
+    pub fn get(&self) -> Option<&Vec<Ty<'ctx>>> {
+        self.0.get()
+    }
+
+    /// Set the fields. Returns false if they were already set.
+    pub(crate) fn set(&self, fields: Vec<Ty<'ctx>>) -> bool {
+        self.0.set(fields).is_ok()
+    }
+}
+
+impl PartialEq for NamedStructBody<'_> {
+    fn eq(&self, _other: &Self) -> bool { true }
+}
+
+impl Eq for NamedStructBody<'_> {}
+
+impl Hash for NamedStructBody<'_> {
+    fn hash<H: std::hash::Hasher>(&self, _state: &mut H) {}
 }
 
 impl<'ctx> Type<'ctx> {
     pub fn is_int(&self) -> bool {
-        matches!(self, Type::Int32 | Type::UInt32 | Type::Int16 | Type::UInt16 | Type::Int8 | Type::UInt8)
+        matches!(self, Type::Int64 | Type::UInt64 | Type::Int32 | Type::UInt32 | Type::Int16 | Type::UInt16 | Type::Int8 | Type::UInt8)
+    }
+
+    /// Returns true if the type is a 64-bit integer.
+    ///
+    /// Unlike the smaller integer types, these are not represented as `i32` in WebAssembly.
+    pub fn is_int64(&self) -> bool {
+        matches!(self, Type::Int64 | Type::UInt64)
     }
 
     pub fn is_float(&self) -> bool {
-        matches!(self, Type::Float32)
+        matches!(self, Type::Float32 | Type::Float64)
     }
 
     pub fn is_func(&self) -> bool {
@@ -41,8 +104,81 @@ impl<'ctx> Type<'ctx> {
         matches!(self, Type::Ptr)
     }
 
+    /// Returns true for both structural and named structs
     pub fn is_struct(&self) -> bool {
-        matches!(self, Type::Struct { fields: _ })
+        matches!(self, Type::Struct { fields: _ } | Type::NamedStruct { name: _, body: _ })
+    }
+
+    /// Returns the fields of a struct type.
+    ///
+    /// Returns None if the type is not a struct or if it's an opaque named struct.
+    pub fn struct_fields(&self) -> Option<&[Ty<'ctx>]> {
+        match self {
+            Type::Struct { fields } => Some(fields),
+            Type::NamedStruct { name: _, body } => body.get().map(|fields| fields.as_slice()),
+            _ => None
+        }
+    }
+
+    /// Returns true if the layout of the type is not known,
+    /// because it is or contains an opaque named struct.
+    pub fn is_opaque(&self) -> bool {
+        match self {
+            Type::Struct { fields } => fields.iter().any(|f| f.is_opaque()),
+            Type::Array { elem, len: _ } => elem.is_opaque(),
+            Type::TaggedUnion { variants } => variants.iter().any(|v| v.is_opaque()),
+            Type::NamedStruct { name: _, body } => match body.get() {
+                None => true,
+                Some(fields) => fields.iter().any(|f| f.is_opaque())
+            },
+            _ => false
+        }
+    }
+
+    /// Returns true if a value of this type contains a value of type `ty`
+    /// in its fields, elements or variants, recursively.
+    ///
+    /// Values behind pointers (or function types) don't count.
+    pub fn contains_by_value(&self, ty: Ty<'ctx>) -> bool {
+        let inner: &[Ty<'ctx>] = match self {
+            Type::Struct { fields } => fields,
+            Type::Array { elem, len: _ } => std::slice::from_ref(elem),
+            Type::TaggedUnion { variants } => variants,
+            Type::NamedStruct { name: _, body } => match body.get() {
+                None => &[],
+                Some(fields) => fields
+            },
+            _ => &[]
+        };
+        inner.iter().any(|t| *t == ty || t.contains_by_value(ty))
+    }
+
+    /// Returns true if the type is or contains an `externref`,
+    /// which means it can't be stored in memory.
+    pub fn contains_extern_ref(&self) -> bool {
+        match self {
+            Type::ExternRef => true,
+            Type::Struct { fields } => fields.iter().any(|f| f.contains_extern_ref()),
+            Type::Array { elem, len: _ } => elem.contains_extern_ref(),
+            Type::TaggedUnion { variants } => variants.iter().any(|v| v.contains_extern_ref()),
+            Type::NamedStruct { name: _, body } => match body.get() {
+                None => false,
+                Some(fields) => fields.iter().any(|f| f.contains_extern_ref())
+            },
+            _ => false
+        }
+    }
+
+    pub fn is_extern_ref(&self) -> bool {
+        matches!(self, Type::ExternRef)
+    }
+
+    pub fn is_array(&self) -> bool {
+        matches!(self, Type::Array { elem: _, len: _ })
+    }
+
+    pub fn is_tagged_union(&self) -> bool {
+        matches!(self, Type::TaggedUnion { variants: _ })
     }
 }
 
@@ -1,15 +1,22 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, convert::TryFrom};
 
-use crate::{instr::{BlockId, InstrBlock, InstrK}, module::Functional, numerics::{BitWidthSign, do_int_types_match, type_to_bws}, pass::MutableFunctionPass, ty::{Ty, Type}};
+use crate::{instr::{BlockId, BlockTag, InstrBlock, InstrK, VecShape}, module::Functional, numerics::{BitWidthSign, do_int_types_match, type_to_bws}, pass::MutableFunctionPass, ty::{Ty, Type}};
 
 pub struct Verifier {}
 
 pub struct VerifierMutInfo<'ctx> {
-    /// Types of the functions in CallIndirect instructions
+    /// Types of the functions in CallIndirect and TailCallIndirect instructions
     call_indirect_function_types: HashMap<(BlockId, usize), Ty<'ctx>>,
     /// Types of the `from`s of BitCast instructions
     bitcast_source_types: HashMap<(BlockId, usize), Ty<'ctx>>,
-    numeric_instrs_data: HashMap<(BlockId, usize), BitWidthSign>
+    numeric_instrs_data: HashMap<(BlockId, usize), BitWidthSign>,
+    /// Operand types of floating-point instructions and source types of Ftoi instructions
+    float_instrs_types: HashMap<(BlockId, usize), Ty<'ctx>>,
+    /// Types of the values of Select instructions
+    select_types: HashMap<(BlockId, usize), Ty<'ctx>>,
+    /// Types of the values Discard, Dup, Swap and Over work with,
+    /// for Swap and Over the second type is the one on the top of the stack
+    stack_instrs_types: HashMap<(BlockId, usize), (Ty<'ctx>, Option<Ty<'ctx>>)>
 }
 
 impl<'ctx> Verifier {
@@ -19,12 +26,14 @@ impl<'ctx> Verifier {
         this_block_id: BlockId,
         module: &crate::module::Module<'ctx>,
         function: &crate::instr::Function<'ctx>,
-        block: &InstrBlock<'ctx>
+        block: &InstrBlock<'ctx>,
+        initial_stack: Vec<Ty<'ctx>>
     ) -> Result<(), VerifyError<'ctx>> {
 
         // We simulate and record the function stack types
-        // Every block starts with an empty stack (values can't be passed to blocks)
-        let mut stack = Vec::new();
+        // Every block starts with its parameters on the stack,
+        // catch handlers start with the payload of the exception
+        let mut stack = initial_stack;
 
         for (i, instr) in block.body.iter().enumerate() {
             match &instr.kind {
@@ -37,26 +46,34 @@ impl<'ctx> Verifier {
                         })
                     }
                     // Also verify the integer doesn't overflow the type
+                    // Types up to 32 bits only use the lower 32 bits of the value,
+                    // the upper 32 bits must be either all zeros or all ones (a negative value)
+                    if !ty.is_int64() && u32::try_from(*val).is_err() && i32::try_from(*val as i64).is_err() {
+                        return Err(VerifyError::ConstIntOverflow { value: *val, ty: *ty })
+                    }
+                    let val32 = *val as u32;
                     match &**ty {
-                        Type::Int8 => if (*val as i32 > i8::MAX as i32) || ((*val as i32) < i8::MIN as i32) {
+                        Type::Int8 => if (val32 as i32 > i8::MAX as i32) || ((val32 as i32) < i8::MIN as i32) {
                             return Err(VerifyError::ConstIntOverflow { value: *val, ty: *ty })
                         },
-                        Type::UInt8 => if *val as i32 > u8::MAX as i32 {
+                        Type::UInt8 => if val32 as i32 > u8::MAX as i32 {
                             return Err(VerifyError::ConstIntOverflow { value: *val, ty: *ty })
                         },
-                        Type::Int16 => if (*val as i32 > i16::MAX as i32) || ((*val as i32) < i16::MIN as i32) {
+                        Type::Int16 => if (val32 as i32 > i16::MAX as i32) || ((val32 as i32) < i16::MIN as i32) {
                             return Err(VerifyError::ConstIntOverflow { value: *val, ty: *ty })
                         },
-                        Type::UInt16 => if *val as i32 > u16::MAX as i32 {
+                        Type::UInt16 => if val32 as i32 > u16::MAX as i32 {
                             return Err(VerifyError::ConstIntOverflow { value: *val, ty: *ty })
                         },
-                        Type::Int32 | Type::UInt32 => { /* can't overflow because IT IS a u32 */ },
+                        Type::Int32 | Type::UInt32 => { /* can't overflow, checked above */ },
+                        Type::Int64 | Type::UInt64 => { /* can't overflow because IT IS a u64 */ },
                         _ => unreachable!()
                     }
                     stack.push(*ty);
                 }
                 InstrK::LdFloat(_) => stack.push(module.float32t()),
-                InstrK::IAdd | InstrK::ISub | InstrK::IMul | InstrK::IDiv | InstrK::ICmp(_) => {
+                InstrK::LdFloat64(_) => stack.push(module.float64t()),
+                InstrK::IAdd | InstrK::ISub | InstrK::IMul | InstrK::IDiv | InstrK::IRem | InstrK::ICmp(_) => {
                     let lhs = stack.pop().ok_or(VerifyError::StackUnderflow)?;
                     let rhs = stack.pop().ok_or(VerifyError::StackUnderflow)?;
 
@@ -90,43 +107,51 @@ impl<'ctx> Verifier {
                     };
                     stack.push(result_ty);
                 },
-                InstrK::FAdd | InstrK::FSub | InstrK::FMul | InstrK::FDiv => {
+                InstrK::FAdd | InstrK::FSub | InstrK::FMul | InstrK::FDiv | InstrK::FCmp(_) |
+                InstrK::FMin | InstrK::FMax | InstrK::FCopysign => {
                     let lhs = stack.pop().ok_or(VerifyError::StackUnderflow)?;
                     let rhs = stack.pop().ok_or(VerifyError::StackUnderflow)?;
 
-                    match (&*lhs, &*rhs) {
-                        (Type::Float32, Type::Float32) => stack.push(module.float32t()),
-                        (Type::Float32, _) => return Err(VerifyError::InvalidType { 
-                            expected: module.float32t(),
-                            actual: rhs,
-                            reason: "Integer arithmetic operation"
-                        }),
-                        _ => return Err(VerifyError::InvalidType { 
-                            expected: module.float32t(),
+                    if !lhs.is_float() {
+                        return Err(VerifyError::InvalidType {
+                            expected: if rhs.is_float() { rhs } else { module.float32t() /* default to f32 */ },
                             actual: lhs,
-                            reason: "Integer arithmetic operation"
+                            reason: "Floating-point numeric operation"
+                        })
+                    } else if lhs != rhs {
+                        return Err(VerifyError::InvalidType {
+                            expected: lhs,
+                            actual: rhs,
+                            reason: "Floating-point numeric operation"
                         })
                     }
+
+                    // The metadata stores the operand type, not necessarily the result type (see below)
+                    out_info.float_instrs_types.insert((block.idx, i), lhs);
+
+                    let result_ty = if let InstrK::FCmp(_) = &instr.kind {
+                        // FCmp is different, because its result is an integer, not a floating point
+                        module.int32t()
+                    } else {
+                        lhs
+                    };
+                    stack.push(result_ty);
                 },
-                /* FCmp is different, because its result is an integer, not a floating point */
-                InstrK::FCmp(_) => {
-                    let lhs = stack.pop().ok_or(VerifyError::StackUnderflow)?;
-                    let rhs = stack.pop().ok_or(VerifyError::StackUnderflow)?;
-                    match (&*lhs, &*rhs) {
-                        (Type::Float32, Type::Float32) => stack.push(module.int32t()),
-                        (Type::Float32, _) => return Err(VerifyError::InvalidType { 
-                            expected: module.float32t(),
-                            actual: rhs,
-                            reason: "Integer arithmetic operation"
-                        }),
-                        _ => return Err(VerifyError::InvalidType { 
+                InstrK::FSqrt | InstrK::FAbs | InstrK::FNeg | InstrK::FFloor |
+                InstrK::FCeil | InstrK::FTrunc | InstrK::FNearest => {
+                    // float -> float
+                    let val = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    if !val.is_float() {
+                        return Err(VerifyError::InvalidType {
                             expected: module.float32t(),
-                            actual: lhs,
-                            reason: "Integer arithmetic operation"
+                            actual: val,
+                            reason: "Floating-point unary operation"
                         })
                     }
-                }
-                InstrK::Itof => {
+                    out_info.float_instrs_types.insert((block.idx, i), val);
+                    stack.push(val);
+                },
+                InstrK::Itof { float_ty } => {
                     let val = stack.pop().ok_or(VerifyError::StackUnderflow)?;
                     if !val.is_int() {
                         return Err(VerifyError::InvalidType { 
@@ -135,9 +160,16 @@ impl<'ctx> Verifier {
                             reason: "Itof instruction"
                         })
                     }
+                    if !float_ty.is_float() {
+                        return Err(VerifyError::InvalidType {
+                            expected: module.float32t(), // default to float32
+                            actual: *float_ty,
+                            reason: "Itof instruction target type"
+                        })
+                    }
                     // Save integer numeric metadata
                     out_info.numeric_instrs_data.insert((block.idx, i), type_to_bws(val).unwrap());
-                    stack.push(module.float32t())
+                    stack.push(*float_ty)
                 }
                 InstrK::Ftoi { int_ty } => {
                     let val = stack.pop().ok_or(VerifyError::StackUnderflow)?;
@@ -145,20 +177,99 @@ impl<'ctx> Verifier {
                         return Err(VerifyError::InvalidType { 
                             expected: module.float32t(),
                             actual: val,
-                            reason: "Itof instruction"
+                            reason: "Ftoi instruction"
                         })
                     }
                     if !int_ty.is_int() {
                         return Err(VerifyError::InvalidType {
                             expected: module.int32t(), // default to int32
                             actual: *int_ty,
-                            reason: "Itof instruction target type"
+                            reason: "Ftoi instruction target type"
                         })
                     }
-                    // Save integer numeric metadata
+                    // Save integer numeric metadata and the source float type
                     out_info.numeric_instrs_data.insert((block.idx, i), type_to_bws(*int_ty).unwrap());
+                    out_info.float_instrs_types.insert((block.idx, i), val);
                     stack.push(*int_ty)
                 }
+                InstrK::VSplat(_) | InstrK::VExtractLane { shape: _, lane: _ } | InstrK::VReplaceLane { shape: _, lane: _ } |
+                InstrK::VAdd(_) | InstrK::VSub(_) | InstrK::VMul(_) => {
+                    if !module.conf.enable_simd {
+                        return Err(VerifyError::SimdDisabled)
+                    }
+                    let result_ty = match &instr.kind {
+                        InstrK::VSplat(shape) => {
+                            let val = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                            verify_vec_lane_type(module, *shape, val)?;
+                            module.v128t()
+                        }
+                        InstrK::VExtractLane { shape, lane } => {
+                            if *lane >= shape.lane_count() {
+                                return Err(VerifyError::OutOfBoundsLaneIndex)
+                            }
+                            let vec = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                            if vec != module.v128t() {
+                                return Err(VerifyError::InvalidType {
+                                    expected: module.v128t(),
+                                    actual: vec,
+                                    reason: "Vector instruction"
+                                })
+                            }
+                            // extracting pushes the lane type
+                            match shape {
+                                VecShape::I32x4 => module.int32t(),
+                                VecShape::F32x4 => module.float32t(),
+                            }
+                        }
+                        InstrK::VReplaceLane { shape, lane } => {
+                            if *lane >= shape.lane_count() {
+                                return Err(VerifyError::OutOfBoundsLaneIndex)
+                            }
+                            let val = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                            verify_vec_lane_type(module, *shape, val)?;
+                            let vec = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                            if vec != module.v128t() {
+                                return Err(VerifyError::InvalidType {
+                                    expected: module.v128t(),
+                                    actual: vec,
+                                    reason: "Vector instruction"
+                                })
+                            }
+                            module.v128t()
+                        }
+                        _ => {
+                            // binary lane-wise operations
+                            for _ in 0..2 {
+                                let vec = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                                if vec != module.v128t() {
+                                    return Err(VerifyError::InvalidType {
+                                        expected: module.v128t(),
+                                        actual: vec,
+                                        reason: "Vector instruction"
+                                    })
+                                }
+                            }
+                            module.v128t()
+                        }
+                    };
+                    stack.push(result_ty)
+                }
+                InstrK::FPromote | InstrK::FDemote => {
+                    let (from, to) = if let InstrK::FPromote = &instr.kind {
+                        (module.float32t(), module.float64t())
+                    } else {
+                        (module.float64t(), module.float32t())
+                    };
+                    let val = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    if val != from {
+                        return Err(VerifyError::InvalidType {
+                            expected: from,
+                            actual: val,
+                            reason: "Float conversion instruction"
+                        })
+                    }
+                    stack.push(to)
+                }
                 InstrK::IConv { target } => {
                     let val = stack.pop().ok_or(VerifyError::StackUnderflow)?;
                     if !val.is_int() {
@@ -180,19 +291,21 @@ impl<'ctx> Verifier {
                     out_info.numeric_instrs_data.insert((block.idx, i), type_to_bws(val).unwrap());
                     stack.push(*target)
                 }
-                InstrK::Not => {
+                InstrK::Not | InstrK::BitNot | InstrK::INeg => {
                     // int -> int
                     let val = stack.pop().ok_or(VerifyError::StackUnderflow)?;
                     if !val.is_int() {
                         return Err(VerifyError::InvalidType { 
                             expected: module.int32t(),
                             actual: val,
-                            reason: "Not instruction"
+                            reason: "Integer unary operation"
                         })
                     }
+                    // Save integer numeric metadata
+                    out_info.numeric_instrs_data.insert((block.idx, i), type_to_bws(val).unwrap());
                     stack.push(val)
                 }
-                InstrK::BitAnd | InstrK::BitOr => {
+                InstrK::BitAnd | InstrK::BitOr | InstrK::BitXor | InstrK::Shl | InstrK::Shr => {
                     // int, int -> int
                     let lhs = stack.pop().ok_or(VerifyError::StackUnderflow)?;
                     let rhs = stack.pop().ok_or(VerifyError::StackUnderflow)?;
@@ -214,10 +327,12 @@ impl<'ctx> Verifier {
                     if !do_int_types_match(lhs, rhs) {
                         return Err(VerifyError::IntegerSizeMismatch {left: lhs, right: rhs})
                     }
+                    // Save integer numeric metadata
+                    out_info.numeric_instrs_data.insert((block.idx, i), type_to_bws(lhs).unwrap());
 
                     stack.push(lhs)
                 }
-                InstrK::CallDirect { func_name } => {
+                InstrK::CallDirect { func_name } | InstrK::TailCallDirect { func_name } => {
                     match module.get_function(func_name) {
                         None => return Err(VerifyError::UndefinedFunctionCall {
                             func_name: func_name.to_owned()
@@ -236,6 +351,9 @@ impl<'ctx> Verifier {
                             }
                             // Add values of the return types
                             stack.extend(func.ret_tys());
+                            if matches!(instr.kind, InstrK::TailCallDirect { func_name: _ }) {
+                                verify_tail_call(module, function, func.ret_tys())?;
+                            }
                         }
                     }
                 }
@@ -270,7 +388,7 @@ impl<'ctx> Verifier {
                         }
                     }
                 },
-                InstrK::CallIndirect => {
+                InstrK::CallIndirect | InstrK::TailCallIndirect => {
                     let func = stack.pop().ok_or(VerifyError::StackUnderflow)?;
                     match &*func {
                         Type::Func { args, ret } => {
@@ -287,6 +405,9 @@ impl<'ctx> Verifier {
                             }
                             // Add values of return types
                             stack.extend(ret);
+                            if matches!(instr.kind, InstrK::TailCallIndirect) {
+                                verify_tail_call(module, function, ret)?;
+                            }
                         },
                         _ => return Err(VerifyError::InvalidTypeCallIndirect)
                     }
@@ -295,20 +416,27 @@ impl<'ctx> Verifier {
                 },
                 InstrK::Bitcast { target } => {
                     let val = stack.pop().ok_or(VerifyError::StackUnderflow)?;
-                    #[allow(clippy::match_single_binding)]
-                    match (&*val, &**target) {
-                        _ => {
-                            // All cases are OK, this might not be true in the future
-                            // that's why this match statement is here
-                            stack.push(*target);
-                        }
+                    verify_not_aggregate(val, "Bitcast instruction")?;
+                    verify_not_aggregate(*target, "Bitcast instruction")?;
+                    // References have no bit representation
+                    verify_storable(val, "Bitcast instruction")?;
+                    verify_storable(*target, "Bitcast instruction")?;
+                    // Only types of the same width can be bitcast between each other
+                    let bit_width = |t: Ty<'ctx>| match &*t {
+                        Type::V128 => 128,
+                        Type::Int64 | Type::UInt64 | Type::Float64 => 64,
+                        _ => 32
+                    };
+                    if bit_width(val) != bit_width(*target) {
+                        return Err(VerifyError::InvalidBitcast { from: val, to: *target })
                     }
+                    stack.push(*target);
                     out_info.bitcast_source_types.insert((this_block_id, i), val);
                 }
                 InstrK::IfElse { then, r#else } => {
                     // the condition
                     let cond = stack.pop().ok_or(VerifyError::StackUnderflow)?;
-                    if !cond.is_int() {
+                    if !cond.is_int() || cond.is_int64() {
                         return Err(VerifyError::InvalidType { 
                             expected: module.int32t(),
                             actual: cond,
@@ -316,16 +444,13 @@ impl<'ctx> Verifier {
                         })
                     }
                     // verify the block types are the same
-                    let then_block_returns = 
-                        function.get_block(*then)
-                        .ok_or(VerifyError::InvalidBlockId)?
-                        .returns();
+                    let then_block = function.get_block(*then).ok_or(VerifyError::InvalidBlockId)?;
+                    let then_block_returns = then_block.returns();
                     match r#else {
                         Some(i) => {
-                            let else_block_returns = 
-                                function.get_block(*i)
-                                .ok_or(VerifyError::InvalidBlockId)?
-                                .returns();
+                            let else_block = function.get_block(*i).ok_or(VerifyError::InvalidBlockId)?;
+                            verify_same_params(then_block, else_block)?;
+                            let else_block_returns = else_block.returns();
                             
                             if then_block_returns != else_block_returns {
                                 return Err(VerifyError::InvalidBlockType {
@@ -336,25 +461,101 @@ impl<'ctx> Verifier {
                             }
                         }
                         None => {
-                            /* if the else block is None, its' return is [] */
-                            if !then_block_returns.is_empty() {
+                            /* if the else block is None, it returns its parameters unchanged */
+                            if then_block_returns != then_block.params() {
                                 return Err(VerifyError::InvalidBlockType {
                                     block: *then,
-                                    expected: vec![],
+                                    expected: then_block.params().clone(),
                                     actual: then_block_returns.clone()
                                 })
                             }
                         }
                     }
+                    pop_block_params(&mut stack, then_block)?;
                     // push the values onto the stack
                     stack.extend_from_slice(then_block_returns);
                 }
-                InstrK::Read { ty } => {
-                    if ty.is_struct() {
-                        return Err(VerifyError::UnexpectedStructType {
-                            r#where: "Read instruction"
+                InstrK::Switch { cases, default } => {
+                    // the index
+                    let index = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    if !index.is_int() || index.is_int64() {
+                        return Err(VerifyError::InvalidType {
+                            expected: module.int32t(),
+                            actual: index,
+                            reason: "Switch index"
                         })
                     }
+                    // verify all the cases have the same type as the default block
+                    let default_block = function.get_block(*default).ok_or(VerifyError::InvalidBlockId)?;
+                    let default_block_returns = default_block.returns();
+                    for case in cases {
+                        let case_block = function.get_block(*case).ok_or(VerifyError::InvalidBlockId)?;
+                        verify_same_params(default_block, case_block)?;
+                        let case_block_returns = case_block.returns();
+                        if case_block_returns != default_block_returns {
+                            return Err(VerifyError::InvalidBlockType {
+                                block: *case,
+                                expected: default_block_returns.clone(),
+                                actual: case_block_returns.clone()
+                            })
+                        }
+                    }
+                    pop_block_params(&mut stack, default_block)?;
+                    if !default_block.params().is_empty() {
+                        // The emitter enters every case through a wasm block
+                        // which passes the parameters through
+                        let params = default_block.params().clone();
+                        module.intern_type(Type::Func { args: params.clone(), ret: params });
+                    }
+                    // push the values onto the stack
+                    stack.extend_from_slice(default_block_returns);
+                }
+                InstrK::TryCatch { body, catches, catch_all } => {
+                    if !module.conf.enable_exceptions {
+                        return Err(VerifyError::ExceptionsDisabled)
+                    }
+                    // verify all the handlers have the same type as the body,
+                    // their tags and parameters were checked before verifying the blocks
+                    let body_block = function.get_block(*body).ok_or(VerifyError::InvalidBlockId)?;
+                    let body_block_returns = body_block.returns();
+                    for handler in catches.iter().map(|(_, handler)| handler).chain(catch_all) {
+                        let handler_block_returns =
+                            function.get_block(*handler)
+                            .ok_or(VerifyError::InvalidBlockId)?
+                            .returns();
+                        if handler_block_returns != body_block_returns {
+                            return Err(VerifyError::InvalidBlockType {
+                                block: *handler,
+                                expected: body_block_returns.clone(),
+                                actual: handler_block_returns.clone()
+                            })
+                        }
+                    }
+                    pop_block_params(&mut stack, body_block)?;
+                    // push the values onto the stack
+                    stack.extend_from_slice(body_block_returns);
+                }
+                InstrK::Throw { tag } => {
+                    if !module.conf.enable_exceptions {
+                        return Err(VerifyError::ExceptionsDisabled)
+                    }
+                    let tag = module.get_exception_tag(tag)
+                        .ok_or_else(|| VerifyError::UndefinedExceptionTag { name: tag.to_owned() })?;
+                    // the last payload value is on the top of the stack
+                    for &ty in tag.payload().iter().rev() {
+                        let val = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                        if val != ty {
+                            return Err(VerifyError::InvalidType {
+                                expected: ty,
+                                actual: val,
+                                reason: "Exception payload"
+                            })
+                        }
+                    }
+                }
+                InstrK::Read { ty } => {
+                    verify_value_type(module, *ty, "Read instruction")?;
+                    verify_storable(*ty, "Read instruction")?;
                     let ptr = stack.pop().ok_or(VerifyError::StackUnderflow)?;
                     if !ptr.is_ptr() {
                         return Err(VerifyError::InvalidType { 
@@ -366,11 +567,8 @@ impl<'ctx> Verifier {
                     stack.push(*ty);
                 }
                 InstrK::Write { ty } => {
-                    if ty.is_struct() {
-                        return Err(VerifyError::UnexpectedStructType {
-                            r#where: "Read instruction"
-                        })
-                    }
+                    verify_value_type(module, *ty, "Write instruction")?;
+                    verify_storable(*ty, "Write instruction")?;
                     let val = stack.pop().ok_or(VerifyError::StackUnderflow)?;
                     if val != *ty {
                         return Err(VerifyError::InvalidType {
@@ -388,10 +586,14 @@ impl<'ctx> Verifier {
                         })
                     }
                 }
-                InstrK::Offset { ty: _ } => {
+                InstrK::Offset { ty } => {
+                    if ty.is_opaque() {
+                        return Err(VerifyError::OpaqueStructType { ty: *ty })
+                    }
+                    verify_storable(*ty, "Offset instruction")?;
                     // Offset requires an integer and a pointer, pushes a pointer
                     let num = stack.pop().ok_or(VerifyError::StackUnderflow)?;
-                    if !num.is_int() {
+                    if !num.is_int() || num.is_int64() {
                         return Err(VerifyError::InvalidType {
                             expected: module.int32t(),
                             actual: num,
@@ -408,17 +610,43 @@ impl<'ctx> Verifier {
                     }
                     stack.push(module.ptr_t());
                 }
+                InstrK::OffsetConst { ty, n: _ } => {
+                    if ty.is_opaque() {
+                        return Err(VerifyError::OpaqueStructType { ty: *ty })
+                    }
+                    verify_storable(*ty, "OffsetConst instruction")?;
+                    // OffsetConst only requires a pointer, pushes a pointer
+                    let ptr = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    if !ptr.is_ptr() {
+                        return Err(VerifyError::InvalidType {
+                            expected: module.ptr_t(),
+                            actual: ptr,
+                            reason: "OffsetConst instruction"
+                        })
+                    }
+                    stack.push(module.ptr_t());
+                }
+                InstrK::StackAlloc { ty } => {
+                    if ty.is_opaque() {
+                        return Err(VerifyError::OpaqueStructType { ty: *ty })
+                    }
+                    verify_storable(*ty, "StackAlloc instruction")?;
+                    verify_type_enabled(module, *ty)?;
+                    stack.push(module.ptr_t());
+                }
                 InstrK::GetFieldPtr { struct_ty, field_idx } => {
                     // Verify the type is, in fact, a struct type
                     if !struct_ty.is_struct() {
                         return Err(VerifyError::GetFieldPtrExpectedStructType)
                     }
+                    // The layout of the struct must be known
+                    if struct_ty.is_opaque() {
+                        return Err(VerifyError::OpaqueStructType { ty: *struct_ty })
+                    }
+                    verify_storable(*struct_ty, "GetFieldPtr instruction")?;
                     // Verify the index doesn't point out of bounds
-                    let struct_field_count = match &**struct_ty {
-                        Type::Struct { fields } => fields.len(),
-                        _ => unreachable!()
-                    };
-                    if *field_idx > struct_field_count {
+                    let struct_field_count = struct_ty.struct_fields().unwrap().len();
+                    if *field_idx >= struct_field_count {
                         return Err(VerifyError::OutOfBoundsStructIndex)
                     }
                     // Verify there's a pointer type on stack
@@ -432,10 +660,119 @@ impl<'ctx> Verifier {
                     }
                     stack.push(module.ptr_t());
                 }
+                InstrK::GetElemPtr { array_ty } => {
+                    // Verify the type is, in fact, an array type
+                    if !array_ty.is_array() {
+                        return Err(VerifyError::GetElemPtrExpectedArrayType)
+                    }
+                    if array_ty.is_opaque() {
+                        return Err(VerifyError::OpaqueStructType { ty: *array_ty })
+                    }
+                    verify_storable(*array_ty, "GetElemPtr instruction")?;
+                    // Verify there's an index and a pointer on stack
+                    let index = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    if !index.is_int() || index.is_int64() {
+                        return Err(VerifyError::InvalidType {
+                            expected: module.int32t(),
+                            actual: index,
+                            reason: "GetElemPtr instruction"
+                        })
+                    }
+                    let ptr = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    if !ptr.is_ptr() {
+                        return Err(VerifyError::InvalidType { 
+                            expected: module.ptr_t(),
+                            actual: ptr,
+                            reason: "GetElemPtr instruction"
+                        })
+                    }
+                    stack.push(module.ptr_t());
+                }
+                InstrK::GetTag { union_ty } | InstrK::SetTag { union_ty, variant_idx: _ } |
+                InstrK::GetVariantPtr { union_ty, variant_idx: _ } => {
+                    // Verify the type is, in fact, a tagged union type
+                    let variant_count = match &**union_ty {
+                        Type::TaggedUnion { variants } => variants.len(),
+                        _ => return Err(VerifyError::ExpectedTaggedUnionType)
+                    };
+                    if union_ty.is_opaque() {
+                        return Err(VerifyError::OpaqueStructType { ty: *union_ty })
+                    }
+                    verify_storable(*union_ty, "Tagged union instruction")?;
+                    // Verify the index doesn't point out of bounds
+                    match &instr.kind {
+                        InstrK::SetTag { union_ty: _, variant_idx } | 
+                        InstrK::GetVariantPtr { union_ty: _, variant_idx } if *variant_idx >= variant_count => {
+                            return Err(VerifyError::OutOfBoundsVariantIndex)
+                        },
+                        _ => {}
+                    }
+                    // Verify there's a pointer type on stack
+                    let ptr = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    if !ptr.is_ptr() {
+                        return Err(VerifyError::InvalidType { 
+                            expected: module.ptr_t(),
+                            actual: ptr,
+                            reason: "Tagged union instruction"
+                        })
+                    }
+                    match &instr.kind {
+                        InstrK::GetTag { union_ty: _ } => stack.push(module.uint32t()),
+                        InstrK::GetVariantPtr { union_ty: _, variant_idx: _ } => stack.push(module.ptr_t()),
+                        _ => { /* SetTag doesn't push anything */ }
+                    }
+                }
                 InstrK::Discard => {
-                    if stack.pop().is_none() {
-                        return Err(VerifyError::StackUnderflow);
+                    let val = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    out_info.stack_instrs_types.insert((this_block_id, i), (val, None));
+                }
+                InstrK::Dup => {
+                    let val = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    verify_not_aggregate(val, "Dup instruction")?;
+                    stack.push(val);
+                    stack.push(val);
+                    out_info.stack_instrs_types.insert((this_block_id, i), (val, None));
+                }
+                InstrK::Swap => {
+                    let top = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    let below = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    verify_not_aggregate(top, "Swap instruction")?;
+                    verify_not_aggregate(below, "Swap instruction")?;
+                    stack.push(top);
+                    stack.push(below);
+                    out_info.stack_instrs_types.insert((this_block_id, i), (below, Some(top)));
+                }
+                InstrK::Over => {
+                    let top = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    let below = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    verify_not_aggregate(top, "Over instruction")?;
+                    verify_not_aggregate(below, "Over instruction")?;
+                    stack.push(below);
+                    stack.push(top);
+                    stack.push(below);
+                    out_info.stack_instrs_types.insert((this_block_id, i), (below, Some(top)));
+                }
+                InstrK::Select => {
+                    let cond = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    if !cond.is_int() || cond.is_int64() {
+                        return Err(VerifyError::InvalidType {
+                            expected: module.int32t(),
+                            actual: cond,
+                            reason: "Select condition"
+                        })
+                    }
+                    let val2 = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    let val1 = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    verify_not_aggregate(val1, "Select instruction")?;
+                    if val1 != val2 {
+                        return Err(VerifyError::InvalidType {
+                            expected: val1,
+                            actual: val2,
+                            reason: "Select instruction"
+                        })
                     }
+                    stack.push(val1);
+                    out_info.select_types.insert((this_block_id, i), val1);
                 }
                 InstrK::Return => {
                     if stack.len() != function.ret_count() {
@@ -459,7 +796,7 @@ impl<'ctx> Verifier {
                 InstrK::MemoryGrow => {
                     // pops an int and pushes it again
                     let val = stack.pop().ok_or(VerifyError::StackUnderflow)?;
-                    if !val.is_int() {
+                    if !val.is_int() || val.is_int64() {
                         return Err(VerifyError::InvalidType { 
                             expected: module.int32t(),
                             actual: val,
@@ -468,6 +805,98 @@ impl<'ctx> Verifier {
                     }
                     stack.push(val); // it's an int
                 }
+                InstrK::AtomicRead { ty: _ } | InstrK::AtomicWrite { ty: _ } | InstrK::AtomicRmw { op: _, ty: _ } |
+                InstrK::AtomicCmpxchg { ty: _ } | InstrK::AtomicWait { ty: _ } | InstrK::AtomicNotify => {
+                    if !module.conf.shared_memory {
+                        return Err(VerifyError::SharedMemoryDisabled)
+                    }
+                    // The operands after the pointer (the last one is on the top of the stack)
+                    // and the result
+                    let (operands, result) = match &instr.kind {
+                        InstrK::AtomicRead { ty } => (vec![], Some(*ty)),
+                        InstrK::AtomicWrite { ty } => (vec![*ty], None),
+                        InstrK::AtomicRmw { op: _, ty } => (vec![*ty], Some(*ty)),
+                        InstrK::AtomicCmpxchg { ty } => (vec![*ty, *ty], Some(*ty)),
+                        InstrK::AtomicWait { ty } => (vec![*ty, module.int64t()], Some(module.int32t())),
+                        InstrK::AtomicNotify => (vec![module.int32t()], Some(module.int32t())),
+                        _ => unreachable!()
+                    };
+                    match &instr.kind {
+                        InstrK::AtomicNotify => {}
+                        InstrK::AtomicWait { ty } if !matches!(type_to_bws(*ty),
+                            Some(BitWidthSign::S32 | BitWidthSign::U32 | BitWidthSign::S64 | BitWidthSign::U64)) => {
+                            return Err(VerifyError::InvalidType {
+                                expected: module.int32t(),
+                                actual: *ty,
+                                reason: "AtomicWait instruction"
+                            })
+                        }
+                        // the type is always the first operand or the result
+                        _ => {
+                            let ty = operands.first().copied().or(result).unwrap();
+                            if !ty.is_int() {
+                                return Err(VerifyError::InvalidType {
+                                    expected: module.int32t(),
+                                    actual: ty,
+                                    reason: "Atomic instruction"
+                                })
+                            }
+                        }
+                    }
+                    for expected in operands.iter().rev() {
+                        let val = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                        if val != *expected {
+                            return Err(VerifyError::InvalidType {
+                                expected: *expected,
+                                actual: val,
+                                reason: "Atomic instruction"
+                            })
+                        }
+                    }
+                    let ptr = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    if !ptr.is_ptr() {
+                        return Err(VerifyError::InvalidType {
+                            expected: module.ptr_t(),
+                            actual: ptr,
+                            reason: "Atomic instruction"
+                        })
+                    }
+                    stack.extend(result);
+                }
+                InstrK::MemCopy | InstrK::MemFill => {
+                    // pops a length, a source pointer (or a value) and a destination pointer
+                    let len = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    if !len.is_int() || len.is_int64() {
+                        return Err(VerifyError::InvalidType {
+                            expected: module.int32t(),
+                            actual: len,
+                            reason: "MemCopy/MemFill length"
+                        })
+                    }
+                    let src = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    if matches!(instr.kind, InstrK::MemCopy) && !src.is_ptr() {
+                        return Err(VerifyError::InvalidType {
+                            expected: module.ptr_t(),
+                            actual: src,
+                            reason: "MemCopy source"
+                        })
+                    }
+                    if matches!(instr.kind, InstrK::MemFill) && (!src.is_int() || src.is_int64()) {
+                        return Err(VerifyError::InvalidType {
+                            expected: module.int32t(),
+                            actual: src,
+                            reason: "MemFill value"
+                        })
+                    }
+                    let dst = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    if !dst.is_ptr() {
+                        return Err(VerifyError::InvalidType {
+                            expected: module.ptr_t(),
+                            actual: dst,
+                            reason: "MemCopy/MemFill destination"
+                        })
+                    }
+                }
                 InstrK::LdGlobal(name) => {
                     let g = module.get_global(name).ok_or_else(|| VerifyError::UndefinedGlobal { name: name.clone() })?;
                     stack.push(g.ty);
@@ -483,18 +912,25 @@ impl<'ctx> Verifier {
                         })
                     }
                 }
+                InstrK::LdNullRef => stack.push(module.extern_ref_t()),
+                InstrK::IsNullRef => {
+                    let val = stack.pop().ok_or(VerifyError::StackUnderflow)?;
+                    if !val.is_extern_ref() {
+                        return Err(VerifyError::InvalidType {
+                            expected: module.extern_ref_t(),
+                            actual: val,
+                            reason: "IsNullRef instruction"
+                        })
+                    }
+                    stack.push(module.int32t());
+                }
                 InstrK::Fail => {}
                 InstrK::Loop(body) => {
-                    // Verify that the body block's type is () -> ()
+                    // The loop produces the values its body block returns,
+                    // whether they're really left by the breaks is checked at every break
                     let body_block_returns = function.get_block(*body)
                         .ok_or(VerifyError::InvalidBlockId)?.returns();
-                    if !body_block_returns.is_empty() {
-                        return Err(VerifyError::InvalidBlockType {
-                            block: *body,
-                            expected: vec![],
-                            actual: body_block_returns.clone()
-                        })
-                    }
+                    stack.extend(body_block_returns.iter().copied());
                 }
                 InstrK::Break => {
                     // The Verifier runs AFTER the CF-Verifier
@@ -502,9 +938,18 @@ impl<'ctx> Verifier {
                     // Verify that this block HAS the metadata, in other words,
                     // that it's part of SOME loop
                     // because it's invalid to use `Break` without a `Loop`
-                    if block.meta.retrieve::<usize>(key!("innermost_loop_distance")).is_none() {
-                        return Err(VerifyError::BreakWithoutLoop)
-                    }
+                    let loop_body: BlockId = block.meta.retrieve_copied(key!("innermost_loop"))
+                        .ok_or(VerifyError::BreakWithoutLoop)?;
+                    verify_break_values(&stack, function, loop_body)?;
+                }
+                InstrK::BreakLoop(loop_body) => {
+                    // The CF-Verifier already checked that the target loop
+                    // encloses this block
+                    verify_break_values(&stack, function, *loop_body)?;
+                }
+                InstrK::ContinueLoop(_) => {
+                    // The CF-Verifier already checked that the target loop
+                    // encloses this block, nothing is popped or pushed
                 }
                 InstrK::LdStaticMemPtr(_) => {
                     stack.push(module.ptr_t())
@@ -522,11 +967,21 @@ impl<'ctx> Verifier {
             }
         }
 
+        // The end of a loop body continues with the next iteration,
+        // values can only leave the loop with a break
+        if block.tag == BlockTag::Loop && !stack.is_empty() {
+            return Err(VerifyError::InvalidBlockType {
+                block: this_block_id,
+                expected: vec![],
+                actual: stack
+            })
+        }
+
         // at the end of the block, check if the types left on the stack
         // agree with the block's type
-        if !stack.iter()
+        if block.tag != BlockTag::Loop && (stack.len() != block.returns().len() || !stack.iter()
             .zip(block.returns().iter())
-            .all(|(t1, t2)| *t1 == *t2) {
+            .all(|(t1, t2)| *t1 == *t2)) {
             // if not all types are equal =>
             return Err(VerifyError::InvalidBlockType {
                 block: this_block_id,
@@ -538,33 +993,225 @@ impl<'ctx> Verifier {
         Ok(())
     }
 
-    /// Ensure that there are no arguments, return values, locals or block types with a bare `struct` type
-    fn verify_no_struct_types(&self, function: &crate::instr::Function<'ctx>) -> Result<(), VerifyError<'ctx>> {
+    /// Ensure that all arguments, return values, locals and block types can be used as values
+    fn verify_value_types(&self, module: &crate::module::Module<'ctx>, function: &crate::instr::Function<'ctx>) -> Result<(), VerifyError<'ctx>> {
         for ty in function.all_locals_ty() {
-            if ty.is_struct() {
-                return Err(VerifyError::UnexpectedStructType { r#where: "Function local" })
-            }
+            verify_value_type(module, *ty, "Function local")?;
         }
         for ty in function.ret_tys() {
-            if ty.is_struct() {
-                return Err(VerifyError::UnexpectedStructType { r#where: "Function return value" })
-            }
+            verify_value_type(module, *ty, "Function return value")?;
         }
         for block in function.blocks_iter() {
+            for ty in block.params() {
+                verify_value_type(module, *ty, "Block parameter")?;
+            }
             for ty in block.returns() {
-                if ty.is_struct() {
-                    return Err(VerifyError::UnexpectedStructType { r#where: "Block return value" })
+                verify_value_type(module, *ty, "Block return value")?;
+            }
+        }
+        for tag in module.exception_tags_iter() {
+            for ty in tag.payload() {
+                verify_not_aggregate(*ty, "Exception payload")?;
+                verify_type_enabled(module, *ty)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Collect the types every block starts with on its stack: its parameters,
+    /// or the payload of the exception for catch handler blocks.
+    ///
+    /// The main block, loop bodies and catch handlers can't have parameters,
+    /// nothing passes them any values.
+    fn initial_stacks(
+        &self,
+        module: &crate::module::Module<'ctx>,
+        function: &crate::instr::Function<'ctx>) -> Result<HashMap<BlockId, Vec<Ty<'ctx>>>, VerifyError<'ctx>> {
+
+        let no_params = |block: BlockId| {
+            let block = function.get_block(block).ok_or(VerifyError::InvalidBlockId)?;
+            if block.params().is_empty() {
+                Ok(())
+            } else {
+                Err(VerifyError::BlockParamsNotAllowed { block: block.idx })
+            }
+        };
+        no_params(function.entry_block().idx)?;
+
+        let mut stacks: HashMap<BlockId, Vec<Ty<'ctx>>> = function.blocks_iter()
+            .map(|block| (block.idx, block.params().clone()))
+            .collect();
+        for instr in function.blocks_iter().flat_map(|b| b.body.iter()) {
+            match &instr.kind {
+                InstrK::Loop(body) => no_params(*body)?,
+                InstrK::TryCatch { body: _, catches, catch_all } => {
+                    for (tag, handler) in catches {
+                        no_params(*handler)?;
+                        let tag = module.get_exception_tag(tag)
+                            .ok_or_else(|| VerifyError::UndefinedExceptionTag { name: tag.to_owned() })?;
+                        stacks.insert(*handler, tag.payload().clone());
+                    }
+                    if let Some(handler) = catch_all {
+                        no_params(*handler)?;
+                    }
                 }
+                _ => {}
             }
         }
+        Ok(stacks)
+    }
+}
+
+/// A tail call returns the results of the called function from the current function,
+/// so they must have the same result types.
+///
+/// The frame of the current function is popped before the tail call,
+/// so the memory allocated by `StackAlloc` wouldn't live long enough to be passed to it
+fn verify_tail_call<'ctx>(
+    module: &crate::module::Module<'ctx>,
+    function: &crate::instr::Function<'ctx>,
+    callee_rets: &[Ty<'ctx>]) -> Result<(), VerifyError<'ctx>> {
+
+    if !module.conf.enable_tail_calls {
+        return Err(VerifyError::TailCallsDisabled)
+    }
+    if function.uses_stack_alloc() {
+        return Err(VerifyError::TailCallWithStackAlloc)
+    }
+    if callee_rets != function.ret_tys().as_slice() {
+        return Err(VerifyError::TailCallResultMismatch {
+            expected: function.ret_tys().clone(),
+            actual: callee_rets.to_vec()
+        })
+    }
+    Ok(())
+}
+
+/// Pop the values passed to a block off the stack, they must have the types of its parameters
+fn pop_block_params<'ctx>(stack: &mut Vec<Ty<'ctx>>, block: &InstrBlock<'ctx>) -> Result<(), VerifyError<'ctx>> {
+    let params = block.params();
+    if stack.len() < params.len() {
+        return Err(VerifyError::StackUnderflow)
+    }
+    let values = stack.split_off(stack.len() - params.len());
+    if &values != params {
+        return Err(VerifyError::InvalidBlockType {
+            block: block.idx,
+            expected: params.clone(),
+            actual: values
+        })
+    }
+    Ok(())
+}
+
+/// Blocks which a branch instruction chooses between must take the same parameters
+fn verify_same_params<'ctx>(first: &InstrBlock<'ctx>, other: &InstrBlock<'ctx>) -> Result<(), VerifyError<'ctx>> {
+    if first.params() != other.params() {
+        return Err(VerifyError::InvalidBlockType {
+            block: other.idx,
+            expected: first.params().clone(),
+            actual: other.params().clone()
+        })
+    }
+    Ok(())
+}
+
+/// Check that the values on the stack at a break are the values the loop produces
+fn verify_break_values<'ctx>(
+    stack: &[Ty<'ctx>],
+    function: &crate::instr::Function<'ctx>,
+    loop_body: BlockId) -> Result<(), VerifyError<'ctx>> {
+
+    let loop_returns = function.get_block(loop_body).ok_or(VerifyError::InvalidBlockId)?.returns();
+    if stack != loop_returns.as_slice() {
+        return Err(VerifyError::InvalidBlockType {
+            block: loop_body,
+            expected: loop_returns.clone(),
+            actual: stack.to_vec()
+        })
+    }
+    Ok(())
+}
+
+/// Struct, array and union types can't be used as values (in locals, on the stack etc.)
+fn verify_not_aggregate<'ctx>(ty: Ty<'ctx>, r#where: &'static str) -> Result<(), VerifyError<'ctx>> {
+    if ty.is_struct() {
+        Err(VerifyError::UnexpectedStructType { r#where })
+    } else if ty.is_array() {
+        Err(VerifyError::UnexpectedArrayType { r#where })
+    } else if ty.is_tagged_union() {
+        Err(VerifyError::UnexpectedTaggedUnionType { r#where })
+    } else {
         Ok(())
     }
 }
 
+/// A struct type can be used as a value if all its fields can,
+/// its values are flattened into scalars (see [`flatten_type`](crate::abi::flatten_type)).
+/// The flattening uses the memory layout of the struct, so it must also be storable.
+/// Other types are checked with [`verify_not_aggregate`] and [`verify_type_enabled`]
+fn verify_value_type<'ctx>(module: &crate::module::Module<'ctx>, ty: Ty<'ctx>, r#where: &'static str) -> Result<(), VerifyError<'ctx>> {
+    match ty.struct_fields() {
+        Some(fields) => {
+            verify_storable(ty, r#where)?;
+            fields.iter().try_for_each(|field| verify_value_type(module, *field, r#where))
+        }
+        None if ty.is_struct() => Err(VerifyError::OpaqueStructType { ty }),
+        None => {
+            verify_not_aggregate(ty, r#where)?;
+            verify_type_enabled(module, ty)
+        }
+    }
+}
+
+/// References can't be stored in memory, so they can't be read, written,
+/// bitcast or be a part of a type which lives in memory
+fn verify_storable<'ctx>(ty: Ty<'ctx>, r#where: &'static str) -> Result<(), VerifyError<'ctx>> {
+    if ty.contains_extern_ref() {
+        return Err(VerifyError::UnexpectedExternRefType { r#where })
+    }
+    Ok(())
+}
+
+/// Some types require a WebAssembly feature to be enabled in the module configuration
+fn verify_type_enabled<'ctx>(module: &crate::module::Module<'ctx>, ty: Ty<'ctx>) -> Result<(), VerifyError<'ctx>> {
+    if matches!(&*ty, Type::V128) && !module.conf.enable_simd {
+        return Err(VerifyError::SimdDisabled)
+    }
+    Ok(())
+}
+
+/// Verify a scalar value has the type of the lanes of a vector
+fn verify_vec_lane_type<'ctx>(module: &crate::module::Module<'ctx>, shape: VecShape, val: Ty<'ctx>) -> Result<(), VerifyError<'ctx>> {
+    let ok = match shape {
+        VecShape::I32x4 => val.is_int() && !val.is_int64(),
+        VecShape::F32x4 => val == module.float32t(),
+    };
+    if !ok {
+        return Err(VerifyError::InvalidType {
+            expected: match shape {
+                VecShape::I32x4 => module.int32t(),
+                VecShape::F32x4 => module.float32t(),
+            },
+            actual: val,
+            reason: "Vector lane value"
+        })
+    }
+    Ok(())
+}
+
 impl<'ctx> MutableFunctionPass<'ctx> for Verifier {
     type Error = VerifyError<'ctx>;
     type MutationInfo = VerifierMutInfo<'ctx>;
 
+    fn visit_module(&mut self, module: &crate::module::Module<'ctx>) -> Result<(), Self::Error> {
+        // The configuration is checked once, even if there are no functions
+        if module.conf.shared_memory && module.conf.maximum_memory_size.is_none() {
+            return Err(VerifyError::SharedMemoryWithoutMaximum)
+        }
+        Ok(())
+    }
+
     fn visit_function(
         &mut self, 
         module: &crate::module::Module<'ctx>,
@@ -573,11 +1220,15 @@ impl<'ctx> MutableFunctionPass<'ctx> for Verifier {
         let mut info = VerifierMutInfo {
             call_indirect_function_types: HashMap::new(),
             bitcast_source_types: HashMap::new(),
-            numeric_instrs_data: HashMap::new()
+            numeric_instrs_data: HashMap::new(),
+            float_instrs_types: HashMap::new(),
+            select_types: HashMap::new(),
+            stack_instrs_types: HashMap::new()
         };
 
         // do this before verifying the blocks themselves
-        self.verify_no_struct_types(function)?;
+        self.verify_value_types(module, function)?;
+        let mut initial_stacks = self.initial_stacks(module, function)?;
         
         for block in function.blocks_iter() {
             self.verify_block(
@@ -585,7 +1236,8 @@ impl<'ctx> MutableFunctionPass<'ctx> for Verifier {
                 block.idx,
                 module,
                 function,
-                block
+                block,
+                initial_stacks.remove(&block.idx).unwrap_or_default()
             )?
         }
 
@@ -608,7 +1260,7 @@ impl<'ctx> MutableFunctionPass<'ctx> for Verifier {
                 if info.call_indirect_function_types.contains_key(&key) {
                     let function_ty = info.call_indirect_function_types[&key];
     
-                    debug_assert!(matches!(instr.kind, InstrK::CallIndirect));
+                    debug_assert!(matches!(instr.kind, InstrK::CallIndirect | InstrK::TailCallIndirect));
     
                     instr.meta.insert_ty(key!("ty"), function_ty)
                 }
@@ -626,6 +1278,31 @@ impl<'ctx> MutableFunctionPass<'ctx> for Verifier {
 
                     instr.meta.insert(key!("bws"), bws)
                 }
+
+                if info.float_instrs_types.contains_key(&key) {
+                    let float_ty = info.float_instrs_types[&key];
+
+                    instr.meta.insert_ty(key!("ty"), float_ty)
+                }
+
+                if info.select_types.contains_key(&key) {
+                    let select_ty = info.select_types[&key];
+
+                    debug_assert!(matches!(instr.kind, InstrK::Select));
+
+                    instr.meta.insert_ty(key!("ty"), select_ty)
+                }
+
+                if info.stack_instrs_types.contains_key(&key) {
+                    let (ty, ty2) = info.stack_instrs_types[&key];
+
+                    debug_assert!(matches!(instr.kind, InstrK::Discard | InstrK::Dup | InstrK::Swap | InstrK::Over));
+
+                    instr.meta.insert_ty(key!("ty"), ty);
+                    if let Some(ty2) = ty2 {
+                        instr.meta.insert_ty(key!("ty2"), ty2);
+                    }
+                }
             }
 
         }
@@ -644,13 +1321,95 @@ pub enum VerifyError<'ctx> {
     OutOfBoundsLocalIndex,
     InvalidTypeCallIndirect,
     InvalidBlockType { block: BlockId, expected: Vec<Ty<'ctx>>, actual: Vec<Ty<'ctx>> },
+    BlockParamsNotAllowed { block: BlockId },
     InvalidBlockId,
     UnexpectedStructType { r#where: &'static str },
     GetFieldPtrExpectedStructType,
     OutOfBoundsStructIndex,
+    UnexpectedArrayType { r#where: &'static str },
+    GetElemPtrExpectedArrayType,
+    OpaqueStructType { ty: Ty<'ctx> },
+    UnexpectedTaggedUnionType { r#where: &'static str },
+    ExpectedTaggedUnionType,
+    OutOfBoundsVariantIndex,
+    SimdDisabled,
+    SharedMemoryDisabled,
+    SharedMemoryWithoutMaximum,
+    TailCallsDisabled,
+    TailCallResultMismatch { expected: Vec<Ty<'ctx>>, actual: Vec<Ty<'ctx>> },
+    TailCallWithStackAlloc,
+    ExceptionsDisabled,
+    UndefinedExceptionTag { name: String },
+    OutOfBoundsLaneIndex,
+    UnexpectedExternRefType { r#where: &'static str },
     UndefinedGlobal { name: String },
     IntegerSizeMismatch { left: Ty<'ctx>, right: Ty<'ctx>},
-    ConstIntOverflow { value: u32, ty: Ty<'ctx> },
+    ConstIntOverflow { value: u64, ty: Ty<'ctx> },
     ArgumentStore { idx: usize },
-    BreakWithoutLoop
-}
\ No newline at end of file
+    BreakWithoutLoop,
+    InvalidBitcast { from: Ty<'ctx>, to: Ty<'ctx> }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{builder::{FunctionBuilder, InstrBuilder}, cf_verify::ControlFlowVerifier, instr::BlockTag, module::{Module, WasmModuleConf}, ty::Type};
+
+    use super::{VerifyError, Verifier};
+
+    #[test]
+    pub fn block_with_params_extra_values_test() {
+        let mut m = Module::default();
+
+        let mut builder = FunctionBuilder::new("func".to_string(), [m.int32t()], [m.int32t()]);
+        let arg0 = builder.get_arg(0);
+        let then_block = builder.new_block_with_params([m.int32t()], [m.int32t()], BlockTag::IfElse);
+        let else_block = builder.new_block_with_params([m.int32t()], [m.int32t()], BlockTag::IfElse);
+        builder.i_ld_local(arg0);
+        builder.i_ld_local(arg0);
+        builder.i_if_else(then_block, Some(else_block));
+        // the parameter stays on the stack below the new value
+        builder.switch_block(then_block);
+        builder.i_ld_int(1, m.int32t());
+        builder.finish(&mut m);
+
+        m.do_mut_pass(&mut ControlFlowVerifier{}).unwrap();
+        assert!(matches!(
+            m.do_mut_pass(&mut Verifier{}),
+            Err(VerifyError::InvalidBlockType { block, expected: _, actual }) if block == then_block && actual.len() == 2));
+    }
+
+    #[test]
+    pub fn struct_value_with_extern_ref_test() {
+        let mut m = Module::default();
+
+        let struct_t = m.intern_type(Type::Struct { fields: vec![m.int32t(), m.extern_ref_t()] });
+        let mut builder = FunctionBuilder::new("func".to_string(), [struct_t], [struct_t]);
+        let arg0 = builder.get_arg(0);
+        builder.i_ld_local(arg0);
+        builder.finish(&mut m);
+
+        m.do_mut_pass(&mut ControlFlowVerifier{}).unwrap();
+        assert!(matches!(m.do_mut_pass(&mut Verifier{}), Err(VerifyError::UnexpectedExternRefType { r#where: _ })));
+    }
+
+    #[test]
+    pub fn shared_memory_without_maximum_test() {
+        // the configuration is checked even without any functions
+        let mut m = Module::new(WasmModuleConf { shared_memory: true, ..WasmModuleConf::default() });
+        assert!(matches!(m.do_mut_pass(&mut Verifier{}), Err(VerifyError::SharedMemoryWithoutMaximum)));
+    }
+
+    #[test]
+    pub fn tail_call_with_stack_alloc_test() {
+        let mut m = Module::new(WasmModuleConf { enable_tail_calls: true, ..WasmModuleConf::default() });
+
+        // the pointer passed to the tail call would point into the popped frame
+        let mut builder = FunctionBuilder::new("func".to_string(), [m.ptr_t()], []);
+        builder.i_stack_alloc(m.int32t());
+        builder.i_tail_call("func".to_string());
+        builder.finish(&mut m);
+
+        m.do_mut_pass(&mut ControlFlowVerifier{}).unwrap();
+        assert!(matches!(m.do_mut_pass(&mut Verifier{}), Err(VerifyError::TailCallWithStackAlloc)));
+    }
+}
@@ -0,0 +1,10 @@
+(module
+  (type (;0;) (func (param i32 i32) (result i32)))
+  (func (;0;) (type 0) (param i32 i32) (result i32)
+    local.get 0
+    local.get 1
+    i32.const 3
+    i32.shl
+    i32.add
+    i32.load)
+)
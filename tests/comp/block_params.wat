@@ -0,0 +1,29 @@
+(module
+  (type (;0;) (func (param i32 i32) (result i32)))
+  (type (;1;) (func (result i32)))
+  (type (;2;) (func (param i32) (result i32)))
+  (func (;0;) (type 0) (param i32 i32) (result i32)
+    (local i32)
+    local.get 1
+    local.get 0
+    if (type 2)
+      i32.const 1
+      i32.add
+    else
+      i32.const 2
+      i32.mul
+    end
+    local.get 1
+    local.set 2
+    block (type 2)
+      block (type 2)
+        block (type 2)
+          local.get 2
+          br_table 0 1
+        end
+        local.get 0
+        i32.sub
+        br 1
+      end
+    end)
+)
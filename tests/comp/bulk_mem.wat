@@ -0,0 +1,13 @@
+(module
+  (type (;0;) (func (param i32 i32 i32)))
+  (func (;0;) (type 0) (param i32 i32 i32)
+    local.get 0
+    local.get 1
+    local.get 2
+    memory.copy
+    local.get 1
+    i32.const 0
+    local.get 2
+    memory.fill)
+  (memory 1)
+)
@@ -0,0 +1,26 @@
+(module
+  (type (;0;) (func (param i32) (result i32)))
+  (global (;0;) (mut i32) (i32.const 16400))
+  (func (;0;) (type 0) (param i32) (result i32)
+    (local i32)
+    global.get 0
+    i32.const 16
+    i32.sub
+    local.tee 1
+    global.set 0
+    try (result i32)
+      local.get 1
+      drop
+      local.get 0
+    catch_all
+      local.get 1
+      i32.const 16
+      i32.add
+      global.set 0
+      rethrow 0
+    end
+    local.get 1
+    i32.const 16
+    i32.add
+    global.set 0)
+)
@@ -0,0 +1,24 @@
+(module
+  (type (;0;) (func (param i32)))
+  (type (;1;) (func))
+  (type (;2;) (func (param i32) (result i32)))
+  (tag (;0;) (type 0) (param i32))
+  (tag (;1;) (type 1))
+  (func (;0;) (type 2) (param i32) (result i32)
+    try (result i32)  ;; label = @1
+      local.get 0
+      if  ;; label = @2
+        local.get 0
+        throw 0
+      else
+      end
+      i32.const 1
+    catch 0
+      i32.const 2
+      i32.add
+    catch 1
+      i32.const 3
+    catch_all
+      i32.const 0
+    end)
+)
@@ -0,0 +1,28 @@
+(module
+  (type (;0;) (func))
+  (type (;1;) (func (param i32) (result i32)))
+  (tag (;0;) (type 0))
+  (func (;0;) (type 1) (param i32) (result i32)
+    (local i32)
+    block  ;; label = @1
+      loop  ;; label = @2
+        try  ;; label = @3
+          local.get 1
+          i32.const 1
+          i32.add
+          local.set 1
+          local.get 1
+          local.get 0
+          i32.ge_s
+          if  ;; label = @4
+            throw 0
+          else
+          end
+        catch 0
+          br 2 (;@1;)
+        end
+        br 0 (;@2;)
+      end
+    end
+    local.get 1)
+)
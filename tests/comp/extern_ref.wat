@@ -0,0 +1,9 @@
+(module
+  (type (;0;) (func (param externref) (result i32)))
+  (func (;0;) (type 0) (param externref) (result i32)
+    (local externref)
+    ref.null extern
+    local.set 1
+    local.get 0
+    ref.is_null)
+)
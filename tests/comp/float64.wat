@@ -0,0 +1,11 @@
+(module
+  (type (;0;) (func (param f32 i32) (result f64)))
+  (func (;0;) (type 0) (param f32 i32) (result f64)
+    local.get 0
+    f64.promote_f32
+    local.get 1
+    f64.convert_i32_s
+    f64.mul
+    f64.const 0.5
+    f64.add)
+)
@@ -0,0 +1,20 @@
+(module
+  (type (;0;) (func (param f64 f32) (result f64)))
+  (func (;0;) (type 0) (param f64 f32) (result f64)
+    local.get 1
+    f32.sqrt
+    local.get 1
+    f32.abs
+    f32.min
+    f32.neg
+    f32.nearest
+    drop
+    local.get 0
+    f64.floor
+    local.get 0
+    f64.ceil
+    f64.max
+    local.get 0
+    f64.trunc
+    f64.copysign)
+)
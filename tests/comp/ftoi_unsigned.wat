@@ -0,0 +1,29 @@
+(module
+  (type (;0;) (func (param f32 f64 i32 i64) (result i32)))
+  (func (;0;) (type 0) (param f32 f64 i32 i64) (result i32)
+    local.get 2
+    f32.convert_i32_u
+    drop
+    local.get 3
+    f64.convert_i64_u
+    drop
+    local.get 0
+    f32.const 255
+    f32.min
+    f32.const 0
+    f32.max
+    i32.trunc_sat_f32_u
+    drop
+    local.get 1
+    f64.const 32767
+    f64.min
+    f64.const -32768
+    f64.max
+    i32.trunc_sat_f64_s
+    drop
+    local.get 1
+    i64.trunc_sat_f64_u
+    drop
+    local.get 0
+    i32.trunc_sat_f32_u)
+)
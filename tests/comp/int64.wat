@@ -0,0 +1,10 @@
+(module
+  (type (;0;) (func (param i64 i32) (result i64)))
+  (func (;0;) (type 0) (param i64 i32) (result i64)
+    local.get 0
+    local.get 1
+    i64.extend_i32_s
+    i64.mul
+    i64.const 3
+    i64.div_s)
+)
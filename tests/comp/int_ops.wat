@@ -0,0 +1,29 @@
+(module
+  (type (;0;) (func (param i32 i32) (result i32)))
+  (func (;0;) (type 0) (param i32 i32) (result i32)
+    local.get 0
+    local.get 0
+    i32.shl
+    i32.const 65535
+    i32.and
+    i32.const -1
+    i32.xor
+    i32.const 65535
+    i32.and
+    local.get 0
+    i32.rem_u
+    local.get 0
+    i32.shr_u
+    drop
+    local.get 1
+    i32.const -1
+    i32.mul
+    i32.const 16
+    i32.shl
+    i32.const 16
+    i32.shr_s
+    local.get 1
+    i32.xor
+    local.get 1
+    i32.shr_s)
+)
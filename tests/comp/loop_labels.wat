@@ -0,0 +1,37 @@
+(module
+  (type (;0;) (func (param i32) (result i32)))
+  (func (;0;) (type 0) (param i32) (result i32)
+    (local i32)
+    block  ;; label = @1
+      loop  ;; label = @2
+        local.get 0
+        if  ;; label = @3
+          br 2 (;@1;)
+        else
+        end
+        block  ;; label = @3
+          loop  ;; label = @4
+            local.get 1
+            i32.const 1
+            i32.add
+            local.set 1
+            local.get 1
+            i32.const 100
+            i32.ge_s
+            if  ;; label = @5
+              br 4 (;@1;)
+            else
+            end
+            local.get 1
+            i32.const 2
+            i32.mul
+            local.set 1
+            br 2 (;@2;)
+            br 0 (;@4;)
+          end
+        end
+        br 0 (;@2;)
+      end
+    end
+    local.get 1)
+)
@@ -0,0 +1,25 @@
+(module
+  (type (;0;) (func (param i32) (result i32)))
+  (func (;0;) (type 0) (param i32) (result i32)
+    (local i32)
+    block (result i32)  ;; label = @1
+      loop  ;; label = @2
+        local.get 1
+        local.get 1
+        i32.mul
+        local.get 0
+        i32.ge_s
+        if  ;; label = @3
+          local.get 1
+          br 2 (;@1;)
+        else
+        end
+        local.get 1
+        i32.const 1
+        i32.add
+        local.set 1
+        br 0 (;@2;)
+      end
+      unreachable
+    end)
+)
@@ -0,0 +1,86 @@
+(module
+  (type (;0;) (func (param i32 i32 i32)))
+  (func (;0;) (type 0) (param i32 i32 i32)
+    (local i32 i32 i32)
+    local.get 0
+    local.get 1
+    local.get 2
+    local.set 5
+    local.set 4
+    local.set 3
+    local.get 3
+    local.get 4
+    i32.gt_u
+    if
+      block
+        loop
+          local.get 5
+          i32.eqz
+          br_if 1
+          local.get 5
+          i32.const -1
+          i32.add
+          local.set 5
+          local.get 3
+          local.get 5
+          i32.add
+          local.get 4
+          local.get 5
+          i32.add
+          i32.load8_u
+          i32.store8
+          br 0
+        end
+      end
+    else
+      block
+        loop
+          local.get 5
+          i32.eqz
+          br_if 1
+          local.get 3
+          local.get 4
+          i32.load8_u
+          i32.store8
+          local.get 3
+          i32.const 1
+          i32.add
+          local.set 3
+          local.get 4
+          i32.const 1
+          i32.add
+          local.set 4
+          local.get 5
+          i32.const -1
+          i32.add
+          local.set 5
+          br 0
+        end
+      end
+    end
+    local.get 1
+    i32.const 0
+    local.get 2
+    local.set 5
+    local.set 4
+    local.set 3
+    block
+      loop
+        local.get 5
+        i32.eqz
+        br_if 1
+        local.get 3
+        local.get 4
+        i32.store8
+        local.get 3
+        i32.const 1
+        i32.add
+        local.set 3
+        local.get 5
+        i32.const -1
+        i32.add
+        local.set 5
+        br 0
+      end
+    end)
+)
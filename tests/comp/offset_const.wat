@@ -0,0 +1,12 @@
+(module
+  (type (;0;) (func (param i32) (result f64)))
+  (func (;0;) (type 0) (param i32) (result f64)
+    local.get 0
+    i32.const 24
+    i32.add
+    f64.load align=8
+    local.get 0
+    i32.load align=4
+    f64.convert_i32_s
+    f64.add)
+)
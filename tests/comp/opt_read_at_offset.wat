@@ -0,0 +1,8 @@
+(module
+  (type (;0;) (func (param i32) (result i32 f64)))
+  (func (;0;) (type 0) (param i32) (result i32 f64)
+    local.get 0
+    i32.load offset=4
+    local.get 0
+    f64.load offset=24)
+)
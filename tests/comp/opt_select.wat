@@ -0,0 +1,15 @@
+(module
+  (type (;0;) (func (param i32 i32) (result i32)))
+  (func (;0;) (type 0) (param i32 i32) (result i32)
+    local.get 0
+    local.get 1
+    local.get 0
+    local.get 1
+    i32.gt_s
+    select
+    i32.const 1
+    i32.const 2
+    local.get 0
+    select
+    i32.add)
+)
@@ -0,0 +1,20 @@
+(module
+  (type (;0;) (func (param i32) (result i32)))
+  (type (;1;) (func (param i32)))
+  (tag (;0;) (type 1))
+  (func (;0;) (type 0) (param i32) (result i32)
+    try (result i32)
+      local.get 0
+      if
+        i32.const 0
+        call 0
+        return
+      else
+      end
+      local.get 0
+    catch 0
+      i32.const 1
+      i32.add
+    end
+    return_call 0)
+)
@@ -0,0 +1,13 @@
+(module
+  (type (;0;) (func (param i32 i32 f32) (result i32)))
+  (func (;0;) (type 0) (param i32 i32 f32) (result i32)
+    local.get 0
+    local.get 1
+    i32.store16 offset=2
+    local.get 0
+    local.get 2
+    f32.store offset=16
+    local.get 0
+    i32.const 4
+    i32.add)
+)
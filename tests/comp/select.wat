@@ -0,0 +1,8 @@
+(module
+  (type (;0;) (func (param i32 f64 f64) (result f64)))
+  (func (;0;) (type 0) (param i32 f64 f64) (result f64)
+    local.get 1
+    local.get 2
+    local.get 0
+    select)
+)
@@ -0,0 +1,39 @@
+(module
+  (type (;0;) (func (param i32 i32) (result i32)))
+  (func (;0;) (type 0) (param i32 i32) (result i32)
+    (local i32)
+    local.get 0
+    i32.atomic.load
+    local.set 2
+    local.get 0
+    i32.const 1
+    i32.atomic.store8
+    local.get 0
+    i32.const 5
+    i32.atomic.rmw16.add_u
+    i32.const 16
+    i32.shl
+    i32.const 16
+    i32.shr_s
+    drop
+    local.get 0
+    i32.const 0
+    i32.const 1
+    i32.atomic.rmw8.cmpxchg_u
+    drop
+    local.get 0
+    i64.const 7
+    i64.atomic.rmw.xchg
+    drop
+    local.get 0
+    local.get 1
+    i64.const 1000
+    memory.atomic.wait32
+    drop
+    local.get 0
+    i32.const 1
+    memory.atomic.notify
+    local.get 2
+    i32.add)
+  (memory 1 16 shared)
+)
@@ -0,0 +1,19 @@
+(module
+  (type (;0;) (func (param i32) (result i32)))
+  (import "env" "__stack_pointer" (global (;0;) (mut i32)))
+  (func (;0;) (type 0) (param i32) (result i32)
+    (local i32)
+    global.get 0
+    i32.const 16
+    i32.sub
+    local.tee 1
+    global.set 0
+    local.get 1
+    local.get 0
+    i32.atomic.store
+    local.get 0
+    local.get 1
+    i32.const 16
+    i32.add
+    global.set 0)
+)
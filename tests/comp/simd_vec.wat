@@ -0,0 +1,10 @@
+(module
+  (type (;0;) (func (param i32 i32) (result i32)))
+  (func (;0;) (type 0) (param i32 i32) (result i32)
+    local.get 0
+    v128.load
+    local.get 1
+    i32x4.splat
+    i32x4.add
+    i32x4.extract_lane 2)
+)
@@ -0,0 +1,41 @@
+(module
+  (type (;0;) (func (param i32) (result i32)))
+  (global (;0;) (mut i32) (i32.const 16400))
+  (func (;0;) (type 0) (param i32) (result i32)
+    (local i32 i32)
+    global.get 0
+    i32.const 16
+    i32.sub
+    local.tee 2
+    global.set 0
+    local.get 2
+    local.set 1
+    local.get 1
+    local.get 0
+    i32.store
+    local.get 0
+    if
+      i32.const 0
+      local.get 2
+      i32.const 16
+      i32.add
+      global.set 0
+      return
+    else
+      local.get 2
+      i32.const 16
+      i32.add
+      global.set 0
+      unreachable
+    end
+    local.get 2
+    i32.const 8
+    i32.add
+    drop
+    local.get 1
+    i32.load
+    local.get 2
+    i32.const 16
+    i32.add
+    global.set 0)
+)
@@ -0,0 +1,28 @@
+(module
+  (type (;0;) (func (param i32 f64) (result f64)))
+  (func (;0;) (type 0) (param i32 f64) (result f64)
+    (local f64 f64 i32 i32)
+    local.get 1
+    local.tee 2
+    local.get 2
+    f64.mul
+    local.get 0
+    local.set 4
+    local.tee 2
+    local.get 4
+    local.get 2
+    local.set 2
+    local.set 4
+    local.get 2
+    local.get 4
+    drop
+    f64.add
+    local.get 0
+    i32.const 1
+    local.set 5
+    local.set 4
+    local.get 5
+    local.get 4
+    i32.sub
+    drop)
+)
@@ -0,0 +1,61 @@
+(module
+  (type (;0;) (func (param i32 i32)))
+  (type (;1;) (func (param i32)))
+  (type (;2;) (func (result i32 i32 i32 i32 i32)))
+  (global (;0;) (mut i32) (i32.const 16400))
+  (func (;0;) (type 0) (param i32 i32)
+    (local i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32)
+    global.get 0
+    i32.const 32
+    i32.sub
+    local.tee 12
+    global.set 0
+    local.get 0
+    if (type 2)
+      local.get 0
+      i32.const 1
+      i32.sub
+      local.get 12
+      call 0
+      local.get 12
+      i32.load
+      local.get 12
+      i32.load offset=4
+      local.get 12
+      i32.load offset=8
+      local.get 12
+      i32.load offset=12
+      local.get 12
+      i32.load offset=16
+    else
+      local.get 2
+      local.get 3
+      local.get 4
+      local.get 5
+      local.get 6
+    end
+    local.set 11
+    local.set 10
+    local.set 9
+    local.set 8
+    local.set 7
+    local.get 1
+    local.get 7
+    i32.store
+    local.get 1
+    local.get 8
+    i32.store offset=4
+    local.get 1
+    local.get 9
+    i32.store offset=8
+    local.get 1
+    local.get 10
+    i32.store offset=12
+    local.get 1
+    local.get 11
+    i32.store offset=16
+    local.get 12
+    i32.const 32
+    i32.add
+    global.set 0)
+)
@@ -0,0 +1,27 @@
+(module
+  (type (;0;) (func (param i32 f64 i32) (result i32 f64)))
+  (func (;0;) (type 0) (param i32 f64 i32) (result i32 f64)
+    (local i32 f64 i32 f64 i32)
+    local.get 2
+    local.set 5
+    local.get 5
+    i32.load
+    local.get 5
+    f64.load offset=8
+    local.set 4
+    local.set 3
+    local.get 2
+    local.get 0
+    local.get 1
+    local.set 6
+    local.set 7
+    local.set 5
+    local.get 5
+    local.get 7
+    i32.store
+    local.get 5
+    local.get 6
+    f64.store offset=8
+    local.get 3
+    local.get 4)
+)
@@ -0,0 +1,47 @@
+(module
+  (type (;0;) (func (param i32) (result i32)))
+  (func (;0;) (type 0) (param i32) (result i32)
+    (local i32 i32)
+    block
+      loop
+        local.get 0
+        local.set 2
+        block
+          block
+            block
+              block
+                local.get 2
+                br_table 0 1 2
+              end
+              br 4
+              br 2
+            end
+            local.get 1
+            i32.const 1
+            i32.add
+            local.set 1
+            br 1
+          end
+          br 2
+        end
+        br 0
+      end
+    end
+    local.get 0
+    local.set 2
+    block (result i32)
+      block
+        block
+          block
+            local.get 2
+            br_table 0 1 2
+          end
+          i32.const 10
+          br 2
+        end
+        i32.const 20
+        br 1
+      end
+      i32.const 30
+    end)
+)
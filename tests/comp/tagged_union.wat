@@ -0,0 +1,17 @@
+(module
+  (type (;0;) (func (param i32) (result f64)))
+  (func (;0;) (type 0) (param i32) (result f64)
+    local.get 0
+    i32.load
+    if (result f64)
+      local.get 0
+      i32.const 8
+      i32.add
+      f64.load
+    else
+      local.get 0
+      i32.const 1
+      i32.store
+      f64.const 0.0
+    end)
+)
@@ -0,0 +1,16 @@
+(module
+  (type (;0;) (func (param i32 i32) (result i32)))
+  (func (;0;) (type 0) (param i32 i32) (result i32)
+    local.get 0
+    if
+      local.get 0
+      i32.const 1
+      i32.sub
+      local.get 1
+      local.get 0
+      i32.add
+      return_call 0
+    else
+    end
+    local.get 1)
+)
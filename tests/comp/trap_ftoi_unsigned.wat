@@ -0,0 +1,19 @@
+(module
+  (type (;0;) (func (param f32 f64) (result i64)))
+  (func (;0;) (type 0) (param f32 f64) (result i64)
+    local.get 1
+    i32.trunc_f64_u
+    drop
+    local.get 1
+    i32.trunc_f64_u
+    i32.const 65535
+    i32.and
+    drop
+    local.get 0
+    i32.trunc_f32_u
+    i32.const 255
+    i32.and
+    drop
+    local.get 0
+    i64.trunc_f32_u)
+)